@@ -0,0 +1,96 @@
+// Benchmark: zero-copy tokens vs. the old allocation-per-token path.
+// Run with `cargo bench --bench tokenize`.
+// [Note] No benchmarking framework here, std::time::Instant is enough to compare two paths.
+use rlox::modules::tokenize::Tokenizer;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+// [Note] The old path is the tokenizer as it was before lexemes were borrowed, taken as is with
+// `git show 8f2c74c:projects/rlox/src/modules/tokenize.rs`. The only edit is dropping the loop at
+// the end of `scan_tokens` that printed every token to stderr, which would swamp the timing.
+#[path = "baseline/tokenize.rs"]
+#[allow(dead_code, unused_imports, clippy::all)]
+mod baseline;
+
+/// A chunk of Lox touching every kind of token, repeated to build the input file.
+const SNIPPET: &str = r#"// Compute some values
+var greeting = "Hello, World";
+var escaped = "tab\there\n";
+fun fib(n) {
+    if (n <= 1) return n; /* base case */
+    return fib(n - 2) + fib(n - 1);
+}
+class Point {
+    init(x, y) { this.x = x; this.y = y; }
+    length() { return (this.x * this.x + this.y * this.y) / 2.5e0; }
+}
+while (count != 0x10 and flag == true or !done) { count = count - 0b1; }
+"#;
+
+const TARGET_SIZE: usize = 4 * 1024 * 1024;
+const ITERATIONS: u32 = 10;
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Generate a multi-megabyte .lox file and read it back in
+    let path = std::env::temp_dir().join("rlox_tokenize_bench.lox");
+    fs::write(&path, SNIPPET.repeat(TARGET_SIZE / SNIPPET.len() + 1))?;
+    let source = fs::read_to_string(&path)?;
+    fs::remove_file(&path)?;
+
+    let megabytes = source.len() as f64 / (1024.0 * 1024.0);
+    println!("input: {:.1} MB", megabytes);
+
+    // Zero-copy: lexemes and unescaped string values are slices of the source
+    let borrowed = time(|| {
+        let (tokens, _) = Tokenizer::new(&source).scan_tokens();
+        black_box(tokens.len())
+    });
+    // Allocation-heavy: every lexeme built up in its own String, as the tokenizer used to do
+    let owned = time(|| {
+        let (tokens, _) = baseline::Tokenizer::new(&source).scan_tokens();
+        black_box(tokens.len())
+    });
+
+    // Both paths must see the same tokens for the comparison to mean anything
+    let (tokens, _) = Tokenizer::new(&source).scan_tokens();
+    let (old_tokens, _) = baseline::Tokenizer::new(&source).scan_tokens();
+    assert_eq!(tokens.len(), old_tokens.len());
+    assert!(tokens.iter().zip(&old_tokens).all(|(token, old)| {
+        let span = (old.span.start, old.span.end, old.span.line, old.span.column);
+        token.lexeme == old.lexeme
+            && (
+                token.span.start,
+                token.span.end,
+                token.span.line,
+                token.span.column,
+            ) == span
+    }));
+
+    report("borrowed", borrowed, megabytes);
+    report("owned", owned, megabytes);
+    println!(
+        "owned / borrowed: {:.2}x",
+        owned.as_secs_f64() / borrowed.as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Average wall time of `ITERATIONS` runs, after one warm-up run.
+fn time(mut run: impl FnMut() -> usize) -> Duration {
+    run();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        run();
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn report(name: &str, elapsed: Duration, megabytes: f64) {
+    println!(
+        "{:>8}: {:>8.2} ms/iter {:>8.1} MB/s",
+        name,
+        elapsed.as_secs_f64() * 1000.0,
+        megabytes / elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,349 @@
+// Command-line interface: turns the arguments rlox was started with into Options.
+// [Note] Hand-rolled rather than pulled in from a crate, the grammar is small:
+//   rlox [options] [command] [<file> | - | -e <code>]
+
+/// Help text, printed for --help and after a usage error.
+pub const USAGE: &str = "\
+Usage: rlox [options] [command] [<file> | - | -e <code>]
+
+Commands:
+  run      Run a script (the default when a script is given)
+  repl     Start the interactive prompt (the default without a script)
+  tokens   Print the tokens of a script
+  ast      Print the syntax tree of a script
+  check    Report errors and warnings in a script without running it
+  disasm   Print the bytecode the VM would run for a script
+
+Scripts:
+  <file>     Read the script from a file
+  -          Read the script from standard input
+  -e <code>  Use <code> as the script
+
+Options:
+  -q, --quiet                  Do not print warnings
+  --backend=<backend>          How to run scripts: tree (the default, walking the syntax tree) or vm (compiling to bytecode)
+  --trace                      Print the VM's stack and each instruction to stderr as it runs (with --backend=vm)
+  --error-format=<format>      How to print errors and warnings: human (the default), short or json
+  --color=<when>               Color human errors: auto (the default, when stderr is a terminal), always or never
+  --debug                      Print the arguments and the script source to stderr
+  -h, --help                   Print this help";
+
+/// Enum representing what rlox has been asked to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    Repl,
+    Tokens,
+    Ast,
+    Check,
+    Disasm,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "run" => Some(Command::Run),
+            "repl" => Some(Command::Repl),
+            "tokens" => Some(Command::Tokens),
+            "ast" => Some(Command::Ast),
+            "check" => Some(Command::Check),
+            "disasm" => Some(Command::Disasm),
+            _ => None,
+        }
+    }
+}
+
+/// Enum representing where the script comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    File(String),
+    Stdin,
+    Inline(String),
+}
+
+/// Enum representing how errors and warnings are printed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The source line with the problem underlined
+    Human,
+    /// One line each, `[line L, column C] Error: ...`
+    Short,
+    /// One JSON object per line, for editors
+    Json,
+}
+
+/// Enum representing which interpreter runs the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// Walk the syntax tree
+    Tree,
+    /// Compile to bytecode for the virtual machine
+    Vm,
+}
+
+/// Enum representing when human errors are colored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+/// Struct representing the parsed command line.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Options {
+    pub command: Command,
+    pub source: Option<Source>,
+    pub quiet: bool,
+    pub backend: Backend,
+    pub trace: bool,
+    pub error_format: ErrorFormat,
+    pub color: Color,
+    pub debug: bool,
+    pub help: bool,
+}
+
+impl Options {
+    /// Parse the command-line arguments, without the program name. Errors are usage errors.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut command = None;
+        let mut source = None;
+        let mut quiet = false;
+        let mut backend = Backend::Tree;
+        let mut trace = false;
+        let mut error_format = ErrorFormat::Human;
+        let mut color = Color::Auto;
+        let mut debug = false;
+        let mut help = false;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let next_source = match arg.as_str() {
+                "-h" | "--help" => {
+                    help = true;
+                    continue;
+                }
+                "-q" | "--quiet" => {
+                    quiet = true;
+                    continue;
+                }
+                "--debug" => {
+                    debug = true;
+                    continue;
+                }
+                "--trace" => {
+                    trace = true;
+                    continue;
+                }
+                "-e" => match args.next() {
+                    Some(code) => Source::Inline(code),
+                    None => return Err(String::from("-e needs the code to run after it")),
+                },
+                "-" => Source::Stdin,
+                option if option.starts_with("--") && option.contains('=') => {
+                    let (name, value) = option.split_once('=').unwrap();
+                    match (name, value) {
+                        ("--backend", "tree") => backend = Backend::Tree,
+                        ("--backend", "vm") => backend = Backend::Vm,
+                        ("--error-format", "human") => error_format = ErrorFormat::Human,
+                        ("--error-format", "short") => error_format = ErrorFormat::Short,
+                        ("--error-format", "json") => error_format = ErrorFormat::Json,
+                        ("--color", "auto") => color = Color::Auto,
+                        ("--color", "always") => color = Color::Always,
+                        ("--color", "never") => color = Color::Never,
+                        ("--backend" | "--error-format" | "--color", _) => {
+                            return Err(format!("Unknown value '{}' for {}", value, name));
+                        }
+                        _ => return Err(format!("Unknown option '{}'", name)),
+                    }
+                    continue;
+                }
+                option if option.starts_with('-') => {
+                    return Err(format!("Unknown option '{}'", option));
+                }
+                word => {
+                    // [Note] The first word names the command, unless a script has already been given
+                    match Command::from_name(word) {
+                        Some(named) if command.is_none() && source.is_none() => {
+                            command = Some(named);
+                            continue;
+                        }
+                        _ => Source::File(String::from(word)),
+                    }
+                }
+            };
+
+            if source.is_some() {
+                return Err(String::from("Only one script can be given"));
+            }
+            source = Some(next_source);
+        }
+
+        if trace && backend != Backend::Vm {
+            return Err(String::from("--trace needs --backend=vm"));
+        }
+
+        let command = match (command, &source) {
+            (Some(Command::Repl), Some(_)) => {
+                return Err(String::from("'repl' does not take a script"));
+            }
+            (Some(command), None) if command != Command::Repl && !help => {
+                return Err(format!(
+                    "'{}' needs a script: a file, '-' or -e <code>",
+                    format!("{:?}", command).to_lowercase()
+                ));
+            }
+            (Some(command), _) => command,
+            (None, Some(_)) => Command::Run,
+            (None, None) => Command::Repl,
+        };
+
+        Ok(Options {
+            command,
+            source,
+            quiet,
+            backend,
+            trace,
+            error_format,
+            color,
+            debug,
+            help,
+        })
+    }
+}
+
+/// Tests for the command line
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| String::from(*arg)))
+    }
+
+    fn options(command: Command, source: Option<Source>) -> Options {
+        Options {
+            command,
+            source,
+            quiet: false,
+            backend: Backend::Tree,
+            trace: false,
+            error_format: ErrorFormat::Human,
+            color: Color::Auto,
+            debug: false,
+            help: false,
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(parse(&[]), Ok(options(Command::Repl, None)));
+        assert_eq!(
+            parse(&["script.lox"]),
+            Ok(options(
+                Command::Run,
+                Some(Source::File(String::from("script.lox")))
+            ))
+        );
+    }
+
+    #[test]
+    fn commands_and_sources() {
+        assert_eq!(
+            parse(&["tokens", "-"]),
+            Ok(options(Command::Tokens, Some(Source::Stdin)))
+        );
+        assert_eq!(
+            parse(&["ast", "-e", "print 1;"]),
+            Ok(options(
+                Command::Ast,
+                Some(Source::Inline(String::from("print 1;")))
+            ))
+        );
+        assert_eq!(
+            parse(&["-e", "print 1;"]),
+            Ok(options(
+                Command::Run,
+                Some(Source::Inline(String::from("print 1;")))
+            ))
+        );
+        assert_eq!(
+            parse(&["disasm", "a.lox"]),
+            Ok(options(
+                Command::Disasm,
+                Some(Source::File(String::from("a.lox")))
+            ))
+        );
+        // A file can share a name with a command
+        assert_eq!(
+            parse(&["check", "run"]),
+            Ok(options(
+                Command::Check,
+                Some(Source::File(String::from("run")))
+            ))
+        );
+    }
+
+    #[test]
+    fn flags() {
+        let parsed = parse(&["--quiet", "run", "--debug", "a.lox"]).unwrap();
+        assert!(parsed.quiet && parsed.debug && !parsed.help);
+        assert!(parse(&["-q"]).unwrap().quiet);
+        assert!(parse(&["check", "--help"]).unwrap().help);
+
+        let parsed = parse(&["--error-format=json", "--color=never", "-"]).unwrap();
+        assert_eq!(parsed.error_format, ErrorFormat::Json);
+        assert_eq!(parsed.color, Color::Never);
+        assert_eq!(
+            parse(&["--error-format=short"]).unwrap().error_format,
+            ErrorFormat::Short
+        );
+        assert_eq!(
+            parse(&["run", "--backend=vm", "a.lox"]).unwrap().backend,
+            Backend::Vm
+        );
+        assert!(parse(&["--trace", "--backend=vm", "a.lox"]).unwrap().trace);
+    }
+
+    #[test]
+    fn usage_errors() {
+        assert_eq!(
+            parse(&["--verbose"]),
+            Err(String::from("Unknown option '--verbose'"))
+        );
+        assert_eq!(
+            parse(&["--error-format=xml"]),
+            Err(String::from("Unknown value 'xml' for --error-format"))
+        );
+        assert_eq!(
+            parse(&["--backend=jit"]),
+            Err(String::from("Unknown value 'jit' for --backend"))
+        );
+        assert_eq!(
+            parse(&["--trace", "a.lox"]),
+            Err(String::from("--trace needs --backend=vm"))
+        );
+        assert_eq!(
+            parse(&["--colour=always"]),
+            Err(String::from("Unknown option '--colour'"))
+        );
+        assert_eq!(
+            parse(&["run", "-e"]),
+            Err(String::from("-e needs the code to run after it"))
+        );
+        assert_eq!(
+            parse(&["a.lox", "b.lox"]),
+            Err(String::from("Only one script can be given"))
+        );
+        assert_eq!(
+            parse(&["repl", "a.lox"]),
+            Err(String::from("'repl' does not take a script"))
+        );
+        assert_eq!(
+            parse(&["tokens"]),
+            Err(String::from(
+                "'tokens' needs a script: a file, '-' or -e <code>"
+            ))
+        );
+    }
+}
@@ -0,0 +1,2 @@
+// The interpreter is also built as a library so benchmarks and integration tests can reach the modules.
+pub mod modules;
@@ -1,42 +1,305 @@
 // External modules
-pub mod modules;
-use crate::modules::reader::*;
-use crate::modules::tokenize::*;
+use rlox::modules::ast::Stmt;
+use rlox::modules::compiler::*;
+use rlox::modules::diagnostic::*;
+use rlox::modules::disassembler::*;
+use rlox::modules::interpreter::*;
+use rlox::modules::object::ObjFunction;
+use rlox::modules::parser::*;
+use rlox::modules::reader::*;
+use rlox::modules::repl::*;
+use rlox::modules::resolver::*;
+use rlox::modules::tokenize::*;
+use rlox::modules::vm::*;
+
+// Internal modules
+mod cli;
+use cli::{Backend, Color, Command, ErrorFormat, Options, Source, USAGE};
 
 // Standard libarary
 use ::std::env;
+use ::std::io::{self, IsTerminal};
+use ::std::process;
+use ::std::rc::Rc;
+use ::std::thread;
+
+// [Note] Exit statuses follow sysexits.h, as Crafting Interpreters does
+
+/// Exit status for a command line rlox does not understand (EX_USAGE)
+const EXIT_USAGE: i32 = 64;
+
+/// Exit status for errors in the source being interpreted (EX_DATAERR)
+const EXIT_COMPILE_ERROR: i32 = 65;
+
+/// Exit status for errors raised while the program runs (EX_SOFTWARE)
+const EXIT_RUNTIME_ERROR: i32 = 70;
+
+/// Exit status for a script that cannot be read, or a REPL that cannot read its input (EX_IOERR)
+const EXIT_IO_ERROR: i32 = 74;
 
-/// Interpreter main entry point. Executes the source file reader, tokenizer, parser, and evaluator
-fn run(filepath: &String) -> Result<(), Box<dyn std::error::Error>> {
-    // Read the source file contents
-    let contents = read_source(filepath)?;
+/// Stack size for the thread the interpreter runs on
+// [Note] The tree-walking interpreter recurses on the Rust stack for every Lox call. The main thread's
+// default stack is too small for MAX_CALL_DEPTH calls in a debug build, so it gets a thread of its own.
+const INTERPRETER_STACK_SIZE: usize = 64 * 1024 * 1024;
 
-    // Tokenize the source code
-    let mut tokenizer = Tokenizer::new(&contents);
-    let _tokens = tokenizer.scan_tokens();
+/// Read the script from wherever the command line said it is.
+fn read(source: &Source) -> Result<String, Box<Diagnostic>> {
+    match source {
+        Source::File(filepath) => read_source(filepath),
+        Source::Stdin => read_stdin(),
+        Source::Inline(code) => Ok(code.clone()),
+    }
+}
 
-    // Parse the tokens into an abstract syntax tree (AST)
-    // let ast = parse(&tokens)?;
+/// Whether human errors should be colored.
+fn use_color(color: Color) -> bool {
+    match color {
+        Color::Always => true,
+        Color::Never => false,
+        // [Note] NO_COLOR is the common convention for turning color off everywhere (no-color.org)
+        Color::Auto => io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none(),
+    }
+}
 
-    // Evaluate the AST
-    // evaluate(&ast)?;
+/// Struct printing diagnostics to stderr, in the format the command line asked for.
+struct Reporter<'a> {
+    options: &'a Options,
+    /// Name of the script in rendered locations
+    file: &'a str,
+    source: &'a str,
+}
 
-    Ok(())
+impl Reporter<'_> {
+    fn report(&self, diagnostic: impl Into<Diagnostic>) {
+        let diagnostic = diagnostic.into();
+        if self.options.quiet && !diagnostic.is_error() {
+            return;
+        }
+        match self.options.error_format {
+            ErrorFormat::Human => eprint!(
+                "{}",
+                diagnostic.render(self.source, self.file, use_color(self.options.color))
+            ),
+            ErrorFormat::Short => eprintln!("{}", diagnostic),
+            ErrorFormat::Json => eprintln!("{}", diagnostic.to_json(self.file)),
+        }
+    }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // [Note] Box<dyn std::error::Error> is read as "any kind of error".
+/// Print every token in the source, one per line. Lexical errors go to stderr.
+fn tokens(contents: &str, reporter: &Reporter) -> i32 {
+    let mut status = 0;
+    for result in Tokenizer::new(contents) {
+        match result {
+            Ok(token) => {
+                print!(
+                    "{}:{} {:?} {}",
+                    token.span.line, token.span.column, token.token_type, token.lexeme
+                );
+                match token.literal {
+                    Some(Literal::String(value)) => println!(" {:?}", value),
+                    Some(Literal::Number(value)) => println!(" {}", value),
+                    None => println!(),
+                }
+            }
+            Err(error) => {
+                reporter.report(&error);
+                status = EXIT_COMPILE_ERROR;
+            }
+        }
+    }
+    status
+}
 
-    // Check if filepath argument was provided. Gracefully exit if not.
-    // All other command-line arguments > 1 are ignored
-    let filepath = env::args().nth(1).ok_or("ERROR: No file path provided")?;
+/// Tokenize and parse the source code into an abstract syntax tree (AST), reporting any errors.
+fn parse(contents: &str, reporter: &Reporter) -> Result<Vec<Stmt>, i32> {
+    // [Note] The parser pulls tokens from the tokenizer as it needs them.
+    // Chain .trace(true) onto the tokenizer to dump every token to stderr while debugging
+    let mut parser = Parser::new(Tokenizer::new(contents));
+    let result = parser.parse();
 
-    // Debug filepath
-    dbg!(&filepath);
+    // Report every lexical error in the file, then every syntax error, and stop before evaluating
+    for error in parser.lex_errors() {
+        reporter.report(error);
+    }
+    match result {
+        Ok(_) if !parser.lex_errors().is_empty() => Err(EXIT_COMPILE_ERROR),
+        Ok(ast) => Ok(ast),
+        Err(errors) => {
+            for error in &errors {
+                reporter.report(error);
+            }
+            Err(EXIT_COMPILE_ERROR)
+        }
+    }
+}
 
-    // Enter intepreter main
-    run(&filepath)?;
+/// Parse the source and bind every variable to its declaration, reporting any errors and warnings.
+fn compile(contents: &str, reporter: &Reporter) -> Result<(Vec<Stmt>, Locals), i32> {
+    let ast = parse(contents, reporter)?;
 
-    // [Note] Ok(()) Equivalent to C's return 0.
-    Ok(())
+    // Check for mistakes that can be found without running
+    let mut resolver = Resolver::new();
+    let result = resolver.resolve(&ast);
+    for warning in resolver.warnings() {
+        reporter.report(warning);
+    }
+    match result {
+        Ok(locals) => Ok((ast, locals)),
+        Err(errors) => {
+            for error in &errors {
+                reporter.report(error);
+            }
+            Err(EXIT_COMPILE_ERROR)
+        }
+    }
+}
+
+/// Compile the source to bytecode for the VM, reporting any errors and warnings.
+fn compile_bytecode(contents: &str, reporter: &Reporter) -> Result<Rc<ObjFunction>, i32> {
+    // [Note] The VM does not need the resolver's depths, its compiler finds every local's slot itself
+    let (ast, _) = compile(contents, reporter)?;
+    Compiler::new().compile(&ast).map_err(|errors| {
+        for error in &errors {
+            reporter.report(error);
+        }
+        EXIT_COMPILE_ERROR
+    })
+}
+
+/// Evaluate the source with the backend the command line chose.
+fn run(contents: &str, options: &Options, reporter: &Reporter) -> i32 {
+    let result = match options.backend {
+        Backend::Tree => {
+            let (ast, locals) = match compile(contents, reporter) {
+                Ok(compiled) => compiled,
+                Err(status) => return status,
+            };
+            let mut interpreter = Interpreter::new();
+            interpreter.resolve(locals);
+            interpreter.interpret(&ast)
+        }
+        Backend::Vm => match compile_bytecode(contents, reporter) {
+            Ok(script) => Vm::new().trace(options.trace).interpret(script),
+            Err(status) => return status,
+        },
+    };
+    match result {
+        Ok(()) => 0,
+        // The program called exit(code)
+        Err(RuntimeError {
+            exit_code: Some(code),
+            ..
+        }) => code,
+        Err(error) => {
+            reporter.report(&error);
+            EXIT_RUNTIME_ERROR
+        }
+    }
+}
+
+/// Interpreter main entry point: do what the command line asked, and return the exit status.
+fn execute(options: &Options) -> i32 {
+    let source = match &options.source {
+        Some(source) => source,
+        None => {
+            let session = match options.backend {
+                Backend::Tree => Session::new(),
+                Backend::Vm => Session::with_vm(Vm::new().trace(options.trace)),
+            };
+            let report = |diagnostic: Diagnostic, input: &str| {
+                let reporter = Reporter {
+                    options,
+                    file: "<repl>",
+                    source: input,
+                };
+                reporter.report(diagnostic);
+            };
+            return match run_repl(session, report) {
+                Ok(status) => status,
+                Err(error) => {
+                    eprintln!("Error: {}", error);
+                    EXIT_IO_ERROR
+                }
+            };
+        }
+    };
+
+    // [Note] The name shown in error locations
+    let file = match source {
+        Source::File(filepath) => filepath.as_str(),
+        Source::Stdin => "<stdin>",
+        Source::Inline(_) => "<inline>",
+    };
+    let contents = match read(source) {
+        Ok(contents) => contents,
+        Err(error) => {
+            let reporter = Reporter {
+                options,
+                file,
+                source: "",
+            };
+            reporter.report(*error);
+            return EXIT_IO_ERROR;
+        }
+    };
+    if options.debug {
+        dbg!(&contents);
+    }
+
+    let reporter = Reporter {
+        options,
+        file,
+        source: &contents,
+    };
+    match options.command {
+        Command::Run => run(&contents, options, &reporter),
+        Command::Tokens => tokens(&contents, &reporter),
+        Command::Ast => match parse(&contents, &reporter) {
+            Ok(ast) => {
+                for statement in ast {
+                    println!("{}", statement);
+                }
+                0
+            }
+            Err(status) => status,
+        },
+        Command::Check => match compile(&contents, &reporter) {
+            Ok(_) => 0,
+            Err(status) => status,
+        },
+        Command::Disasm => match compile_bytecode(&contents, &reporter) {
+            Ok(script) => {
+                print!("{}", disassemble(&script));
+                0
+            }
+            Err(status) => status,
+        },
+        Command::Repl => unreachable!("the command line does not allow a script for the REPL"),
+    }
+}
+
+fn main() {
+    let options = match Options::parse(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("Error: {}\n\n{}", message, USAGE);
+            process::exit(EXIT_USAGE);
+        }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if options.debug {
+        dbg!(&options);
+    }
+
+    // Enter intepreter main
+    let interpreter = thread::Builder::new()
+        .stack_size(INTERPRETER_STACK_SIZE)
+        .spawn(move || execute(&options))
+        .expect("interpreter thread should start");
+    let status = interpreter.join().expect("interpreter thread panicked");
+    process::exit(status);
 }
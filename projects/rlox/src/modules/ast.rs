@@ -0,0 +1,553 @@
+use crate::modules::tokenize::Span;
+use std::fmt;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+// Representing Code: Chapter 5 of "Crafting Interpreters"
+// [Note] The abstract syntax tree (AST) is the structured form of the program the parser builds out of tokens.
+// [Note] Following the sketch in docs/Lox.md, every kind of node is its own struct (the "complex type"),
+// and the Expr and Stmt enums tie them together. Every node carries the span of the source it came from.
+
+/// Enum representing an expression, a piece of code that produces a value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Literal(Literal),
+    Grouping(Grouping),
+    Unary(Unary),
+    Binary(Binary),
+    Logical(Logical),
+    Variable(Variable),
+    Assign(Assign),
+    Call(Call),
+    Get(Get),
+    Set(Set),
+    This(This),
+    Super(Super),
+    List(List),
+    Map(Map),
+    Index(Index),
+    SetIndex(SetIndex),
+}
+
+/// Enum representing a statement, a piece of code that produces an effect.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Expression(Expression),
+    Print(Print),
+    Var(Var),
+    Block(Block),
+    If(If),
+    While(While),
+    ForIn(ForIn),
+    Break(Break),
+    Continue(Continue),
+    // [Note] Functions are shared with the runtime values that close over them, hence the Rc
+    Function(Rc<Function>),
+    Return(Return),
+    Class(Class),
+}
+
+/// A name as written in the source: a variable, parameter, function, class or property name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Identifier {
+    pub name: String,
+    pub span: Span,
+}
+
+/// A unique number for every expression that refers to a variable, so the resolver can tell the
+/// interpreter which scope each one refers to.
+// [Note] Spans are not enough: in the REPL, every line is parsed separately and starts at offset 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ExprId(usize);
+
+impl ExprId {
+    /// A fresh id, different from every id handed out before it.
+    pub fn next() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        ExprId(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+/// Enum representing the value of a literal expression.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LiteralValue {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(String),
+}
+
+/// e.g. `nil`, `true`, `3.14`, `"hello"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Literal {
+    pub value: LiteralValue,
+    pub span: Span,
+}
+
+/// e.g. `(a + b)`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grouping {
+    pub expression: Box<Expr>,
+    pub span: Span,
+}
+
+/// Enum representing the prefix operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate, // -
+    Not,    // !
+    BitNot, // ~
+}
+
+/// e.g. `-5` or `!end_of_file()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unary {
+    pub operator: UnaryOp,
+    pub operator_span: Span,
+    pub right: Box<Expr>,
+    pub span: Span,
+}
+
+/// Enum representing the infix operators that always evaluate both operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// e.g. `a + b` or `x <= 10`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binary {
+    pub left: Box<Expr>,
+    pub operator: BinaryOp,
+    pub operator_span: Span,
+    pub right: Box<Expr>,
+    pub span: Span,
+}
+
+/// Enum representing the short-circuiting operators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+/// e.g. `ready and willing`. Kept apart from Binary because the right side may never run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logical {
+    pub left: Box<Expr>,
+    pub operator: LogicalOp,
+    pub operator_span: Span,
+    pub right: Box<Expr>,
+    pub span: Span,
+}
+
+/// e.g. `average`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub id: ExprId,
+    pub name: Identifier,
+    pub span: Span,
+}
+
+/// e.g. `average = (min + max) / 2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assign {
+    pub id: ExprId,
+    pub name: Identifier,
+    pub value: Box<Expr>,
+    pub span: Span,
+}
+
+/// e.g. `serve("Dear Reader")`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub callee: Box<Expr>,
+    pub arguments: Vec<Expr>,
+    pub span: Span,
+}
+
+/// e.g. `breakfast.eggs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Get {
+    pub object: Box<Expr>,
+    pub name: Identifier,
+    pub span: Span,
+}
+
+/// e.g. `breakfast.eggs = 2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub object: Box<Expr>,
+    pub name: Identifier,
+    pub value: Box<Expr>,
+    pub span: Span,
+}
+
+/// `this` inside a method
+#[derive(Debug, Clone, PartialEq)]
+pub struct This {
+    pub id: ExprId,
+    pub span: Span,
+}
+
+/// e.g. `super.cook`, a superclass method bound to `this`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Super {
+    pub id: ExprId,
+    pub method: Identifier,
+    pub span: Span,
+}
+
+/// e.g. `[1, 2, 3]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    pub elements: Vec<Expr>,
+    pub span: Span,
+}
+
+/// e.g. `{"eggs": 2, "toast": true}`, keys and values in the order written
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map {
+    pub entries: Vec<(Expr, Expr)>,
+    pub span: Span,
+}
+
+/// e.g. `shelf[2]` or `menu["eggs"]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    pub span: Span,
+}
+
+/// e.g. `shelf[2] = "jam"`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetIndex {
+    pub object: Box<Expr>,
+    pub index: Box<Expr>,
+    pub value: Box<Expr>,
+    pub span: Span,
+}
+
+/// An expression evaluated for its side effects, e.g. `cook();`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub expression: Expr,
+    pub span: Span,
+}
+
+/// e.g. `print "Hello, world!";`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Print {
+    pub expression: Expr,
+    pub span: Span,
+}
+
+/// e.g. `var average = (min + max) / 2;`. Without an initializer the variable starts as nil.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: Identifier,
+    pub initializer: Option<Expr>,
+    pub span: Span,
+}
+
+/// `{ ... }`, a new scope
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// `if (condition) then_branch else else_branch`
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub condition: Expr,
+    pub then_branch: Box<Stmt>,
+    pub else_branch: Option<Box<Stmt>>,
+    pub span: Span,
+}
+
+/// `while (condition) body`. `for` loops are desugared into this by the parser.
+#[derive(Debug, Clone, PartialEq)]
+pub struct While {
+    pub condition: Expr,
+    pub body: Box<Stmt>,
+    // [Note] A desugared `for` keeps its increment apart from the body, so that `continue`,
+    // which skips the rest of the body, still runs it
+    pub increment: Option<Expr>,
+    pub span: Span,
+}
+
+/// `for (var name in iterable) body`: once for every element of a list, key of a map or
+/// character of a string
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForIn {
+    pub name: Identifier,
+    pub iterable: Expr,
+    pub body: Box<Stmt>,
+    pub span: Span,
+}
+
+/// `break;`, leaves the innermost loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Break {
+    pub span: Span,
+}
+
+/// `continue;`, skips to the next iteration of the innermost loop
+#[derive(Debug, Clone, PartialEq)]
+pub struct Continue {
+    pub span: Span,
+}
+
+/// e.g. `fun add(a, b) { return a + b; }`, also used for methods
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Identifier,
+    pub params: Vec<Identifier>,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// e.g. `return inner;`. Without a value the function returns nil.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Return {
+    pub value: Option<Expr>,
+    pub span: Span,
+}
+
+/// e.g. `class Breakfast { cook() { ... } }`, or `class Brunch < Breakfast { ... }`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: Identifier,
+    pub superclass: Option<Variable>,
+    pub methods: Vec<Rc<Function>>,
+    pub span: Span,
+}
+
+impl Expr {
+    /// The span of source code the expression was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Literal(e) => e.span,
+            Expr::Grouping(e) => e.span,
+            Expr::Unary(e) => e.span,
+            Expr::Binary(e) => e.span,
+            Expr::Logical(e) => e.span,
+            Expr::Variable(e) => e.span,
+            Expr::Assign(e) => e.span,
+            Expr::Call(e) => e.span,
+            Expr::Get(e) => e.span,
+            Expr::Set(e) => e.span,
+            Expr::This(e) => e.span,
+            Expr::Super(e) => e.span,
+            Expr::List(e) => e.span,
+            Expr::Map(e) => e.span,
+            Expr::Index(e) => e.span,
+            Expr::SetIndex(e) => e.span,
+        }
+    }
+}
+
+impl Stmt {
+    /// The span of source code the statement was parsed from.
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Expression(s) => s.span,
+            Stmt::Print(s) => s.span,
+            Stmt::Var(s) => s.span,
+            Stmt::Block(s) => s.span,
+            Stmt::If(s) => s.span,
+            Stmt::While(s) => s.span,
+            Stmt::ForIn(s) => s.span,
+            Stmt::Break(s) => s.span,
+            Stmt::Continue(s) => s.span,
+            Stmt::Function(s) => s.span,
+            Stmt::Return(s) => s.span,
+            Stmt::Class(s) => s.span,
+        }
+    }
+}
+
+/// Format a number the way Lox prints it: integers without a trailing ".0".
+pub fn format_number(value: f64) -> String {
+    if value.is_infinite() {
+        // [Note] Rust prints "inf", the reference implementation prints Java's "Infinity"
+        String::from(if value > 0.0 { "Infinity" } else { "-Infinity" })
+    } else if value.fract() == 0.0 && value.abs() < 1e16 {
+        // [Note] -0.0 is kept as "-0", like the reference implementation
+        format!("{:.0}", value)
+    } else {
+        format!("{}", value)
+    }
+}
+
+// [Note] The Display impls print the tree as Lisp-like S-expressions, e.g. `1 + 2 * 3` becomes
+// `(+ 1 (* 2 3))`. It makes the structure (and so the precedence) the parser chose explicit.
+
+impl fmt::Display for LiteralValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LiteralValue::Nil => write!(f, "nil"),
+            LiteralValue::Bool(value) => write!(f, "{}", value),
+            LiteralValue::Number(value) => write!(f, "{}", format_number(*value)),
+            LiteralValue::String(value) => write!(f, "{:?}", value),
+        }
+    }
+}
+
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnaryOp::Negate => write!(f, "-"),
+            UnaryOp::Not => write!(f, "!"),
+            UnaryOp::BitNot => write!(f, "~"),
+        }
+    }
+}
+
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::BitAnd => "&",
+            BinaryOp::BitOr => "|",
+            BinaryOp::BitXor => "^",
+            BinaryOp::ShiftLeft => "<<",
+            BinaryOp::ShiftRight => ">>",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for LogicalOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogicalOp::And => write!(f, "and"),
+            LogicalOp::Or => write!(f, "or"),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Literal(e) => write!(f, "{}", e.value),
+            Expr::Grouping(e) => write!(f, "(group {})", e.expression),
+            Expr::Unary(e) => write!(f, "({} {})", e.operator, e.right),
+            Expr::Binary(e) => write!(f, "({} {} {})", e.operator, e.left, e.right),
+            Expr::Logical(e) => write!(f, "({} {} {})", e.operator, e.left, e.right),
+            Expr::Variable(e) => write!(f, "{}", e.name.name),
+            Expr::Assign(e) => write!(f, "(= {} {})", e.name.name, e.value),
+            Expr::Call(e) => {
+                write!(f, "(call {}", e.callee)?;
+                for argument in &e.arguments {
+                    write!(f, " {}", argument)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Get(e) => write!(f, "(. {} {})", e.object, e.name.name),
+            Expr::Set(e) => write!(f, "(= (. {} {}) {})", e.object, e.name.name, e.value),
+            Expr::This(_) => write!(f, "this"),
+            Expr::Super(e) => write!(f, "(super {})", e.method.name),
+            Expr::List(e) => {
+                write!(f, "(list")?;
+                for element in &e.elements {
+                    write!(f, " {}", element)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Map(e) => {
+                write!(f, "(map")?;
+                for (key, value) in &e.entries {
+                    write!(f, " ({} {})", key, value)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Index(e) => write!(f, "([] {} {})", e.object, e.index),
+            Expr::SetIndex(e) => write!(f, "(= ([] {} {}) {})", e.object, e.index, e.value),
+        }
+    }
+}
+
+impl fmt::Display for Stmt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stmt::Expression(s) => write!(f, "(; {})", s.expression),
+            Stmt::Print(s) => write!(f, "(print {})", s.expression),
+            Stmt::Var(s) => match &s.initializer {
+                Some(initializer) => write!(f, "(var {} {})", s.name.name, initializer),
+                None => write!(f, "(var {})", s.name.name),
+            },
+            Stmt::Block(s) => {
+                write!(f, "(block")?;
+                for statement in &s.statements {
+                    write!(f, " {}", statement)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::If(s) => {
+                write!(f, "(if {} {}", s.condition, s.then_branch)?;
+                if let Some(else_branch) = &s.else_branch {
+                    write!(f, " {}", else_branch)?;
+                }
+                write!(f, ")")
+            }
+            Stmt::While(s) => match &s.increment {
+                Some(increment) => write!(f, "(while {} {} {})", s.condition, s.body, increment),
+                None => write!(f, "(while {} {})", s.condition, s.body),
+            },
+            Stmt::ForIn(s) => write!(f, "(for {} in {} {})", s.name.name, s.iterable, s.body),
+            Stmt::Break(_) => write!(f, "(break)"),
+            Stmt::Continue(_) => write!(f, "(continue)"),
+            Stmt::Function(s) => write!(f, "{}", s),
+            Stmt::Return(s) => match &s.value {
+                Some(value) => write!(f, "(return {})", value),
+                None => write!(f, "(return)"),
+            },
+            Stmt::Class(s) => {
+                write!(f, "(class {}", s.name.name)?;
+                if let Some(superclass) = &s.superclass {
+                    write!(f, " < {}", superclass.name.name)?;
+                }
+                for method in &s.methods {
+                    write!(f, " {}", method)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<&str> = self.params.iter().map(|p| p.name.as_str()).collect();
+        write!(f, "(fun {} ({})", self.name.name, params.join(" "))?;
+        for statement in &self.body {
+            write!(f, " {}", statement)?;
+        }
+        write!(f, ")")
+    }
+}
@@ -0,0 +1,191 @@
+use crate::modules::ast::{format_number, BinaryOp};
+// Bitwise operators: an extension to Lox, which only has floating point numbers.
+// [Note] The operators work on the integer a number holds exactly. An f64 holds every whole number
+// up to 2^53 exactly, so operands must be whole and within ±(2^53 - 1), and a result that would
+// leave that range is an error rather than a silently rounded number. Negative numbers behave as
+// two's complement, as in C: ~0 is -1, and >> keeps the sign.
+
+/// The largest whole number every operand and result must stay within, 2^53 - 1.
+pub const MAX_EXACT: f64 = 9_007_199_254_740_991.0;
+
+/// The largest shift allowed. Shifting by 53 or more would push every bit out of range.
+pub const MAX_SHIFT: i128 = 52;
+
+/// Enum representing which operand of a binary operator was not usable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Left,
+    Right,
+}
+
+/// Struct representing a bitwise operation that has no exact result.
+#[derive(Debug, PartialEq)]
+pub struct BitwiseError {
+    /// The operand to blame, or None when both were fine but the result is out of range
+    pub operand: Option<Operand>,
+    pub message: String,
+}
+
+/// The integer a number holds, if it is a whole number within the exact range.
+fn integer(operator: &str, value: f64, operand: Option<Operand>) -> Result<i64, BitwiseError> {
+    if value.fract() == 0.0 && value.abs() <= MAX_EXACT {
+        Ok(value as i64)
+    } else {
+        Err(BitwiseError {
+            operand,
+            message: format!(
+                "Operand of '{}' must be an exact integer, not {}.",
+                operator,
+                format_number(value)
+            ),
+        })
+    }
+}
+
+/// Apply a bitwise binary operator (`&`, `|`, `^`, `<<` or `>>`) to two numbers.
+pub fn binary(operator: BinaryOp, left: f64, right: f64) -> Result<f64, BitwiseError> {
+    let symbol = operator.to_string();
+    let a = integer(&symbol, left, Some(Operand::Left))?;
+    let b = integer(&symbol, right, Some(Operand::Right))?;
+
+    // [Note] Worked out in 128 bits: a 53 bit number shifted by up to 52 bits cannot overflow it
+    let (a, b) = (i128::from(a), i128::from(b));
+    let result = match operator {
+        BinaryOp::BitAnd => a & b,
+        BinaryOp::BitOr => a | b,
+        BinaryOp::BitXor => a ^ b,
+        BinaryOp::ShiftLeft | BinaryOp::ShiftRight => {
+            if !(0..=MAX_SHIFT).contains(&b) {
+                return Err(BitwiseError {
+                    operand: Some(Operand::Right),
+                    message: format!("Shift amount must be from 0 to {}, not {}.", MAX_SHIFT, b),
+                });
+            }
+            if operator == BinaryOp::ShiftLeft {
+                a << b
+            } else {
+                a >> b
+            }
+        }
+        _ => unreachable!("{} is not a bitwise operator", operator),
+    };
+
+    // [Note] &, | and ^ of two exact integers are always exact, only << can leave the range
+    let result = result as f64;
+    if result.abs() > MAX_EXACT {
+        return Err(BitwiseError {
+            operand: None,
+            message: format!(
+                "Result of {} {} {} is too large to be an exact integer.",
+                format_number(left),
+                symbol,
+                format_number(right)
+            ),
+        });
+    }
+    Ok(result)
+}
+
+/// Apply `~` to a number.
+pub fn not(value: f64) -> Result<f64, BitwiseError> {
+    Ok(!integer("~", value, None)? as f64)
+}
+
+/// Tests for the bitwise operations
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::interpreter::tests::{output, runtime_error};
+
+    #[test]
+    fn operators() {
+        assert_eq!(binary(BinaryOp::BitAnd, 12.0, 10.0), Ok(8.0));
+        assert_eq!(binary(BinaryOp::BitOr, 12.0, 10.0), Ok(14.0));
+        assert_eq!(binary(BinaryOp::BitXor, 12.0, 10.0), Ok(6.0));
+        assert_eq!(
+            binary(BinaryOp::ShiftLeft, 1.0, 52.0),
+            Ok(4503599627370496.0)
+        );
+        assert_eq!(binary(BinaryOp::ShiftRight, 40.0, 3.0), Ok(5.0));
+        assert_eq!(not(0.0), Ok(-1.0));
+        assert_eq!(not(-6.0), Ok(5.0));
+    }
+
+    #[test]
+    fn negative_numbers_are_twos_complement() {
+        assert_eq!(binary(BinaryOp::BitAnd, -1.0, 255.0), Ok(255.0));
+        assert_eq!(binary(BinaryOp::ShiftRight, -16.0, 2.0), Ok(-4.0));
+        assert_eq!(binary(BinaryOp::BitOr, -MAX_EXACT, MAX_EXACT), Ok(-1.0));
+    }
+
+    #[test]
+    fn inexact_operands_and_results() {
+        let error = binary(BinaryOp::BitAnd, 1.5, 1.0).unwrap_err();
+        assert_eq!(error.operand, Some(Operand::Left));
+        assert_eq!(
+            error.message,
+            "Operand of '&' must be an exact integer, not 1.5."
+        );
+
+        let error = binary(BinaryOp::BitOr, 1.0, 1e16).unwrap_err();
+        assert_eq!(error.operand, Some(Operand::Right));
+
+        let error = binary(BinaryOp::ShiftLeft, 1.0, 53.0).unwrap_err();
+        assert_eq!(error.operand, Some(Operand::Right));
+        assert_eq!(error.message, "Shift amount must be from 0 to 52, not 53.");
+        assert!(binary(BinaryOp::ShiftRight, 1.0, -1.0).is_err());
+
+        assert!(binary(BinaryOp::ShiftLeft, MAX_EXACT, 52.0).is_err());
+        let error = binary(BinaryOp::ShiftLeft, 3.0, 52.0).unwrap_err();
+        assert_eq!(error.operand, None);
+        assert_eq!(
+            error.message,
+            "Result of 3 << 52 is too large to be an exact integer."
+        );
+
+        assert!(not(f64::NAN).is_err());
+        assert!(not(f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn lox_programs() {
+        let script = "
+            fun isPowerOf2(x) { return x != 0 and (x & (x - 1)) == 0; }
+            fun bitsSet(x) { var count = 0; while (x != 0) { x = x & (x - 1); count = count + 1; } return count; }
+            print isPowerOf2(64); print isPowerOf2(12);
+            print bitsSet(5); print bitsSet(0); print bitsSet((1 << 32) - 1);
+            print 6 ^ 3 | 8; print ~5 + 1;";
+        assert_eq!(
+            output(script),
+            vec!["true", "false", "2", "0", "32", "13", "-5"]
+        );
+    }
+
+    #[test]
+    fn runtime_errors_point_at_the_operand() {
+        assert_eq!(
+            runtime_error("var half = 0.5;\nprint 1 | half;"),
+            "[line 2, column 11] Error: Operand of '|' must be an exact integer, not 0.5."
+        );
+        assert_eq!(
+            runtime_error("print 1 << 60;"),
+            "[line 1, column 12] Error: Shift amount must be from 0 to 52, not 60."
+        );
+        assert_eq!(
+            runtime_error("print 3 << 52;"),
+            "[line 1, column 7] Error: Result of 3 << 52 is too large to be an exact integer."
+        );
+        assert_eq!(
+            runtime_error("print ~1.5;"),
+            "[line 1, column 8] Error: Operand of '~' must be an exact integer, not 1.5."
+        );
+        assert_eq!(
+            runtime_error("print 1 & \"1\";"),
+            "[line 1, column 9] Error: Operands must be numbers."
+        );
+        assert_eq!(
+            runtime_error("print ~nil;"),
+            "[line 1, column 7] Error: Operand must be a number."
+        );
+    }
+}
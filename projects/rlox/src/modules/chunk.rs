@@ -0,0 +1,284 @@
+use crate::modules::interpreter::Value;
+use crate::modules::tokenize::Span;
+// Chunks of Bytecode: Chapter 14 of "Crafting Interpreters"
+// [Note] A chunk is the compiled form of one function: a flat array of bytes, each instruction an
+// opcode byte followed by its operands, plus the constants the instructions refer to by index.
+// Operands wider than a byte are stored big-endian.
+
+/// Enum representing the instructions of the virtual machine.
+/// The operands of each instruction follow it in the chunk, and are listed here with their width.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// constant: u16. Push a value from the constant pool
+    Constant,
+    Nil,
+    True,
+    False,
+    /// Discard the value on top of the stack
+    Pop,
+    /// slot: u8
+    GetLocal,
+    /// slot: u8
+    SetLocal,
+    /// name: u16
+    GetGlobal,
+    /// name: u16
+    DefineGlobal,
+    /// name: u16
+    SetGlobal,
+    /// index: u8
+    GetUpvalue,
+    /// index: u8
+    SetUpvalue,
+    /// name: u16
+    GetProperty,
+    /// name: u16. Fail unless the value on top of the stack is an instance, whose field is about
+    /// to be set. Checked before the value is evaluated, as the tree-walker does
+    CheckInstance,
+    /// name: u16
+    SetProperty,
+    /// name: u16. Look up a method on the superclass, bound to `this`
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
+    Not,
+    Negate,
+    BitNot,
+    Print,
+    /// offset: u16. Jump forward
+    Jump,
+    /// offset: u16. Jump forward if the value on top of the stack is falsey, leaving it there
+    JumpIfFalse,
+    /// offset: u16. Jump backward
+    Loop,
+    /// argument count: u8
+    Call,
+    /// function: u16, then a pair of bytes for every upvalue: whether it is a local of the
+    /// enclosing function (1) or one of its upvalues (0), and the slot or upvalue index
+    Closure,
+    /// Move the local on top of the stack into the heap, for the closures that captured it
+    CloseUpvalue,
+    Return,
+    /// name: u16
+    Class,
+    /// name: u16, the superclass's name for the error when it is not a class
+    Inherit,
+    /// name: u16
+    Method,
+    /// Push a new empty list
+    List,
+    /// Add the value on top of the stack to the end of the list under it
+    Append,
+    /// Push a new empty map
+    Map,
+    /// Add the key and value on top of the stack to the map under them
+    Insert,
+    GetIndex,
+    SetIndex,
+    /// Replace the value on top of the stack with a list of the elements a for-in loop visits
+    Elements,
+    /// slot: u8, offset: u16. Push the next element of the list in local `slot`, whose position
+    /// is kept in local `slot + 1`, or jump forward when there are none left
+    ForIn,
+}
+
+impl OpCode {
+    // [Note] Listed in declaration order, so the position of every opcode here is its byte
+    const ALL: [OpCode; 53] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::CheckInstance,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::BitAnd,
+        OpCode::BitOr,
+        OpCode::BitXor,
+        OpCode::ShiftLeft,
+        OpCode::ShiftRight,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::BitNot,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::List,
+        OpCode::Append,
+        OpCode::Map,
+        OpCode::Insert,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::Elements,
+        OpCode::ForIn,
+    ];
+
+    /// The opcode a byte encodes, if it is one.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL
+            .get(usize::from(byte))
+            .copied()
+            .filter(|op| *op as u8 == byte)
+    }
+}
+
+/// Struct representing a compiled function body: its bytecode, constants and where each byte came from.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// The source span of every byte, run-length encoded: (span, number of bytes in a row)
+    // [Note] An instruction and its operands always share a span, and so often do the instructions
+    // of one expression, so runs are long and the table stays much smaller than the code
+    spans: Vec<(Span, usize)>,
+    /// More spans an instruction's errors can point at, by the instruction's offset, e.g. a call's
+    /// arguments. Kept in order of offset
+    operand_spans: Vec<(usize, Vec<Span>)>,
+}
+
+impl Chunk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a byte of code that came from `span`.
+    pub fn write(&mut self, byte: u8, span: Span) {
+        self.code.push(byte);
+        match self.spans.last_mut() {
+            Some((last, count)) if *last == span => *count += 1,
+            _ => self.spans.push((span, 1)),
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, span: Span) {
+        self.write(op as u8, span);
+    }
+
+    pub fn write_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.write(byte, span);
+        }
+    }
+
+    /// Add a value to the constant pool, returning its index.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Record the spans of the operands of the instruction at `offset`.
+    pub fn add_operand_spans(&mut self, offset: usize, spans: Vec<Span>) {
+        self.operand_spans.push((offset, spans));
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    /// Where the byte at `offset` came from.
+    pub fn span(&self, offset: usize) -> Span {
+        let mut start = 0;
+        for (span, count) in &self.spans {
+            start += count;
+            if offset < start {
+                return *span;
+            }
+        }
+        panic!("offset {} is past the end of the chunk", offset)
+    }
+
+    /// The spans of the operands of the instruction at `offset`, empty if none were recorded.
+    pub fn operand_spans(&self, offset: usize) -> &[Span] {
+        match self
+            .operand_spans
+            .binary_search_by_key(&offset, |(start, _)| *start)
+        {
+            Ok(index) => &self.operand_spans[index].1,
+            Err(_) => &[],
+        }
+    }
+}
+
+/// Tests for chunks
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn opcodes_round_trip_through_bytes() {
+        for (byte, op) in OpCode::ALL.iter().enumerate() {
+            assert_eq!(*op as usize, byte);
+            assert_eq!(OpCode::from_byte(byte as u8), Some(*op));
+        }
+        assert_eq!(OpCode::from_byte(OpCode::ALL.len() as u8), None);
+    }
+
+    #[test]
+    fn spans_are_run_length_encoded() {
+        let first = Span::new(0, 5, 1, 1);
+        let second = Span::new(6, 8, 2, 1);
+        let mut chunk = Chunk::new();
+        let constant = chunk.add_constant(Value::Number(1.2));
+        chunk.write_op(OpCode::Constant, first);
+        chunk.write_u16(constant as u16, first);
+        chunk.write_op(OpCode::Negate, first);
+        chunk.write_op(OpCode::Print, second);
+
+        assert_eq!(chunk.spans, vec![(first, 4), (second, 1)]);
+        assert_eq!(chunk.span(0), first);
+        assert_eq!(chunk.span(3), first);
+        assert_eq!(chunk.span(4), second);
+        assert_eq!(chunk.read_u16(1), 0);
+    }
+
+    #[test]
+    fn operand_spans_by_offset() {
+        let mut chunk = Chunk::new();
+        let argument = Span::new(2, 3, 1, 3);
+        chunk.add_operand_spans(4, vec![argument]);
+        chunk.add_operand_spans(9, vec![]);
+        assert_eq!(chunk.operand_spans(4), &[argument]);
+        assert!(chunk.operand_spans(5).is_empty());
+    }
+}
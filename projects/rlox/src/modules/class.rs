@@ -0,0 +1,134 @@
+use crate::modules::ast::{Call, Identifier};
+use crate::modules::function::LoxFunction;
+use crate::modules::interpreter::{Callable, Interpreter, RuntimeError, Value};
+use crate::modules::tokenize::Span;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+// Classes and Inheritance: Chapters 12 and 13 of "Crafting Interpreters"
+// [Note] A class is a callable value that makes instances. The instances hold the state (fields),
+// the class holds the behaviour (methods), shared by every instance made from it.
+
+/// Struct representing a class declared in Lox code, as a runtime value.
+#[derive(Debug)]
+pub struct LoxClass {
+    name: String,
+    superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: &str,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> Self {
+        LoxClass {
+            name: String::from(name),
+            superclass,
+            methods,
+        }
+    }
+
+    /// Look up a method by name, in this class and then up the superclass chain.
+    // [Note] A subclass's own method wins over an inherited one of the same name: it overrides it
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self.superclass.as_ref()?.find_method(name),
+        }
+    }
+}
+
+impl fmt::Display for LoxClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Callable for LoxClass {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// A class is called with the arguments of its initializer, if it has one.
+    fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    fn declaration(&self) -> Option<Span> {
+        self.find_method("init")?.declaration()
+    }
+
+    fn type_name(&self) -> &'static str {
+        "class"
+    }
+
+    fn into_class(self: Rc<Self>) -> Option<Rc<LoxClass>> {
+        Some(self)
+    }
+
+    /// Calling a class makes a new instance, and runs `init` on it.
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        call: &Call,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Rc::new(RefCell::new(Instance::new(Rc::clone(&self))));
+        if let Some(init) = self.find_method("init") {
+            Rc::new(init.bind(Rc::clone(&instance))).call(interpreter, call, arguments)?;
+        }
+        Ok(Value::Instance(instance))
+    }
+}
+
+/// Struct representing an object: an instance of a class, with its own set of fields.
+#[derive(Debug)]
+pub struct Instance {
+    class: Rc<LoxClass>,
+    fields: HashMap<String, Value>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<LoxClass>) -> Self {
+        Instance {
+            class,
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Read a property: a field, or else a method bound to the instance.
+    // [Note] Takes the Rc rather than &self, because a bound method keeps the instance alive
+    pub fn get(instance: &Rc<RefCell<Instance>>, name: &Identifier) -> Result<Value, RuntimeError> {
+        // Fields shadow methods
+        if let Some(value) = instance.borrow().fields.get(&name.name) {
+            return Ok(value.clone());
+        }
+
+        let class = Rc::clone(&instance.borrow().class);
+        match class.find_method(&name.name) {
+            Some(method) => Ok(Value::Callable(Rc::new(method.bind(Rc::clone(instance))))),
+            None => Err(RuntimeError::new(
+                name.span,
+                format!(
+                    "Undefined property '{}' on {}.",
+                    name.name,
+                    instance.borrow()
+                ),
+            )),
+        }
+    }
+
+    /// Write a field. Lox lets code add new fields to an instance at any time.
+    pub fn set(&mut self, name: &Identifier, value: Value) {
+        self.fields.insert(name.name.clone(), value);
+    }
+}
+
+impl fmt::Display for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} instance", self.class)
+    }
+}
@@ -0,0 +1,545 @@
+use crate::modules::ast::format_number;
+use crate::modules::interpreter::Value;
+use crate::modules::native::{NativeError, NativeFunction};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+// Lists and maps: an extension to Lox, which only has instances to hold several values.
+// [Note] Like instances, lists and maps are reference values: assigning one or passing it to a
+// function shares it, and a change made through one name is seen through every other. Their
+// methods are native functions bound to the collection they were looked up on.
+
+/// A list's elements, shared by every value that refers to the list.
+pub type LoxList = Rc<RefCell<Vec<Value>>>;
+
+/// Enum representing a value that can be a map key.
+// [Note] Only values that compare by content can be keys. A number key is stored as its bits,
+// with -0 turned into 0 so that the two zeros, which are equal, find the same entry.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Bool(bool),
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl Key {
+    pub fn new(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Bool(value) => Ok(Key::Bool(*value)),
+            Value::Number(value) if value.is_nan() => Err(String::from(
+                "Map keys must not be NaN, it is not equal to itself.",
+            )),
+            Value::Number(value) => Ok(Key::Number((value + 0.0).to_bits())),
+            Value::String(value) => Ok(Key::String(Rc::clone(value))),
+            other => Err(format!(
+                "Map keys must be strings, numbers or booleans, not {}.",
+                other.type_name()
+            )),
+        }
+    }
+
+    /// The key as a Lox value again.
+    pub fn value(&self) -> Value {
+        match self {
+            Key::Bool(value) => Value::Bool(*value),
+            Key::Number(bits) => Value::Number(f64::from_bits(*bits)),
+            Key::String(value) => Value::String(Rc::clone(value)),
+        }
+    }
+}
+
+/// Struct representing a map's entries, in the order their keys were first added.
+#[derive(Debug, Default)]
+pub struct LoxMap {
+    entries: Vec<(Key, Value)>,
+    /// Where each key's entry is in `entries`
+    positions: HashMap<Key, usize>,
+}
+
+impl LoxMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        self.positions
+            .get(key)
+            .map(|&position| &self.entries[position].1)
+    }
+
+    /// Set the value for `key`. A key already in the map keeps its place in the order.
+    pub fn insert(&mut self, key: Key, value: Value) {
+        match self.positions.get(&key) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The keys, in order, as Lox values.
+    pub fn keys(&self) -> Vec<Value> {
+        self.entries.iter().map(|(key, _)| key.value()).collect()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (&Key, &Value)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+}
+
+// Indexing
+
+/// Enum representing which part of `object[index]` was not usable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    Object,
+    Index,
+}
+
+/// Struct representing an index that could not be read or set.
+#[derive(Debug, PartialEq)]
+pub struct IndexError {
+    pub part: Part,
+    pub message: String,
+}
+
+impl IndexError {
+    fn index(message: String) -> Self {
+        IndexError {
+            part: Part::Index,
+            message,
+        }
+    }
+}
+
+fn not_indexable(object: &Value) -> IndexError {
+    IndexError {
+        part: Part::Object,
+        message: format!(
+            "Only lists and maps can be indexed, not {}.",
+            object.type_name()
+        ),
+    }
+}
+
+/// The position `index` refers to in a list of length `length`.
+fn position(index: &Value, length: usize) -> Result<usize, IndexError> {
+    let Value::Number(index) = index else {
+        return Err(IndexError::index(format!(
+            "List index must be a number, not {}.",
+            index.type_name()
+        )));
+    };
+    if index.fract() != 0.0 || !index.is_finite() {
+        return Err(IndexError::index(format!(
+            "List index must be a whole number, not {}.",
+            format_number(*index)
+        )));
+    }
+    if *index < 0.0 || *index >= length as f64 {
+        return Err(IndexError::index(format!(
+            "Index {} is out of bounds for a list of length {}.",
+            format_number(*index),
+            length
+        )));
+    }
+    Ok(*index as usize)
+}
+
+/// `object[index]`
+pub fn get(object: &Value, index: &Value) -> Result<Value, IndexError> {
+    match object {
+        Value::List(list) => {
+            let list = list.borrow();
+            Ok(list[position(index, list.len())?].clone())
+        }
+        Value::Map(map) => {
+            let key = Key::new(index).map_err(IndexError::index)?;
+            match map.borrow().get(&key) {
+                Some(value) => Ok(value.clone()),
+                None => Err(IndexError::index(format!(
+                    "Key {} is not in the map.",
+                    Element(index)
+                ))),
+            }
+        }
+        _ => Err(not_indexable(object)),
+    }
+}
+
+/// `object[index] = value`. A list can only be set within its bounds, `push` makes it longer.
+pub fn set(object: &Value, index: &Value, value: Value) -> Result<(), IndexError> {
+    match object {
+        Value::List(list) => {
+            let mut list = list.borrow_mut();
+            let position = position(index, list.len())?;
+            list[position] = value;
+            Ok(())
+        }
+        Value::Map(map) => {
+            let key = Key::new(index).map_err(IndexError::index)?;
+            map.borrow_mut().insert(key, value);
+            Ok(())
+        }
+        _ => Err(not_indexable(object)),
+    }
+}
+
+// Methods
+
+/// The method `name` of a list or map, bound to it. None if there is no such method.
+pub fn method(object: &Value, name: &str) -> Option<NativeFunction> {
+    match (object, name) {
+        (Value::List(list), "push") => {
+            let list = Rc::clone(list);
+            Some(NativeFunction::new(
+                "push",
+                1,
+                Box::new(move |arguments| {
+                    list.borrow_mut().push(arguments[0].clone());
+                    Ok(Value::Nil)
+                }),
+            ))
+        }
+        (Value::List(list), "pop") => {
+            let list = Rc::clone(list);
+            Some(NativeFunction::new(
+                "pop",
+                0,
+                Box::new(move |_| {
+                    list.borrow_mut().pop().ok_or_else(|| {
+                        NativeError::Call(String::from("Can't pop from an empty list."))
+                    })
+                }),
+            ))
+        }
+        (Value::List(list), "len") => {
+            let list = Rc::clone(list);
+            Some(NativeFunction::new(
+                "len",
+                0,
+                Box::new(move |_| Ok(Value::Number(list.borrow().len() as f64))),
+            ))
+        }
+        (Value::Map(map), "len") => {
+            let map = Rc::clone(map);
+            Some(NativeFunction::new(
+                "len",
+                0,
+                Box::new(move |_| Ok(Value::Number(map.borrow().len() as f64))),
+            ))
+        }
+        (Value::Map(map), "keys") => {
+            let map = Rc::clone(map);
+            Some(NativeFunction::new(
+                "keys",
+                0,
+                Box::new(move |_| Ok(list(map.borrow().keys()))),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// A new list value holding `elements`.
+pub fn list(elements: Vec<Value>) -> Value {
+    Value::List(Rc::new(RefCell::new(elements)))
+}
+
+// Iteration
+
+/// A list of the values a for-in loop over `iterable` visits.
+// [Note] A list is its own sequence, read one element at a time, so elements pushed by the loop body
+// are visited too, and popping them ends the loop early. A map's keys and a string's characters are
+// copied into a new list up front, since a map entry has no position to carry on from.
+pub fn sequence(iterable: &Value) -> Result<LoxList, String> {
+    match iterable {
+        Value::List(list) => Ok(Rc::clone(list)),
+        Value::Map(map) => Ok(Rc::new(RefCell::new(map.borrow().keys()))),
+        Value::String(text) => Ok(Rc::new(RefCell::new(
+            text.chars()
+                .map(|c| Value::String(Rc::from(c.to_string())))
+                .collect(),
+        ))),
+        other => Err(format!(
+            "Only lists, maps and strings can be looped over, not {}.",
+            other.type_name()
+        )),
+    }
+}
+
+/// Struct representing the values a for-in loop goes through, as they are reached.
+pub struct Elements {
+    list: LoxList,
+    next: usize,
+}
+
+impl Elements {
+    pub fn new(iterable: &Value) -> Result<Self, String> {
+        Ok(Elements {
+            list: sequence(iterable)?,
+            next: 0,
+        })
+    }
+}
+
+impl Iterator for Elements {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        let element = self.list.borrow().get(self.next).cloned()?;
+        self.next += 1;
+        Some(element)
+    }
+}
+
+// Printing
+
+/// A value as it prints inside a list or map: strings are quoted, so `["1"]` and `[1]` differ.
+struct Element<'a>(&'a Value);
+
+impl fmt::Display for Element<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Value::String(text) => write!(f, "\"{}\"", text),
+            value => write!(f, "{}", value),
+        }
+    }
+}
+
+thread_local! {
+    /// The collections being printed, innermost last
+    static PRINTING: RefCell<Vec<*const ()>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Print a collection, or `[...]` / `{...}` if it is already being printed further out.
+// [Note] A list can hold itself (`a.push(a)`), and printing it naively would never end
+fn guarded(
+    f: &mut fmt::Formatter,
+    address: *const (),
+    cycle: &str,
+    write: impl FnOnce(&mut fmt::Formatter) -> fmt::Result,
+) -> fmt::Result {
+    if PRINTING.with(|printing| printing.borrow().contains(&address)) {
+        return write!(f, "{}", cycle);
+    }
+    PRINTING.with(|printing| printing.borrow_mut().push(address));
+    let result = write(f);
+    PRINTING.with(|printing| printing.borrow_mut().pop());
+    result
+}
+
+pub fn fmt_list(list: &LoxList, f: &mut fmt::Formatter) -> fmt::Result {
+    guarded(f, Rc::as_ptr(list) as *const (), "[...]", |f| {
+        write!(f, "[")?;
+        for (i, element) in list.borrow().iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", Element(element))?;
+        }
+        write!(f, "]")
+    })
+}
+
+pub fn fmt_map(map: &Rc<RefCell<LoxMap>>, f: &mut fmt::Formatter) -> fmt::Result {
+    guarded(f, Rc::as_ptr(map) as *const (), "{...}", |f| {
+        write!(f, "{{")?;
+        for (i, (key, value)) in map.borrow().entries().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}: {}", Element(&key.value()), Element(value))?;
+        }
+        write!(f, "}}")
+    })
+}
+
+/// Tests for lists and maps
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::interpreter::tests::{output, runtime_error};
+
+    #[test]
+    fn maps_keep_insertion_order() {
+        let mut map = LoxMap::new();
+        let key = |value: Value| Key::new(&value).unwrap();
+        map.insert(key(Value::String("b".into())), Value::Number(1.0));
+        map.insert(key(Value::Number(-0.0)), Value::Nil);
+        map.insert(key(Value::Bool(true)), Value::Nil);
+        map.insert(key(Value::String("b".into())), Value::Number(2.0));
+        map.insert(key(Value::Number(0.0)), Value::Bool(false));
+
+        assert_eq!(map.len(), 3);
+        assert_eq!(
+            map.keys(),
+            vec![
+                Value::String("b".into()),
+                Value::Number(0.0),
+                Value::Bool(true)
+            ]
+        );
+        assert_eq!(
+            map.get(&key(Value::String("b".into()))),
+            Some(&Value::Number(2.0))
+        );
+        assert_eq!(map.get(&key(Value::Number(0.0))), Some(&Value::Bool(false)));
+        assert!(Key::new(&Value::Number(f64::NAN)).is_err());
+        assert!(Key::new(&Value::Nil).is_err());
+    }
+
+    #[test]
+    fn literals_and_printing() {
+        assert_eq!(
+            output(
+                "print []; print [1, \"two\", nil, [true]]; print {}; print {\"a\": 1, 2: \"b\",};\
+                 print str([\"x\"]);"
+            ),
+            vec![
+                "[]",
+                "[1, \"two\", nil, [true]]",
+                "{}",
+                "{\"a\": 1, 2: \"b\"}",
+                "[\"x\"]"
+            ]
+        );
+    }
+
+    #[test]
+    fn indexing() {
+        let script = "var a = [10, 20, 30]; print a[0] + a[2]; a[1] = \"x\"; print a;\
+                      var m = {\"k\": 1}; m[\"k\"] = m[\"k\"] + 1; m[false] = 0; print m; print m[\"k\"];\
+                      print [[1, 2], [3, 4]][1][0]; print a[1] = 5;";
+        assert_eq!(
+            output(script),
+            vec![
+                "40",
+                "[10, \"x\", 30]",
+                "{\"k\": 2, false: 0}",
+                "2",
+                "3",
+                "5"
+            ]
+        );
+        // Number keys are compared by value, so 0 and -0 are the same key
+        assert_eq!(output("var m = {0: \"zero\"}; print m[-0];"), vec!["zero"]);
+    }
+
+    #[test]
+    fn collections_are_shared_on_assignment() {
+        let script = "var a = [1]; var b = a; b.push(2); print a; print a == b; print a == [1, 2];\
+                      fun fill(m) { m[\"x\"] = 1; } var m = {}; fill(m); print m;";
+        assert_eq!(
+            output(script),
+            vec!["[1, 2]", "true", "false", "{\"x\": 1}"]
+        );
+    }
+
+    #[test]
+    fn methods() {
+        let script = "var a = []; a.push(1); a.push(2); print a.len(); print a.pop(); print a;\
+                      var m = {\"b\": 1, \"a\": 2}; m[\"c\"] = 3; m[\"b\"] = 4; print m.keys(); print m.len();\
+                      var push = a.push; push(3); print a; print push; print len(a) + len(m);";
+        assert_eq!(
+            output(script),
+            vec![
+                "2",
+                "2",
+                "[1]",
+                "[\"b\", \"a\", \"c\"]",
+                "3",
+                "[1, 3]",
+                "<native fn push>",
+                "5"
+            ]
+        );
+    }
+
+    #[test]
+    fn iteration() {
+        let script = "for (var x in [1, 2, 3]) print x;\
+                      for (var k in {\"a\": 1, \"b\": 2}) print k;\
+                      for (var c in \"hé\") print c;\
+                      var a = [1]; for (var x in a) { if (x < 3) a.push(x + 1); print x; }\
+                      for (var x in [1, 2, 3, 4]) { if (x == 2) continue; if (x == 4) break; print x * 10; }";
+        assert_eq!(
+            output(script),
+            vec!["1", "2", "3", "a", "b", "h", "é", "1", "2", "3", "10", "30"]
+        );
+        // Every element gets its own variable, for closures to keep
+        let script = "var fs = []; for (var x in [1, 2]) { fun f() { return x; } fs.push(f); }\
+                      print fs[0]() + fs[1]();";
+        assert_eq!(output(script), vec!["3"]);
+    }
+
+    #[test]
+    fn cycles_print_once() {
+        assert_eq!(
+            output("var a = [1]; a.push(a); print a; var m = {}; m[1] = m; print m;"),
+            vec!["[1, [...]]", "{1: {...}}"]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_index() {
+        assert_eq!(
+            runtime_error("var a = [1, 2, 3];\nprint a[1 + 4];"),
+            "[line 2, column 9] Error: Index 5 is out of bounds for a list of length 3."
+        );
+        assert_eq!(
+            runtime_error("var a = [];\na[-1] = 1;"),
+            "[line 2, column 3] Error: Index -1 is out of bounds for a list of length 0."
+        );
+        assert_eq!(
+            runtime_error("print [1][0.5];"),
+            "[line 1, column 11] Error: List index must be a whole number, not 0.5."
+        );
+        assert_eq!(
+            runtime_error("print [1][\"0\"];"),
+            "[line 1, column 11] Error: List index must be a number, not string."
+        );
+        assert_eq!(
+            runtime_error("var m = {\"a\": 1};\nprint m[\"b\"];"),
+            "[line 2, column 9] Error: Key \"b\" is not in the map."
+        );
+        assert_eq!(
+            runtime_error("print {}[nil];"),
+            "[line 1, column 10] Error: Map keys must be strings, numbers or booleans, not nil."
+        );
+        assert_eq!(
+            runtime_error("var m = {[]: 1};"),
+            "[line 1, column 10] Error: Map keys must be strings, numbers or booleans, not list."
+        );
+    }
+
+    #[test]
+    fn other_errors() {
+        assert_eq!(
+            runtime_error("var n = 1;\nprint n[0];"),
+            "[line 2, column 7] Error: Only lists and maps can be indexed, not number."
+        );
+        assert_eq!(
+            runtime_error("[].pop();"),
+            "[line 1, column 1] Error: Can't pop from an empty list."
+        );
+        assert_eq!(
+            runtime_error("[].keys();"),
+            "[line 1, column 4] Error: Undefined property 'keys' on list."
+        );
+        assert_eq!(
+            runtime_error("for (var x in 3) print x;"),
+            "[line 1, column 15] Error: Only lists, maps and strings can be looped over, not number."
+        );
+    }
+}
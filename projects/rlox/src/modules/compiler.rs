@@ -0,0 +1,991 @@
+use crate::modules::ast::*;
+use crate::modules::chunk::{Chunk, OpCode};
+use crate::modules::interpreter::Value;
+use crate::modules::object::{ObjFunction, Object};
+use crate::modules::tokenize::Span;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+// Compiling to Bytecode: Chapters 17 and 21 to 29 of "Crafting Interpreters"
+// [Note] clox compiles straight from tokens in a single pass. This compiler walks the AST instead,
+// after the resolver has checked it, so both backends report exactly the same syntax and resolve
+// errors and warnings, and the compiler only has to deal with what the bytecode cannot express.
+// It still works out its own local slots and upvalues, the resolver's depths are for environments.
+
+/// The most locals a function can have in scope at once, since a slot is a one byte operand.
+pub const MAX_LOCALS: usize = 256;
+
+/// The most variables a function can capture, since an upvalue index is a one byte operand.
+pub const MAX_UPVALUES: usize = 256;
+
+/// Struct representing something the program does that the bytecode has no room for.
+#[derive(Debug, PartialEq)]
+pub struct CompileError {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, column {}] Error: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+/// Enum representing the kind of function being compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Struct representing a local variable, which lives in a slot of its function's stack window.
+#[derive(Debug)]
+struct Local {
+    name: String,
+    depth: usize,
+    /// Whether a closure refers to it, so it must be moved to the heap when it goes out of scope
+    is_captured: bool,
+}
+
+/// Struct representing a variable a function captures: a local of the enclosing function, or one
+/// of the enclosing function's own upvalues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Upvalue {
+    index: u8,
+    is_local: bool,
+}
+
+/// Struct representing a loop being compiled, for `break` and `continue` to jump out of.
+#[derive(Debug)]
+struct Loop {
+    /// The scope depth outside the loop body. Locals deeper than this are popped by a jump
+    scope_depth: usize,
+    /// Where `continue` goes, if it is already known. A loop with an increment continues at the
+    /// increment, which is only compiled after the body
+    continue_target: Option<usize>,
+    /// Jumps to patch once the end of the loop is known
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+}
+
+/// Struct representing a function being compiled.
+#[derive(Debug)]
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    loops: Vec<Loop>,
+    /// The constant holding each number and string already used, names included, so each is only
+    /// stored once per chunk
+    interned: HashMap<Interned, u16>,
+}
+
+/// Enum representing a constant that is stored once per chunk: a number by its bits, or a string.
+// [Note] By bits rather than by value, so 0 and -0 stay apart
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Interned {
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: &str, arity: usize) -> Self {
+        // [Note] Slot 0 holds the function being called. In a method it is `this`, which the VM
+        // puts there, for anywhere else the empty name keeps it from being found
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+        FunctionState {
+            function: ObjFunction {
+                name: String::from(name),
+                arity,
+                upvalue_count: 0,
+                chunk: Chunk::new(),
+                declaration: None,
+            },
+            kind,
+            locals: vec![Local {
+                name: String::from(slot_zero),
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            loops: Vec::new(),
+            interned: HashMap::new(),
+        }
+    }
+}
+
+/// The compiler machine responsible for turning a resolved AST into bytecode.
+pub struct Compiler {
+    /// The function being compiled, innermost last
+    functions: Vec<FunctionState>,
+    errors: Vec<CompileError>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler {
+            functions: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Main entry point for compiling. Returns the top level of the script as a function of no
+    /// arguments, or every error found. The statements must have been through the resolver.
+    pub fn compile(&mut self, statements: &[Stmt]) -> Result<Rc<ObjFunction>, Vec<CompileError>> {
+        self.functions
+            .push(FunctionState::new(FunctionKind::Script, "", 0));
+        for statement in statements {
+            self.statement(statement);
+        }
+        let end = statements.last().map_or(Span::default(), Stmt::span);
+        self.emit_return(end);
+        let state = self
+            .functions
+            .pop()
+            .expect("the script should be compiling");
+
+        if self.errors.is_empty() {
+            Ok(Rc::new(state.function))
+        } else {
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    // Statements
+
+    fn statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression(s) => {
+                self.expression(&s.expression);
+                self.emit(OpCode::Pop, s.span);
+            }
+            Stmt::Print(s) => {
+                self.expression(&s.expression);
+                self.emit(OpCode::Print, s.span);
+            }
+            Stmt::Var(s) => {
+                match &s.initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit(OpCode::Nil, s.span),
+                }
+                self.define_variable(&s.name);
+            }
+            Stmt::Block(s) => {
+                self.begin_scope();
+                for statement in &s.statements {
+                    self.statement(statement);
+                }
+                self.end_scope(s.span);
+            }
+            Stmt::If(s) => {
+                self.expression(&s.condition);
+                let then_jump = self.emit_jump(OpCode::JumpIfFalse, s.span);
+                self.emit(OpCode::Pop, s.span);
+                self.statement(&s.then_branch);
+                let else_jump = self.emit_jump(OpCode::Jump, s.span);
+                self.patch_jump(then_jump, s.span);
+                self.emit(OpCode::Pop, s.span);
+                if let Some(else_branch) = &s.else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(else_jump, s.span);
+            }
+            Stmt::While(s) => self.while_statement(s),
+            Stmt::ForIn(s) => self.for_in_statement(s),
+            Stmt::Break(s) => {
+                self.pop_loop_locals(s.span);
+                let jump = self.emit_jump(OpCode::Jump, s.span);
+                self.innermost_loop().breaks.push(jump);
+            }
+            Stmt::Continue(s) => {
+                self.pop_loop_locals(s.span);
+                match self.innermost_loop().continue_target {
+                    Some(target) => self.emit_loop(target, s.span),
+                    None => {
+                        let jump = self.emit_jump(OpCode::Jump, s.span);
+                        self.innermost_loop().continues.push(jump);
+                    }
+                }
+            }
+            Stmt::Function(s) => {
+                // [Note] A local function is in scope in its own body, so it can call itself
+                if self.current().scope_depth > 0 {
+                    self.add_local(&s.name);
+                    self.function(s, FunctionKind::Function);
+                } else {
+                    self.function(s, FunctionKind::Function);
+                    self.define_variable(&s.name);
+                }
+            }
+            Stmt::Return(s) => {
+                if self.current().kind == FunctionKind::Initializer {
+                    // [Note] The resolver only lets an initializer `return;`, which returns `this`
+                    self.emit(OpCode::GetLocal, s.span);
+                    self.emit_byte(0, s.span);
+                } else {
+                    match &s.value {
+                        Some(value) => self.expression(value),
+                        None => self.emit(OpCode::Nil, s.span),
+                    }
+                }
+                self.emit(OpCode::Return, s.span);
+            }
+            Stmt::Class(s) => self.class_declaration(s),
+        }
+    }
+
+    fn while_statement(&mut self, s: &While) {
+        let loop_start = self.chunk().code.len();
+        self.expression(&s.condition);
+        let exit_jump = self.emit_jump(OpCode::JumpIfFalse, s.span);
+        self.emit(OpCode::Pop, s.span);
+
+        let scope_depth = self.current().scope_depth;
+        self.current().loops.push(Loop {
+            scope_depth,
+            continue_target: s.increment.is_none().then_some(loop_start),
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        self.statement(&s.body);
+        let finished = self.current().loops.pop().expect("the loop should be open");
+
+        if let Some(increment) = &s.increment {
+            for jump in finished.continues {
+                self.patch_jump(jump, s.span);
+            }
+            self.expression(increment);
+            self.emit(OpCode::Pop, s.span);
+        }
+        self.emit_loop(loop_start, s.span);
+
+        self.patch_jump(exit_jump, s.span);
+        self.emit(OpCode::Pop, s.span);
+        for jump in finished.breaks {
+            self.patch_jump(jump, s.span);
+        }
+    }
+
+    /// `for (var name in iterable) body`
+    // [Note] The list of elements and the position reached in it are kept in two hidden locals,
+    // whose names cannot clash with a variable's. Each element gets a scope of its own, so a
+    // closure made in the body keeps the element it was made for.
+    fn for_in_statement(&mut self, s: &ForIn) {
+        self.expression(&s.iterable);
+        self.emit(OpCode::Elements, s.iterable.span());
+
+        self.begin_scope();
+        self.add_local(&Identifier {
+            name: String::from("(elements)"),
+            span: s.iterable.span(),
+        });
+        self.emit_constant(Value::Number(0.0), s.span);
+        self.add_local(&Identifier {
+            name: String::from("(position)"),
+            span: s.iterable.span(),
+        });
+        let slot = (self.current().locals.len() - 2) as u8;
+
+        let loop_start = self.chunk().code.len();
+        self.emit(OpCode::ForIn, s.span);
+        self.emit_byte(slot, s.span);
+        let exit_jump = self.chunk().code.len();
+        self.chunk().write_u16(u16::MAX, s.span);
+
+        let scope_depth = self.current().scope_depth;
+        self.current().loops.push(Loop {
+            scope_depth,
+            continue_target: Some(loop_start),
+            breaks: Vec::new(),
+            continues: Vec::new(),
+        });
+        self.begin_scope();
+        self.add_local(&s.name);
+        self.statement(&s.body);
+        self.end_scope(s.span);
+        let finished = self.current().loops.pop().expect("the loop should be open");
+        self.emit_loop(loop_start, s.span);
+
+        self.patch_jump(exit_jump, s.span);
+        for jump in finished.breaks {
+            self.patch_jump(jump, s.span);
+        }
+        self.end_scope(s.span);
+    }
+
+    /// Compile a function's body into a chunk of its own, and emit the closure that makes it a value.
+    fn function(&mut self, declaration: &Function, kind: FunctionKind) {
+        let span = declaration.span;
+        self.functions.push(FunctionState::new(
+            kind,
+            &declaration.name.name,
+            declaration.params.len(),
+        ));
+        self.current().function.declaration = Some(declaration.name.span);
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(param);
+        }
+        for statement in &declaration.body {
+            self.statement(statement);
+        }
+        self.emit_return(span);
+
+        let mut state = self
+            .functions
+            .pop()
+            .expect("the function should be compiling");
+        state.function.upvalue_count = state.upvalues.len();
+        let function = Value::Object(Object::Function(Rc::new(state.function)));
+        let constant = self.make_constant(function, span);
+        self.emit(OpCode::Closure, span);
+        self.chunk().write_u16(constant, span);
+        for upvalue in state.upvalues {
+            self.emit_byte(u8::from(upvalue.is_local), span);
+            self.emit_byte(upvalue.index, span);
+        }
+    }
+
+    fn class_declaration(&mut self, s: &Class) {
+        let name = self.name_constant(&s.name.name, s.name.span);
+        if self.current().scope_depth > 0 {
+            self.add_local(&s.name);
+        }
+        self.emit(OpCode::Class, s.name.span);
+        self.chunk().write_u16(name, s.name.span);
+        if self.current().scope_depth == 0 {
+            self.emit(OpCode::DefineGlobal, s.name.span);
+            self.chunk().write_u16(name, s.name.span);
+        }
+
+        // [Note] `super` is a local in a scope around the methods, which they capture like any other
+        if let Some(superclass) = &s.superclass {
+            self.variable(&superclass.name, superclass.span);
+            self.begin_scope();
+            self.add_local(&Identifier {
+                name: String::from("super"),
+                span: superclass.span,
+            });
+            self.variable(&s.name, s.name.span);
+            let superclass_name = self.name_constant(&superclass.name.name, superclass.span);
+            self.emit(OpCode::Inherit, superclass.span);
+            self.chunk().write_u16(superclass_name, superclass.span);
+        }
+
+        // The class stays on the stack while its methods are added to it
+        self.variable(&s.name, s.name.span);
+        for method in &s.methods {
+            let kind = if method.name.name == "init" {
+                FunctionKind::Initializer
+            } else {
+                FunctionKind::Method
+            };
+            self.function(method, kind);
+            let name = self.name_constant(&method.name.name, method.name.span);
+            self.emit(OpCode::Method, method.name.span);
+            self.chunk().write_u16(name, method.name.span);
+        }
+        self.emit(OpCode::Pop, s.span);
+
+        if s.superclass.is_some() {
+            self.end_scope(s.span);
+        }
+    }
+
+    // Expressions
+
+    fn expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Literal(e) => match &e.value {
+                LiteralValue::Nil => self.emit(OpCode::Nil, e.span),
+                LiteralValue::Bool(true) => self.emit(OpCode::True, e.span),
+                LiteralValue::Bool(false) => self.emit(OpCode::False, e.span),
+                value => self.emit_constant(Value::from(value), e.span),
+            },
+            Expr::Grouping(e) => self.expression(&e.expression),
+            Expr::Unary(e) => {
+                self.expression(&e.right);
+                let op = match e.operator {
+                    UnaryOp::Not => OpCode::Not,
+                    UnaryOp::Negate => OpCode::Negate,
+                    UnaryOp::BitNot => OpCode::BitNot,
+                };
+                self.emit_with_operands(op, e.operator_span, vec![e.right.span()]);
+            }
+            Expr::Binary(e) => {
+                self.expression(&e.left);
+                self.expression(&e.right);
+                let op = match e.operator {
+                    BinaryOp::Equal => OpCode::Equal,
+                    BinaryOp::NotEqual => OpCode::NotEqual,
+                    BinaryOp::Greater => OpCode::Greater,
+                    BinaryOp::GreaterEqual => OpCode::GreaterEqual,
+                    BinaryOp::Less => OpCode::Less,
+                    BinaryOp::LessEqual => OpCode::LessEqual,
+                    BinaryOp::Add => OpCode::Add,
+                    BinaryOp::Subtract => OpCode::Subtract,
+                    BinaryOp::Multiply => OpCode::Multiply,
+                    BinaryOp::Divide => OpCode::Divide,
+                    BinaryOp::BitAnd => OpCode::BitAnd,
+                    BinaryOp::BitOr => OpCode::BitOr,
+                    BinaryOp::BitXor => OpCode::BitXor,
+                    BinaryOp::ShiftLeft => OpCode::ShiftLeft,
+                    BinaryOp::ShiftRight => OpCode::ShiftRight,
+                };
+                // Bitwise errors can blame either operand, or the whole expression
+                self.emit_with_operands(
+                    op,
+                    e.operator_span,
+                    vec![e.left.span(), e.right.span(), e.span],
+                );
+            }
+            Expr::Logical(e) => {
+                // [Note] The left operand stays on the stack as the result if it decides it
+                self.expression(&e.left);
+                let end_jump = match e.operator {
+                    LogicalOp::And => self.emit_jump(OpCode::JumpIfFalse, e.operator_span),
+                    LogicalOp::Or => {
+                        let else_jump = self.emit_jump(OpCode::JumpIfFalse, e.operator_span);
+                        let end_jump = self.emit_jump(OpCode::Jump, e.operator_span);
+                        self.patch_jump(else_jump, e.operator_span);
+                        end_jump
+                    }
+                };
+                self.emit(OpCode::Pop, e.operator_span);
+                self.expression(&e.right);
+                self.patch_jump(end_jump, e.operator_span);
+            }
+            Expr::Variable(e) => self.variable(&e.name, e.name.span),
+            Expr::Assign(e) => {
+                self.expression(&e.value);
+                let (op, operand) = self.resolve(&e.name.name, e.name.span, true);
+                self.emit_variable(op, operand, e.name.span);
+            }
+            Expr::Call(e) => {
+                self.expression(&e.callee);
+                for argument in &e.arguments {
+                    self.expression(argument);
+                }
+                let spans = e.arguments.iter().map(Expr::span).collect();
+                self.emit_with_operands(OpCode::Call, e.span, spans);
+                // [Note] The parser allows at most MAX_ARGUMENTS (255) arguments
+                self.emit_byte(e.arguments.len() as u8, e.span);
+            }
+            Expr::Get(e) => {
+                self.expression(&e.object);
+                let name = self.name_constant(&e.name.name, e.name.span);
+                self.emit(OpCode::GetProperty, e.name.span);
+                self.chunk().write_u16(name, e.name.span);
+            }
+            Expr::Set(e) => {
+                self.expression(&e.object);
+                let name = self.name_constant(&e.name.name, e.name.span);
+                self.emit(OpCode::CheckInstance, e.name.span);
+                self.chunk().write_u16(name, e.name.span);
+                self.expression(&e.value);
+                self.emit(OpCode::SetProperty, e.name.span);
+                self.chunk().write_u16(name, e.name.span);
+            }
+            Expr::This(e) => self.variable(
+                &Identifier {
+                    name: String::from("this"),
+                    span: e.span,
+                },
+                e.span,
+            ),
+            Expr::Super(e) => {
+                let identifier = |name: &str| Identifier {
+                    name: String::from(name),
+                    span: e.span,
+                };
+                self.variable(&identifier("this"), e.span);
+                self.variable(&identifier("super"), e.span);
+                let name = self.name_constant(&e.method.name, e.method.span);
+                self.emit(OpCode::GetSuper, e.method.span);
+                self.chunk().write_u16(name, e.method.span);
+            }
+            Expr::List(e) => {
+                self.emit(OpCode::List, e.span);
+                for element in &e.elements {
+                    self.expression(element);
+                    self.emit(OpCode::Append, e.span);
+                }
+            }
+            Expr::Map(e) => {
+                self.emit(OpCode::Map, e.span);
+                for (key, value) in &e.entries {
+                    self.expression(key);
+                    self.expression(value);
+                    // Key errors point at the key
+                    self.emit(OpCode::Insert, key.span());
+                }
+            }
+            Expr::Index(e) => {
+                self.expression(&e.object);
+                self.expression(&e.index);
+                self.emit_with_operands(
+                    OpCode::GetIndex,
+                    e.span,
+                    vec![e.object.span(), e.index.span()],
+                );
+            }
+            Expr::SetIndex(e) => {
+                self.expression(&e.object);
+                self.expression(&e.index);
+                self.expression(&e.value);
+                self.emit_with_operands(
+                    OpCode::SetIndex,
+                    e.span,
+                    vec![e.object.span(), e.index.span()],
+                );
+            }
+        }
+    }
+
+    // Variables
+
+    /// Push the value of the variable `name`.
+    fn variable(&mut self, name: &Identifier, span: Span) {
+        let (op, operand) = self.resolve(&name.name, span, false);
+        self.emit_variable(op, operand, span);
+    }
+
+    /// Store the value on top of the stack in a new variable, declared where the compiler is.
+    /// A local just stays where it is on the stack, in the slot that is now its own.
+    fn define_variable(&mut self, name: &Identifier) {
+        if self.current().scope_depth > 0 {
+            self.add_local(name);
+        } else {
+            let constant = self.name_constant(&name.name, name.span);
+            self.emit(OpCode::DefineGlobal, name.span);
+            self.chunk().write_u16(constant, name.span);
+        }
+    }
+
+    /// The instruction that reads (or with `set`, writes) the variable `name`, and its operand.
+    fn resolve(&mut self, name: &str, span: Span, set: bool) -> (OpCode, u16) {
+        let current = self.functions.len() - 1;
+        if let Some(slot) = self.resolve_local(current, name) {
+            let op = if set {
+                OpCode::SetLocal
+            } else {
+                OpCode::GetLocal
+            };
+            return (op, u16::from(slot));
+        }
+        if let Some(index) = self.resolve_upvalue(current, name, span) {
+            let op = if set {
+                OpCode::SetUpvalue
+            } else {
+                OpCode::GetUpvalue
+            };
+            return (op, u16::from(index));
+        }
+        let op = if set {
+            OpCode::SetGlobal
+        } else {
+            OpCode::GetGlobal
+        };
+        (op, self.name_constant(name, span))
+    }
+
+    fn emit_variable(&mut self, op: OpCode, operand: u16, span: Span) {
+        self.emit(op, span);
+        match op {
+            OpCode::GetGlobal | OpCode::SetGlobal => self.chunk().write_u16(operand, span),
+            _ => self.emit_byte(operand as u8, span),
+        }
+    }
+
+    /// The slot of the local `name` in the function at `function`, if it has one in scope.
+    fn resolve_local(&self, function: usize, name: &str) -> Option<u8> {
+        let locals = &self.functions[function].locals;
+        // [Note] Searched from the innermost scope out, so a local shadows the ones before it
+        let slot = locals.iter().rposition(|local| local.name == name)?;
+        Some(slot as u8)
+    }
+
+    /// The upvalue of the function at `function` that captures `name`, if an enclosing function has it.
+    // [Note] Each function in between captures the variable too, so it can hand it on inwards
+    fn resolve_upvalue(&mut self, function: usize, name: &str, span: Span) -> Option<u8> {
+        if function == 0 {
+            return None;
+        }
+        let enclosing = function - 1;
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[usize::from(slot)].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true, span));
+        }
+        let index = self.resolve_upvalue(enclosing, name, span)?;
+        Some(self.add_upvalue(function, index, false, span))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool, span: Span) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+        if upvalues.len() == MAX_UPVALUES {
+            self.error(span, "Too many closure variables in function.");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    // Scopes
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    /// Leave a scope, discarding its locals. Captured ones are moved to the heap first.
+    fn end_scope(&mut self, span: Span) {
+        self.current().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let op = if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.current().locals.pop();
+            self.emit(op, span);
+        }
+    }
+
+    /// Discard the locals of the loop body before jumping out of it. The compiler still has them
+    /// in scope, as the code after the jump is still inside the body.
+    fn pop_loop_locals(&mut self, span: Span) {
+        let depth = self.innermost_loop().scope_depth;
+        let ops: Vec<OpCode> = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for op in ops {
+            self.emit(op, span);
+        }
+    }
+
+    fn add_local(&mut self, name: &Identifier) {
+        if self.current().locals.len() == MAX_LOCALS {
+            self.error(name.span, "Too many local variables in function.");
+            return;
+        }
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.name.clone(),
+            depth,
+            is_captured: false,
+        });
+    }
+
+    // Emitting code
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("a function should be compiling")
+    }
+
+    fn innermost_loop(&mut self) -> &mut Loop {
+        // [Note] The parser only accepts `break` and `continue` inside a loop
+        self.current()
+            .loops
+            .last_mut()
+            .expect("jump outside of a loop")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().function.chunk
+    }
+
+    fn emit(&mut self, op: OpCode, span: Span) {
+        self.chunk().write_op(op, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        self.chunk().write(byte, span);
+    }
+
+    /// Emit an instruction whose errors can point at `operands` as well as `span`.
+    fn emit_with_operands(&mut self, op: OpCode, span: Span, operands: Vec<Span>) {
+        let offset = self.chunk().code.len();
+        self.chunk().add_operand_spans(offset, operands);
+        self.emit(op, span);
+    }
+
+    fn emit_return(&mut self, span: Span) {
+        if self.current().kind == FunctionKind::Initializer {
+            self.emit(OpCode::GetLocal, span);
+            self.emit_byte(0, span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+    }
+
+    fn make_constant(&mut self, value: Value, span: Span) -> u16 {
+        let index = self.chunk().add_constant(value);
+        match u16::try_from(index) {
+            Ok(index) => index,
+            Err(_) => {
+                self.error(span, "Too many constants in one function.");
+                0
+            }
+        }
+    }
+
+    /// The constant holding a number or string, added the first time it is used in the chunk.
+    // [Note] Only distinct values count towards the limit of constants, so a script that is merely
+    // long can repeat a literal as often as it likes
+    fn interned_constant(&mut self, value: Value, span: Span) -> u16 {
+        let key = match &value {
+            Value::Number(number) => Interned::Number(number.to_bits()),
+            Value::String(string) => Interned::String(Rc::clone(string)),
+            _ => return self.make_constant(value, span),
+        };
+        if let Some(constant) = self.current().interned.get(&key) {
+            return *constant;
+        }
+        let constant = self.make_constant(value, span);
+        self.current().interned.insert(key, constant);
+        constant
+    }
+
+    fn emit_constant(&mut self, value: Value, span: Span) {
+        let constant = self.interned_constant(value, span);
+        self.emit(OpCode::Constant, span);
+        self.chunk().write_u16(constant, span);
+    }
+
+    /// The constant holding the name of a variable, property or class.
+    fn name_constant(&mut self, name: &str, span: Span) -> u16 {
+        self.interned_constant(Value::String(Rc::from(name)), span)
+    }
+
+    /// Emit a forward jump with a placeholder offset, returning where the offset is to patch it.
+    fn emit_jump(&mut self, op: OpCode, span: Span) -> usize {
+        self.emit(op, span);
+        self.chunk().write_u16(u16::MAX, span);
+        self.chunk().code.len() - 2
+    }
+
+    /// Make the jump whose offset is at `offset` land on the next instruction emitted.
+    fn patch_jump(&mut self, offset: usize, span: Span) {
+        // The jump is from the end of its offset
+        let distance = self.chunk().code.len() - offset - 2;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error(span, "Too much code to jump over.");
+            0
+        });
+        let [high, low] = distance.to_be_bytes();
+        self.chunk().code[offset] = high;
+        self.chunk().code[offset + 1] = low;
+    }
+
+    /// Emit a jump back to `start`.
+    fn emit_loop(&mut self, start: usize, span: Span) {
+        self.emit(OpCode::Loop, span);
+        // The jump is from the end of the instruction, past its own two byte offset
+        let distance = self.chunk().code.len() - start + 2;
+        let distance = u16::try_from(distance).unwrap_or_else(|_| {
+            self.error(span, "Loop body too large.");
+            0
+        });
+        self.chunk().write_u16(distance, span);
+    }
+
+    fn error(&mut self, span: Span, message: &str) {
+        self.errors.push(CompileError {
+            span,
+            message: String::from(message),
+        });
+    }
+}
+
+/// Tests for the compiler
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::parser::Parser;
+    use crate::modules::resolver::Resolver;
+    use crate::modules::tokenize::Tokenizer;
+
+    fn compile(source: &str) -> Result<Rc<ObjFunction>, Vec<CompileError>> {
+        let statements = Parser::new(Tokenizer::new(source))
+            .parse()
+            .expect("source should parse");
+        Resolver::new()
+            .resolve(&statements)
+            .expect("source should resolve");
+        Compiler::new().compile(&statements)
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        compile(source)
+            .expect_err("source should not compile")
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn globals_by_name_and_locals_by_slot() {
+        let script = compile("var a = 1; { var b = 2; var c = b; print a; }").unwrap();
+        let name = Value::String(Rc::from("a"));
+        // Slot 0 is the script itself, so b is in slot 1
+        let expected = vec![
+            OpCode::Constant as u8,
+            0,
+            0, // 1
+            OpCode::DefineGlobal as u8,
+            0,
+            1, // a
+            OpCode::Constant as u8,
+            0,
+            2, // 2
+            OpCode::GetLocal as u8,
+            1, // b
+            OpCode::GetGlobal as u8,
+            0,
+            1, // a
+            OpCode::Print as u8,
+            OpCode::Pop as u8, // c
+            OpCode::Pop as u8, // b
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(script.chunk.code, expected);
+        assert_eq!(script.chunk.constants[1], name);
+    }
+
+    #[test]
+    fn names_are_stored_once() {
+        let script = compile("var a = 1; a = a + a; print a;").unwrap();
+        assert_eq!(script.chunk.constants.len(), 2);
+    }
+
+    #[test]
+    fn literals_are_stored_once() {
+        let script = compile("print 1 + 1; print \"a\" + \"a\"; var a = 0; print a;").unwrap();
+        // A string literal shares its constant with a name spelled the same way
+        let constants: Vec<String> = script
+            .chunk
+            .constants
+            .iter()
+            .map(Value::to_string)
+            .collect();
+        assert_eq!(constants, vec!["1", "a", "0"]);
+
+        // Only distinct constants count towards the limit
+        let prints = "print 1; print \"one\";\n".repeat(u16::MAX as usize + 2);
+        assert!(compile(&prints).is_ok());
+    }
+
+    #[test]
+    fn functions_capture_upvalues() {
+        let script = compile("fun outer() { var x = 1; fun inner() { return x; } }").unwrap();
+        let Value::Object(Object::Function(outer)) = &script.chunk.constants[0] else {
+            panic!("outer should be a function constant");
+        };
+        assert_eq!(outer.name, "outer");
+        assert_eq!(outer.upvalue_count, 0);
+        let inner = outer
+            .chunk
+            .constants
+            .iter()
+            .find_map(|constant| match constant {
+                Value::Object(Object::Function(function)) => Some(function),
+                _ => None,
+            });
+        assert_eq!(inner.unwrap().upvalue_count, 1);
+        // inner captures outer's local in slot 1
+        let code = &outer.chunk.code;
+        let closure = code
+            .iter()
+            .position(|byte| *byte == OpCode::Closure as u8)
+            .unwrap();
+        assert_eq!(&code[closure + 3..closure + 5], &[1, 1]);
+    }
+
+    #[test]
+    fn too_many_locals() {
+        let declarations = |count: usize| -> String {
+            (0..count)
+                .map(|i| format!("var v{} = {};\n", i, i))
+                .collect()
+        };
+        // The function itself takes slot 0, so one less than the limit fits
+        let fits = format!("{{\n{}print v0; }}", declarations(MAX_LOCALS - 1));
+        assert!(compile(&fits).is_ok());
+        let source = format!("{{\n{}print v0; }}", declarations(MAX_LOCALS));
+        assert_eq!(
+            errors(&source),
+            vec!["[line 257, column 5] Error: Too many local variables in function."]
+        );
+    }
+
+    #[test]
+    fn too_many_constants() {
+        let prints: String = (0..=u16::MAX as usize + 1)
+            .map(|i| format!("print {};\n", i))
+            .collect();
+        assert_eq!(
+            errors(&prints),
+            vec!["[line 65537, column 7] Error: Too many constants in one function."]
+        );
+    }
+
+    #[test]
+    fn too_much_code_to_jump_over() {
+        let body = "x = x + 1;".repeat(7000);
+        let source = format!("var x = 0; if (true) {{ {} }}", body);
+        let errors = errors(&source);
+        assert_eq!(
+            errors[0],
+            "[line 1, column 12] Error: Too much code to jump over."
+        );
+        let source = format!("var x = 0; while (x < 1) {{ {} }}", body);
+        assert!(errors_contain(&source, "Loop body too large."));
+    }
+
+    fn errors_contain(source: &str, message: &str) -> bool {
+        errors(source).iter().any(|error| error.ends_with(message))
+    }
+}
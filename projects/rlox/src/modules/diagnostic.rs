@@ -0,0 +1,562 @@
+use crate::modules::compiler::CompileError;
+use crate::modules::interpreter::RuntimeError;
+use crate::modules::parser::ParseError;
+use crate::modules::resolver::{ResolveError, Warning};
+use crate::modules::tokenize::{LexError, LexErrorKind, Span};
+use std::fmt;
+use std::fmt::Write;
+// [Note] Every stage reports problems with its own error type (LexError, ParseError...), which knows
+// only what that stage knows. They all convert into a Diagnostic, the one shape the command line and
+// the REPL need to print them: as a single line, as a source snippet with carets, or as JSON.
+//
+// Codes, one range per stage:
+//   E001-E005  reading the script (not found, no permission, cannot open, not UTF-8, read failed)
+//   E101-E105  lexical errors, one per LexErrorKind
+//   E201       syntax errors
+//   E301       resolve errors          W301  unused local variable
+//   E401       runtime errors
+//   E501       bytecode limits (too many constants, locals or upvalues, jumps too long)
+
+/// Enum representing how serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    /// The name used in rendered output, e.g. "error[E201]".
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+
+    /// ANSI escape for the severity's color: bold red for errors, bold yellow for warnings.
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+/// ANSI escapes used by the renderer
+const BOLD: &str = "\x1b[1m";
+const BLUE: &str = "\x1b[1;34m";
+const RESET: &str = "\x1b[0m";
+
+/// Struct representing a secondary location that helps explain a diagnostic, e.g. an earlier declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Struct representing a problem to show the user, from any stage of rlox.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    /// Where the problem is. Problems reading the script have no location.
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    // Diagnostic constructors
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, code, message.into())
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, code, message.into())
+    }
+
+    fn new(severity: Severity, code: &'static str, message: String) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message,
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Set the primary location of the problem.
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    /// Add a secondary location, with a message saying what it has to do with the problem.
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Add a note printed after the source snippets.
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render for a person to read: a header, the source line of every span underlined, then the notes.
+    /// `file` names the script in the location line. `color` adds ANSI escapes for a terminal.
+    pub fn render(&self, source: &str, file: &str, color: bool) -> String {
+        let paint = |escape: &'static str| if color { escape } else { "" };
+        let reset = paint(RESET);
+        let mut out = String::new();
+
+        let _ = writeln!(
+            out,
+            "{}{}[{}]{}{}: {}{}",
+            paint(self.severity.color()),
+            self.severity.name(),
+            self.code,
+            reset,
+            paint(BOLD),
+            self.message,
+            reset
+        );
+
+        if let Some(span) = self.span {
+            let gutter = self.gutter();
+            let margin = format!("{}{:gutter$} |{}", paint(BLUE), "", reset);
+
+            let _ = writeln!(
+                out,
+                "{:gutter$}{}-->{} {}:{}:{}",
+                "",
+                paint(BLUE),
+                reset,
+                file,
+                span.line,
+                span.column
+            );
+            let _ = writeln!(out, "{}", margin);
+            let primary = (span, "", '^', self.severity.color());
+            let labels = self
+                .labels
+                .iter()
+                .map(|label| (label.span, label.message.as_str(), '-', BLUE));
+            let mut shown_line = None;
+            for (span, message, marker, escape) in std::iter::once(primary).chain(labels) {
+                let (text, indent, width) = snippet(source, span);
+                // A label on the line just shown goes straight under it
+                if shown_line != Some(span.line) {
+                    let _ = writeln!(
+                        out,
+                        "{}{:>gutter$} |{} {}",
+                        paint(BLUE),
+                        span.line,
+                        reset,
+                        text
+                    );
+                    shown_line = Some(span.line);
+                }
+                let underline = marker.to_string().repeat(width);
+                let _ = writeln!(
+                    out,
+                    "{} {}{}{}{}",
+                    margin,
+                    indent,
+                    paint(escape),
+                    format!("{} {}", underline, message).trim_end(),
+                    reset
+                );
+            }
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(
+                out,
+                "{} = {}note{}: {}",
+                " ".repeat(self.gutter()),
+                paint(BOLD),
+                reset,
+                note
+            );
+        }
+        out
+    }
+
+    /// Width of the line number gutter: as wide as the largest line number shown, so the '|'s line up.
+    fn gutter(&self) -> usize {
+        match self.span {
+            Some(span) => self
+                .labels
+                .iter()
+                .map(|label| label.span.line)
+                .fold(span.line, u32::max)
+                .to_string()
+                .len(),
+            None => 0,
+        }
+    }
+
+    /// Render as a single line of JSON, for editors and other tools.
+    pub fn to_json(&self, file: &str) -> String {
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "{{\"span\":{},\"message\":{}}}",
+                    span_json(label.span),
+                    json_string(&label.message)
+                )
+            })
+            .collect();
+        let notes: Vec<String> = self.notes.iter().map(|note| json_string(note)).collect();
+        format!(
+            "{{\"severity\":{},\"code\":{},\"message\":{},\"file\":{},\"span\":{},\"labels\":[{}],\"notes\":[{}]}}",
+            json_string(self.severity.name()),
+            json_string(self.code),
+            json_string(&self.message),
+            json_string(file),
+            self.span.map_or(String::from("null"), span_json),
+            labels.join(","),
+            notes.join(",")
+        )
+    }
+}
+
+/// The short, single line form, e.g. `[line 1, column 7] Error: Expected expression, found ';'.`
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(span) = self.span {
+            write!(f, "[line {}, column {}] ", span.line, span.column)?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "Error",
+            Severity::Warning => "Warning",
+        };
+        write!(f, "{}: {}", severity, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// The line `span` starts on, the whitespace that lines a marker up under the span, and how many
+/// markers underline it. A span running past the end of its line is underlined to the end of the line.
+fn snippet(source: &str, span: Span) -> (&str, String, usize) {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |i| start + i);
+    let end = span.end.clamp(start, line_end);
+
+    // Tabs are kept, so the markers line up however wide the terminal draws a tab
+    let indent = source[line_start..start]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let width = source[start..end].chars().count().max(1);
+    (
+        source[line_start..line_end].trim_end_matches('\r'),
+        indent,
+        width,
+    )
+}
+
+fn span_json(span: Span) -> String {
+    format!(
+        "{{\"line\":{},\"column\":{},\"start\":{},\"end\":{}}}",
+        span.line, span.column, span.start, span.end
+    )
+}
+
+/// Quote and escape a string for JSON.
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+// Conversions from each stage's errors
+
+impl From<&LexError> for Diagnostic {
+    fn from(error: &LexError) -> Self {
+        let code = match error.kind {
+            LexErrorKind::UnexpectedCharacter(_) => "E101",
+            LexErrorKind::UnterminatedString => "E102",
+            LexErrorKind::InvalidEscape(_) => "E103",
+            LexErrorKind::MalformedNumber(_) => "E104",
+            LexErrorKind::UnterminatedComment => "E105",
+        };
+        Diagnostic::error(code, error.kind.to_string()).with_span(error.span)
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic::error(
+            "E201",
+            format!("Expected {}, found {}.", error.expected, error.found),
+        )
+        .with_span(error.span)
+    }
+}
+
+impl From<&ResolveError> for Diagnostic {
+    fn from(error: &ResolveError) -> Self {
+        let diagnostic = Diagnostic::error("E301", error.message.clone()).with_span(error.span);
+        match &error.label {
+            Some(label) => diagnostic.with_label(label.span, label.message.clone()),
+            None => diagnostic,
+        }
+    }
+}
+
+impl From<&Warning> for Diagnostic {
+    fn from(warning: &Warning) -> Self {
+        Diagnostic::warning("W301", warning.message.clone()).with_span(warning.span)
+    }
+}
+
+impl From<&CompileError> for Diagnostic {
+    fn from(error: &CompileError) -> Self {
+        Diagnostic::error("E501", error.message.clone()).with_span(error.span)
+    }
+}
+
+impl From<&RuntimeError> for Diagnostic {
+    fn from(error: &RuntimeError) -> Self {
+        Diagnostic {
+            labels: error.labels.clone(),
+            notes: error.notes.clone(),
+            ..Diagnostic::error("E401", error.message.clone()).with_span(error.span)
+        }
+    }
+}
+
+/// Tests for rendering diagnostics
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::interpreter::tests::{interpret_on, Backend};
+    use crate::modules::parser::Parser;
+    use crate::modules::resolver::Resolver;
+    use crate::modules::tokenize::Tokenizer;
+    use std::io;
+
+    fn parse_error(source: &str) -> Diagnostic {
+        let errors = Parser::new(Tokenizer::new(source)).parse().unwrap_err();
+        Diagnostic::from(&errors[0])
+    }
+
+    fn resolve_error(source: &str) -> Diagnostic {
+        let statements = Parser::new(Tokenizer::new(source)).parse().unwrap();
+        let errors = Resolver::new().resolve(&statements).unwrap_err();
+        Diagnostic::from(&errors[0])
+    }
+
+    /// The runtime error `source` stops with, from the tree-walker and then from the VM.
+    fn runtime_errors(source: &str) -> (Diagnostic, Diagnostic) {
+        let error = |backend| {
+            let error = interpret_on(backend, source, Box::new(io::sink())).unwrap_err();
+            Diagnostic::from(&error)
+        };
+        (error(Backend::Tree), error(Backend::Vm))
+    }
+
+    #[test]
+    fn short_form_matches_the_stage_errors() {
+        let source = "print ;";
+        let errors = Parser::new(Tokenizer::new(source)).parse().unwrap_err();
+        assert_eq!(
+            Diagnostic::from(&errors[0]).to_string(),
+            errors[0].to_string()
+        );
+        assert_eq!(
+            Diagnostic::error("E001", "File 'x.lox' not found.").to_string(),
+            "Error: File 'x.lox' not found."
+        );
+    }
+
+    #[test]
+    fn render_underlines_the_span() {
+        let source = "var a = 1;\nprint a +;\n";
+        assert_eq!(
+            parse_error(source).render(source, "x.lox", false),
+            "error[E201]: Expected expression, found ';'.\n \
+             --> x.lox:2:10\n  \
+             |\n\
+             2 | print a +;\n  \
+             |          ^\n"
+        );
+    }
+
+    #[test]
+    fn render_labels_and_notes() {
+        let source = "{\n  var a = 1;\n  var a = 2;\n  print a;\n}";
+        let mut resolver = Resolver::new();
+        let errors = resolver
+            .resolve(&Parser::new(Tokenizer::new(source)).parse().unwrap())
+            .unwrap_err();
+        let diagnostic = Diagnostic::from(&errors[0]).with_note("Use assignment to change a.");
+        assert_eq!(
+            diagnostic.render(source, "x.lox", false),
+            "error[E301]: Already a variable with this name in this scope.\n \
+             --> x.lox:3:7\n  \
+             |\n\
+             3 |   var a = 2;\n  \
+             |       ^\n\
+             2 |   var a = 1;\n  \
+             |       - first declared here\n  \
+             = note: Use assignment to change a.\n"
+        );
+    }
+
+    #[test]
+    fn render_labels_on_the_same_line() {
+        let source = "{ var b = 1; var b = 2; print b; }";
+        let mut resolver = Resolver::new();
+        let errors = resolver
+            .resolve(&Parser::new(Tokenizer::new(source)).parse().unwrap())
+            .unwrap_err();
+        assert!(Diagnostic::from(&errors[0])
+            .render(source, "x.lox", false)
+            .ends_with(
+                "1 | { var b = 1; var b = 2; print b; }\n  \
+                 |                  ^\n  \
+                 |       - first declared here\n"
+            ));
+    }
+
+    #[test]
+    fn own_initializer_labels_the_declaration() {
+        let source = "{\n  var a = a;\n}";
+        assert_eq!(
+            resolve_error(source).render(source, "x.lox", false),
+            "error[E301]: Can't read local variable in its own initializer.\n \
+             --> x.lox:2:11\n  \
+             |\n\
+             2 |   var a = a;\n  \
+             |           ^\n  \
+             |       - declared here\n"
+        );
+    }
+
+    #[test]
+    fn arity_errors_label_the_declaration() {
+        let source = "fun add(a, b) {\n  return a + b;\n}\nadd(1);";
+        let (tree, vm) = runtime_errors(source);
+        assert_eq!(
+            tree.labels,
+            vec![Label {
+                span: Span::new(4, 7, 1, 5),
+                message: String::from("declared here"),
+            }]
+        );
+        assert_eq!(vm.labels, tree.labels);
+
+        // A class is called with its initializer's parameters
+        let source = "class P {\n  init(x) {}\n}\nP();";
+        let (tree, vm) = runtime_errors(source);
+        assert_eq!(tree.labels[0].span, Span::new(12, 16, 2, 3));
+        assert_eq!(vm.labels, tree.labels);
+
+        // Natives are not declared anywhere in the script
+        let (tree, vm) = runtime_errors("clock(1);");
+        assert!(tree.labels.is_empty() && vm.labels.is_empty());
+    }
+
+    #[test]
+    fn undefined_variables_suggest_a_similar_name() {
+        let source = "var count = 1;\nprint cuont;";
+        let (tree, vm) = runtime_errors(source);
+        assert_eq!(tree.notes, vec!["Did you mean 'count'?"]);
+        assert_eq!(vm.notes, tree.notes);
+        assert!(tree
+            .render(source, "x.lox", false)
+            .ends_with("  |       ^^^^^\n  = note: Did you mean 'count'?\n"));
+
+        let (tree, vm) = runtime_errors("var count = 1;\nnothing_like_it = 2;");
+        assert!(tree.notes.is_empty() && vm.notes.is_empty());
+    }
+
+    #[test]
+    fn render_without_a_span() {
+        let diagnostic = Diagnostic::error("E004", "File 'x.lox' is not a valid UTF-8 text file.");
+        assert_eq!(
+            diagnostic.render("", "x.lox", false),
+            "error[E004]: File 'x.lox' is not a valid UTF-8 text file.\n"
+        );
+    }
+
+    #[test]
+    fn render_long_spans_tabs_and_end_of_input() {
+        let source = "\tprint \"never\nclosed";
+        let diagnostic = Diagnostic::error("E102", "Unterminated string.").with_span(Span::new(
+            7,
+            source.len(),
+            1,
+            8,
+        ));
+        // Underlined to the end of the first line, under the tab
+        assert!(diagnostic
+            .render(source, "x.lox", false)
+            .ends_with("1 | \tprint \"never\n  | \t      ^^^^^^\n"));
+
+        let source = "print (1";
+        assert!(parse_error(source)
+            .render(source, "x.lox", false)
+            .ends_with("1 | print (1\n  |         ^\n"));
+    }
+
+    #[test]
+    fn render_with_color() {
+        let rendered = Diagnostic::warning("W301", "Unused.").render("", "x.lox", true);
+        assert_eq!(
+            rendered,
+            "\x1b[1;33mwarning[W301]\x1b[0m\x1b[1m: Unused.\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let diagnostic = Diagnostic::error("E301", "Say \"hi\"\n")
+            .with_span(Span::new(4, 5, 1, 5))
+            .with_label(Span::new(0, 1, 1, 1), "here")
+            .with_note("a\\b");
+        assert_eq!(
+            diagnostic.to_json("dir/x.lox"),
+            "{\"severity\":\"error\",\"code\":\"E301\",\"message\":\"Say \\\"hi\\\"\\n\",\
+             \"file\":\"dir/x.lox\",\"span\":{\"line\":1,\"column\":5,\"start\":4,\"end\":5},\
+             \"labels\":[{\"span\":{\"line\":1,\"column\":1,\"start\":0,\"end\":1},\"message\":\"here\"}],\
+             \"notes\":[\"a\\\\b\"]}"
+        );
+        assert_eq!(
+            Diagnostic::warning("W301", "x").to_json("-"),
+            "{\"severity\":\"warning\",\"code\":\"W301\",\"message\":\"x\",\"file\":\"-\",\
+             \"span\":null,\"labels\":[],\"notes\":[]}"
+        );
+    }
+}
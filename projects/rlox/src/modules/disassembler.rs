@@ -0,0 +1,225 @@
+use crate::modules::chunk::{Chunk, OpCode};
+use crate::modules::interpreter::Value;
+use crate::modules::object::{ObjFunction, Object};
+use std::fmt::Write;
+// Disassembling Chunks: Chapter 14 of "Crafting Interpreters"
+// [Note] Prints bytecode in the same layout as clox's debug.c, one instruction per line:
+//
+//   0000    1 OP_CONSTANT         0 '1.2'
+//   0003    | OP_PRINT
+//
+// the offset, the source line (`|` when it is the same as the byte before), the opcode and its
+// decoded operands. Jumps show their offset and where they land, `->` pointing at the target.
+
+/// The disassembly of a compiled function, followed by every function declared inside it.
+pub fn disassemble(function: &ObjFunction) -> String {
+    let mut out = String::new();
+    disassemble_function(function, &mut out);
+    out
+}
+
+fn disassemble_function(function: &ObjFunction, out: &mut String) {
+    let chunk = &function.chunk;
+    writeln!(out, "== {} ==", function).unwrap();
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, out);
+    }
+
+    // [Note] A function's body is a constant of the chunk that declares it, so nested functions
+    // come out depth first, in the order they are declared
+    for constant in &chunk.constants {
+        if let Value::Object(Object::Function(nested)) = constant {
+            out.push('\n');
+            disassemble_function(nested, out);
+        }
+    }
+}
+
+/// Append the instruction at `offset` to `out`, returning the offset of the next instruction.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    write!(out, "{:04} ", offset).unwrap();
+    let line = chunk.span(offset).line;
+    if offset > 0 && line == chunk.span(offset - 1).line {
+        out.push_str("   | ");
+    } else {
+        write!(out, "{:4} ", line).unwrap();
+    }
+
+    let Some(op) = OpCode::from_byte(chunk.code[offset]) else {
+        writeln!(out, "Unknown opcode {}", chunk.code[offset]).unwrap();
+        return offset + 1;
+    };
+    let name = name(op);
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::CheckInstance
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Inherit
+        | OpCode::Method => {
+            let constant = chunk.read_u16(offset + 1);
+            let value = &chunk.constants[usize::from(constant)];
+            writeln!(out, "{:<16} {:4} '{}'", name, constant, value).unwrap();
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            writeln!(out, "{:<16} {:4}", name, chunk.code[offset + 1]).unwrap();
+            offset + 2
+        }
+        OpCode::Jump | OpCode::JumpIfFalse => {
+            let target = offset + 3 + usize::from(chunk.read_u16(offset + 1));
+            writeln!(out, "{:<16} {:4} -> {}", name, offset, target).unwrap();
+            offset + 3
+        }
+        OpCode::Loop => {
+            let target = offset + 3 - usize::from(chunk.read_u16(offset + 1));
+            writeln!(out, "{:<16} {:4} -> {}", name, offset, target).unwrap();
+            offset + 3
+        }
+        OpCode::ForIn => {
+            // The slot of the list, and where to go when it runs out
+            let slot = chunk.code[offset + 1];
+            let target = offset + 4 + usize::from(chunk.read_u16(offset + 2));
+            writeln!(out, "{:<16} {:4} -> {}", name, slot, target).unwrap();
+            offset + 4
+        }
+        OpCode::Closure => {
+            let constant = chunk.read_u16(offset + 1);
+            let value = &chunk.constants[usize::from(constant)];
+            writeln!(out, "{:<16} {:4} {}", name, constant, value).unwrap();
+            let Value::Object(Object::Function(function)) = value else {
+                return offset + 3;
+            };
+            // Each upvalue: a local of the enclosing function, or one of its upvalues
+            let mut next = offset + 3;
+            for _ in 0..function.upvalue_count {
+                let kind = if chunk.code[next] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                let index = chunk.code[next + 1];
+                writeln!(
+                    out,
+                    "{:04}    |                     {} {}",
+                    next, kind, index
+                )
+                .unwrap();
+                next += 2;
+            }
+            next
+        }
+        _ => {
+            writeln!(out, "{}", name).unwrap();
+            offset + 1
+        }
+    }
+}
+
+/// The name clox gives an opcode, e.g. OP_JUMP_IF_FALSE for JumpIfFalse.
+fn name(op: OpCode) -> String {
+    let mut name = String::from("OP");
+    for c in format!("{:?}", op).chars() {
+        if c.is_ascii_uppercase() {
+            name.push('_');
+        }
+        name.push(c.to_ascii_uppercase());
+    }
+    name
+}
+
+/// Tests for the disassembler
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::compiler::Compiler;
+    use crate::modules::parser::Parser;
+    use crate::modules::tokenize::Tokenizer;
+
+    fn disassembly(source: &str) -> String {
+        let statements = Parser::new(Tokenizer::new(source)).parse().unwrap();
+        disassemble(&Compiler::new().compile(&statements).unwrap())
+    }
+
+    #[test]
+    fn opcode_names() {
+        assert_eq!(name(OpCode::Constant), "OP_CONSTANT");
+        assert_eq!(name(OpCode::JumpIfFalse), "OP_JUMP_IF_FALSE");
+        assert_eq!(name(OpCode::ForIn), "OP_FOR_IN");
+    }
+
+    #[test]
+    fn constants_and_repeated_lines() {
+        assert_eq!(
+            disassembly("var greeting = \"hi\";\nprint greeting;"),
+            "\
+== <script> ==
+0000    1 OP_CONSTANT         0 'hi'
+0003    | OP_DEFINE_GLOBAL    1 'greeting'
+0006    2 OP_GET_GLOBAL       1 'greeting'
+0009    | OP_PRINT
+0010    | OP_NIL
+0011    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    fn jump_targets() {
+        assert_eq!(
+            disassembly("while (false) print 1;"),
+            "\
+== <script> ==
+0000    1 OP_FALSE
+0001    | OP_JUMP_IF_FALSE    1 -> 12
+0004    | OP_POP
+0005    | OP_CONSTANT         0 '1'
+0008    | OP_PRINT
+0009    | OP_LOOP             9 -> 0
+0012    | OP_POP
+0013    | OP_NIL
+0014    | OP_RETURN
+"
+        );
+    }
+
+    #[test]
+    fn nested_functions_and_upvalues() {
+        let source = "fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n  return inner;\n}";
+        assert_eq!(
+            disassembly(source),
+            "\
+== <script> ==
+0000    1 OP_CLOSURE          0 <fn outer>
+0003    | OP_DEFINE_GLOBAL    1 'outer'
+0006    | OP_NIL
+0007    | OP_RETURN
+
+== <fn outer> ==
+0000    2 OP_CONSTANT         0 '1'
+0003    3 OP_CLOSURE          1 <fn inner>
+0006    |                     local 1
+0008    4 OP_GET_LOCAL        2
+0010    | OP_RETURN
+0011    1 OP_NIL
+0012    | OP_RETURN
+
+== <fn inner> ==
+0000    3 OP_GET_UPVALUE      0
+0002    | OP_RETURN
+0003    | OP_NIL
+0004    | OP_RETURN
+"
+        );
+    }
+}
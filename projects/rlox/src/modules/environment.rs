@@ -0,0 +1,189 @@
+use crate::modules::ast::Identifier;
+use crate::modules::interpreter::{RuntimeError, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+// Statements and State: Chapter 8 of "Crafting Interpreters"
+// [Note] An environment holds the variables of one scope. Every block gets a fresh environment
+// that points back at the one it is nested in, so a lookup walks outwards until it finds the name.
+// The chain ends at the global environment.
+
+/// Struct representing the variables declared in one scope.
+// [Note] Environments are shared, and mutated through the sharing (a closure and the block that
+// created it see the same variables), hence Rc<RefCell<...>> rather than plain ownership.
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    /// Create the outermost, global environment.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an environment for a scope nested inside `enclosing`.
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Self {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    /// Declare a variable in this scope.
+    // [Note] Declaring a name that already exists replaces it. At the top level this is handy in a
+    // REPL, and in a nested scope the new variable shadows, not changes, any outer one.
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(String::from(name), value);
+    }
+
+    /// Read a variable, looking outwards from this scope.
+    pub fn get(&self, name: &Identifier) -> Result<Value, RuntimeError> {
+        match (self.values.get(&name.name), &self.enclosing) {
+            (Some(value), _) => Ok(value.clone()),
+            (None, Some(enclosing)) => enclosing.borrow().get(name),
+            (None, None) => Err(self.undefined(name)),
+        }
+    }
+
+    /// Change the value of an existing variable, looking outwards from this scope.
+    /// Unlike define(), assigning is not allowed to create a new variable.
+    pub fn assign(&mut self, name: &Identifier, value: Value) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(&name.name) {
+            *slot = value;
+            return Ok(());
+        }
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(self.undefined(name)),
+        }
+    }
+
+    /// Read a variable the resolver found `depth` scopes out from this one.
+    pub fn get_at(&self, depth: usize, name: &Identifier) -> Result<Value, RuntimeError> {
+        if depth == 0 {
+            self.values
+                .get(&name.name)
+                .cloned()
+                .ok_or_else(|| self.undefined(name))
+        } else {
+            self.ancestor(depth).borrow().get_at(0, name)
+        }
+    }
+
+    /// Change a variable the resolver found `depth` scopes out from this one.
+    pub fn assign_at(
+        &mut self,
+        depth: usize,
+        name: &Identifier,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        if depth == 0 {
+            match self.values.get_mut(&name.name) {
+                Some(slot) => {
+                    *slot = value;
+                    Ok(())
+                }
+                None => Err(self.undefined(name)),
+            }
+        } else {
+            self.ancestor(depth).borrow_mut().assign_at(0, name, value)
+        }
+    }
+
+    /// The environment `depth` scopes out from this one, for depth > 0.
+    fn ancestor(&self, depth: usize) -> Rc<RefCell<Environment>> {
+        let mut environment = Rc::clone(
+            self.enclosing
+                .as_ref()
+                .expect("resolved depth should be within the scope chain"),
+        );
+        for _ in 1..depth {
+            let enclosing = Rc::clone(
+                environment
+                    .borrow()
+                    .enclosing
+                    .as_ref()
+                    .expect("resolved depth should be within the scope chain"),
+            );
+            environment = enclosing;
+        }
+        environment
+    }
+
+    /// The error for a name no scope has, suggesting a similar one from this scope.
+    fn undefined(&self, name: &Identifier) -> RuntimeError {
+        let names = self.values.keys().map(String::as_str);
+        RuntimeError::undefined_variable(name.span, &name.name, names)
+    }
+}
+
+/// Tests for environments
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::tokenize::Span;
+
+    fn identifier(name: &str) -> Identifier {
+        Identifier {
+            name: String::from(name),
+            span: Span::new(0, name.len(), 1, 1),
+        }
+    }
+
+    #[test]
+    fn lookups_walk_outwards() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("a", Value::Number(1.0));
+        globals.borrow_mut().define("b", Value::Number(2.0));
+
+        let mut inner = Environment::with_enclosing(Rc::clone(&globals));
+        inner.define("a", Value::Bool(true));
+        inner.assign(&identifier("b"), Value::Nil).unwrap();
+
+        assert_eq!(inner.get(&identifier("a")), Ok(Value::Bool(true)));
+        assert_eq!(inner.get(&identifier("b")), Ok(Value::Nil));
+        assert_eq!(
+            globals.borrow().get(&identifier("a")),
+            Ok(Value::Number(1.0))
+        );
+        assert_eq!(globals.borrow().get(&identifier("b")), Ok(Value::Nil));
+    }
+
+    #[test]
+    fn resolved_lookups_skip_straight_to_the_scope() {
+        let globals = Rc::new(RefCell::new(Environment::new()));
+        globals.borrow_mut().define("a", Value::Number(1.0));
+        let middle = Rc::new(RefCell::new(Environment::with_enclosing(Rc::clone(
+            &globals,
+        ))));
+        middle.borrow_mut().define("a", Value::Number(2.0));
+        let mut inner = Environment::with_enclosing(Rc::clone(&middle));
+        inner.define("a", Value::Number(3.0));
+
+        assert_eq!(inner.get_at(0, &identifier("a")), Ok(Value::Number(3.0)));
+        assert_eq!(inner.get_at(2, &identifier("a")), Ok(Value::Number(1.0)));
+        inner.assign_at(1, &identifier("a"), Value::Nil).unwrap();
+        assert_eq!(middle.borrow().get(&identifier("a")), Ok(Value::Nil));
+        assert_eq!(inner.get(&identifier("a")), Ok(Value::Number(3.0)));
+    }
+
+    #[test]
+    fn undefined_variables() {
+        let mut environment = Environment::new();
+        let error = "Undefined variable 'x'.";
+
+        assert_eq!(
+            environment.get(&identifier("x")).unwrap_err().message,
+            error
+        );
+        assert_eq!(
+            environment
+                .assign(&identifier("x"), Value::Nil)
+                .unwrap_err()
+                .message,
+            error
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use crate::modules::ast::{Call, Function, Identifier};
+use crate::modules::class::Instance;
+use crate::modules::environment::Environment;
+use crate::modules::interpreter::{Callable, Interpreter, RuntimeError, Value};
+use crate::modules::tokenize::Span;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+// Functions: Chapter 10 of "Crafting Interpreters"
+
+/// Struct representing a function declared in Lox code, as a runtime value.
+// [Note] The closure is the environment the declaration ran in. Calls run the body in a new scope
+// nested inside it (not inside the caller's scope), which is what lets a function see the variables
+// around its declaration even after the code that declared them has returned.
+#[derive(Debug)]
+pub struct LoxFunction {
+    declaration: Rc<Function>,
+    closure: Rc<RefCell<Environment>>,
+    /// Whether this is a class's `init` method, which always returns the instance
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<Function>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> Self {
+        LoxFunction {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Make a copy of a method with `this` bound to `instance`.
+    // [Note] `this` lives in a scope of its own between the method's closure and its body,
+    // matching the scope the resolver put around the class's methods
+    pub fn bind(&self, instance: Rc<RefCell<Instance>>) -> LoxFunction {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        environment.define("this", Value::Instance(instance));
+        LoxFunction::new(
+            Rc::clone(&self.declaration),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+        )
+    }
+}
+
+impl fmt::Display for LoxFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<fn {}>", self.declaration.name.name)
+    }
+}
+
+impl Callable for LoxFunction {
+    fn name(&self) -> &str {
+        &self.declaration.name.name
+    }
+
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn declaration(&self) -> Option<Span> {
+        Some(self.declaration.name.span)
+    }
+
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        _call: &Call,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(&param.name, argument);
+        }
+        let result = interpreter.execute_body(&self.declaration.body, environment)?;
+
+        if self.is_initializer {
+            // `init()` returns the instance, even when called directly or left with `return;`
+            let this = Identifier {
+                name: String::from("this"),
+                span: self.declaration.name.span,
+            };
+            return self.closure.borrow().get_at(0, &this);
+        }
+        Ok(result)
+    }
+}
@@ -0,0 +1,1421 @@
+use crate::modules::ast::*;
+use crate::modules::bitwise::{self, Operand};
+use crate::modules::class::{Instance, LoxClass};
+use crate::modules::collection::{self, Elements, LoxList, LoxMap, Part};
+use crate::modules::diagnostic::Label;
+use crate::modules::environment::Environment;
+use crate::modules::function::LoxFunction;
+use crate::modules::native::{self, NativeFunction};
+use crate::modules::object::Object;
+use crate::modules::resolver::Locals;
+use crate::modules::tokenize::Span;
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
+// Evaluating Expressions, Statements, Functions and Classes: Chapters 7, 8, 10 and 12 of "Crafting Interpreters"
+// [Note] A tree-walking interpreter: it runs the program by visiting the AST nodes directly,
+// recursively evaluating every sub-expression before the node that uses it.
+
+/// Enum representing a Lox value at runtime. Lox is dynamically typed, so any variable can hold any of these.
+// [Note] Strings, callables, instances and collections are reference counted, so copying a value
+// around is always cheap.
+#[derive(Debug, Clone)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    String(Rc<str>),
+    Callable(Rc<dyn Callable>),
+    Instance(Rc<RefCell<Instance>>),
+    List(LoxList),
+    Map(Rc<RefCell<LoxMap>>),
+    /// A function, closure, class, instance or bound method of the bytecode VM
+    Object(Object),
+}
+
+/// Trait for the values that can be called with `(...)`. Display is how the value prints.
+pub trait Callable: fmt::Debug + fmt::Display {
+    /// The name the callable was declared with.
+    fn name(&self) -> &str;
+
+    /// The number of arguments the callable must be called with.
+    fn arity(&self) -> usize;
+
+    /// The kind of callable, as used in error messages.
+    fn type_name(&self) -> &'static str {
+        "function"
+    }
+
+    /// Where the parameters the callable takes are declared, for errors to point at. Natives have none.
+    fn declaration(&self) -> Option<Span> {
+        None
+    }
+
+    /// The callable as a class, if it is one.
+    fn into_class(self: Rc<Self>) -> Option<Rc<LoxClass>> {
+        None
+    }
+
+    /// The callable as a native function, if it is one. The VM calls natives directly.
+    fn as_native(&self) -> Option<&NativeFunction> {
+        None
+    }
+
+    /// Run the callable. The arguments have already been checked against arity().
+    /// `call` is the call expression, for errors to point at.
+    // [Note] `self: Rc<Self>` lets a callable hand out references to itself, the way a class
+    // gives every instance it makes a reference back to the class
+    fn call(
+        self: Rc<Self>,
+        interpreter: &mut Interpreter,
+        call: &Call,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError>;
+}
+
+impl Value {
+    /// Lox's notion of truth: `false` and `nil` are falsey, everything else is truthy.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    /// The value as a class, if it is one.
+    pub fn as_class(&self) -> Option<Rc<LoxClass>> {
+        match self {
+            Value::Callable(callable) => Rc::clone(callable).into_class(),
+            _ => None,
+        }
+    }
+
+    /// The name of the value's type, as used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Callable(callable) => callable.type_name(),
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Object(object) => object.type_name(),
+        }
+    }
+}
+
+// [Note] Lox equality never fails: values of different types are simply not equal.
+// Objects are equal only to themselves, so callables, instances and collections compare by identity.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Callable(a), Value::Callable(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+            (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+            (Value::Object(a), Value::Object(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl From<&LiteralValue> for Value {
+    fn from(literal: &LiteralValue) -> Self {
+        match literal {
+            LiteralValue::Nil => Value::Nil,
+            LiteralValue::Bool(value) => Value::Bool(*value),
+            LiteralValue::Number(value) => Value::Number(*value),
+            LiteralValue::String(value) => Value::String(Rc::from(value.as_str())),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", format_number(*value)),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Callable(callable) => write!(f, "{}", callable),
+            Value::Instance(instance) => write!(f, "{}", instance.borrow()),
+            Value::List(list) => collection::fmt_list(list, f),
+            Value::Map(map) => collection::fmt_map(map, f),
+            Value::Object(object) => write!(f, "{}", object),
+        }
+    }
+}
+
+/// Struct representing an error raised while the program runs, at the code that caused it.
+#[derive(Debug, PartialEq)]
+pub struct RuntimeError {
+    pub span: Span,
+    pub message: String,
+    /// Set when the program called `exit(code)`: not a mistake to report, just the way out
+    /// through every call in progress
+    pub exit_code: Option<i32>,
+    /// Other code that explains the error, e.g. the declaration of a function called wrongly
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl RuntimeError {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        RuntimeError {
+            span,
+            message: message.into(),
+            exit_code: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    /// Stop the program with the exit status `code`.
+    pub fn exit(span: Span, code: i32) -> Self {
+        RuntimeError {
+            exit_code: Some(code),
+            ..Self::new(span, format!("Exited with status {}.", code))
+        }
+    }
+
+    /// A call with the wrong number of arguments, pointing at the callee's parameters if it has some.
+    pub fn arity(span: Span, arity: usize, count: usize, declaration: Option<Span>) -> Self {
+        let error = Self::new(
+            span,
+            format!("Expected {} arguments but got {}.", arity, count),
+        );
+        match declaration {
+            Some(declaration) => error.with_label(declaration, "declared here"),
+            None => error,
+        }
+    }
+
+    /// A variable that is not declared anywhere. `names` are the globals, and the one closest to
+    /// `name`, if any is close enough to be a typo, is suggested in a note.
+    pub fn undefined_variable<'a>(
+        span: Span,
+        name: &str,
+        names: impl IntoIterator<Item = &'a str>,
+    ) -> Self {
+        let error = Self::new(span, format!("Undefined variable '{}'.", name));
+        match similar_name(name, names) {
+            Some(similar) => error.with_note(format!("Did you mean '{}'?", similar)),
+            None => error,
+        }
+    }
+
+    /// Point at other code that explains the error.
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    /// Add a note printed after the source snippets.
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+}
+
+/// The name in `names` fewest edits away from `name`, if it is within one edit per three characters,
+/// rounding up.
+// [Note] Ties go to the name first in alphabetical order, so the note does not depend on the order
+// of a HashMap
+fn similar_name<'a>(name: &str, names: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let limit = name.chars().count().div_ceil(3);
+    names
+        .into_iter()
+        .map(|candidate| (edit_distance(name, candidate), candidate))
+        .filter(|&(distance, _)| distance > 0 && distance <= limit)
+        .min()
+        .map(|(_, candidate)| candidate)
+}
+
+/// The Levenshtein distance between two strings: the fewest characters to insert, delete or replace
+/// to turn one into the other.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    // Distances from the prefix of `a` seen so far to every prefix of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &b_char) in b.iter().enumerate() {
+            let replace = diagonal + usize::from(a_char != b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = replace.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, column {}] Error: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// How deeply calls can nest before the program is stopped with a stack overflow error.
+// [Note] Every Lox call recurses through several Rust frames, so without a limit runaway recursion
+// in a Lox program would overflow (and abort) the interpreter's own stack instead
+pub const MAX_CALL_DEPTH: usize = 1000;
+
+/// Enum representing why a statement stopped before reaching its end.
+// [Note] Jumping out of nested statements is done by returning Err all the way up to the statement
+// that handles the jump, the same way a runtime error travels up to interpret().
+#[derive(Debug)]
+enum Unwind {
+    Error(RuntimeError),
+    Break,
+    Continue,
+    Return(Value),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+/// Struct representing the interpreter, and the state a program keeps while it runs.
+pub struct Interpreter {
+    // [Note] `print` writes here rather than straight to stdout, so tests can capture the output
+    out: Box<dyn Write>,
+    /// The outermost scope, where top-level declarations go
+    globals: Rc<RefCell<Environment>>,
+    /// The innermost scope of the code currently running
+    environment: Rc<RefCell<Environment>>,
+    /// The scope depth of every local variable use, from the resolver
+    locals: Locals,
+    /// How many calls are in progress
+    call_depth: usize,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interpreter {
+    /// Create an interpreter that prints to stdout.
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Create an interpreter that prints to `out`.
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let mut environment = Environment::new();
+        for native in native::library() {
+            let name = native.name().to_owned();
+            environment.define(&name, Value::Callable(Rc::new(native)));
+        }
+        let globals = Rc::new(RefCell::new(environment));
+        Interpreter {
+            out,
+            environment: Rc::clone(&globals),
+            globals,
+            locals: Locals::new(),
+            call_depth: 0,
+        }
+    }
+
+    /// Take in the resolver's results for statements about to be interpreted.
+    // [Note] Adds to, rather than replaces, what is known: functions declared by earlier statements
+    // (earlier REPL lines, say) still need their depths when they are called later
+    pub fn resolve(&mut self, locals: Locals) {
+        self.locals.extend(locals);
+    }
+
+    /// Main entry point for evaluation. Runs the statements in order, stopping at the first runtime error.
+    /// The statements must have been through the resolver, and its results passed to resolve().
+    pub fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(()) => {}
+                Err(Unwind::Error(error)) => return Err(error),
+                // [Note] The parser only accepts `break` and `continue` inside a loop
+                Err(Unwind::Break | Unwind::Continue) => unreachable!("jump outside of a loop"),
+                // [Note] The resolver rejects `return` outside of a function
+                Err(Unwind::Return(_)) => unreachable!("return outside of a function"),
+            }
+        }
+        Ok(())
+    }
+
+    // Statements
+
+    fn execute(&mut self, statement: &Stmt) -> Result<(), Unwind> {
+        match statement {
+            Stmt::Expression(s) => {
+                self.evaluate(&s.expression)?;
+                Ok(())
+            }
+            Stmt::Print(s) => {
+                let value = self.evaluate(&s.expression)?;
+                writeln!(self.out, "{}", value)
+                    .map_err(|error| RuntimeError::new(s.span, error.to_string()).into())
+            }
+            Stmt::Var(s) => {
+                let value = match &s.initializer {
+                    Some(initializer) => self.evaluate(initializer)?,
+                    None => Value::Nil,
+                };
+                self.environment.borrow_mut().define(&s.name.name, value);
+                Ok(())
+            }
+            Stmt::Block(s) => {
+                let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+                self.execute_block(&s.statements, environment)
+            }
+            Stmt::If(s) => {
+                if self.evaluate(&s.condition)?.is_truthy() {
+                    self.execute(&s.then_branch)
+                } else if let Some(else_branch) = &s.else_branch {
+                    self.execute(else_branch)
+                } else {
+                    Ok(())
+                }
+            }
+            Stmt::While(s) => {
+                while self.evaluate(&s.condition)?.is_truthy() {
+                    match self.execute(&s.body) {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(error) => return Err(error),
+                    }
+                    if let Some(increment) = &s.increment {
+                        self.evaluate(increment)?;
+                    }
+                }
+                Ok(())
+            }
+            Stmt::ForIn(s) => {
+                let iterable = self.evaluate(&s.iterable)?;
+                let elements = Elements::new(&iterable)
+                    .map_err(|message| RuntimeError::new(s.iterable.span(), message))?;
+                for element in elements {
+                    // A fresh scope for every element, so closures made in the body keep theirs
+                    let mut environment = Environment::with_enclosing(Rc::clone(&self.environment));
+                    environment.define(&s.name.name, element);
+                    match self.execute_block(std::slice::from_ref(&*s.body), environment) {
+                        Ok(()) | Err(Unwind::Continue) => {}
+                        Err(Unwind::Break) => break,
+                        Err(error) => return Err(error),
+                    }
+                }
+                Ok(())
+            }
+            Stmt::Break(_) => Err(Unwind::Break),
+            Stmt::Continue(_) => Err(Unwind::Continue),
+            Stmt::Function(s) => {
+                let function = LoxFunction::new(Rc::clone(s), Rc::clone(&self.environment), false);
+                self.environment
+                    .borrow_mut()
+                    .define(&s.name.name, Value::Callable(Rc::new(function)));
+                Ok(())
+            }
+            Stmt::Return(s) => {
+                let value = match &s.value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                Err(Unwind::Return(value))
+            }
+            Stmt::Class(s) => {
+                let superclass = match &s.superclass {
+                    Some(superclass) => {
+                        let value = self.look_up_variable(superclass.id, &superclass.name)?;
+                        let Some(class) = value.as_class() else {
+                            return Err(RuntimeError::new(
+                                superclass.span,
+                                format!(
+                                    "Superclass must be a class, '{}' is a {}.",
+                                    superclass.name.name,
+                                    value.type_name()
+                                ),
+                            )
+                            .into());
+                        };
+                        Some(class)
+                    }
+                    None => None,
+                };
+
+                // Methods of a subclass close over a scope holding `super`, like the resolver expects
+                let enclosing = Rc::clone(&self.environment);
+                if let Some(superclass) = &superclass {
+                    let mut environment = Environment::with_enclosing(Rc::clone(&enclosing));
+                    environment.define(
+                        "super",
+                        Value::Callable(Rc::clone(superclass) as Rc<dyn Callable>),
+                    );
+                    self.environment = Rc::new(RefCell::new(environment));
+                }
+
+                let methods = s
+                    .methods
+                    .iter()
+                    .map(|method| {
+                        let function = LoxFunction::new(
+                            Rc::clone(method),
+                            Rc::clone(&self.environment),
+                            method.name.name == "init",
+                        );
+                        (method.name.name.clone(), Rc::new(function))
+                    })
+                    .collect();
+                let class = LoxClass::new(&s.name.name, superclass, methods);
+                self.environment = enclosing;
+                self.environment
+                    .borrow_mut()
+                    .define(&s.name.name, Value::Callable(Rc::new(class)));
+                Ok(())
+            }
+        }
+    }
+
+    /// Run statements in the scope `environment`, then return to the current scope.
+    fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Environment,
+    ) -> Result<(), Unwind> {
+        let previous = mem::replace(&mut self.environment, Rc::new(RefCell::new(environment)));
+        // [Note] The scope is left however the block ends, so no early return with ? in here
+        let result = statements
+            .iter()
+            .try_for_each(|statement| self.execute(statement));
+        self.environment = previous;
+        result
+    }
+
+    /// Run the body of a function in the scope `environment`, returning the function's result.
+    pub(crate) fn execute_body(
+        &mut self,
+        body: &[Stmt],
+        environment: Environment,
+    ) -> Result<Value, RuntimeError> {
+        match self.execute_block(body, environment) {
+            Ok(()) => Ok(Value::Nil),
+            Err(Unwind::Return(value)) => Ok(value),
+            Err(Unwind::Error(error)) => Err(error),
+            // [Note] The parser does not let `break` and `continue` reach out of a function body
+            Err(Unwind::Break | Unwind::Continue) => unreachable!("jump out of a function"),
+        }
+    }
+
+    // Expressions
+
+    /// Evaluate an expression to a value.
+    pub fn evaluate(&mut self, expression: &Expr) -> Result<Value, RuntimeError> {
+        match expression {
+            Expr::Literal(e) => Ok(Value::from(&e.value)),
+            Expr::Grouping(e) => self.evaluate(&e.expression),
+            Expr::Unary(e) => self.unary(e),
+            Expr::Binary(e) => self.binary(e),
+            Expr::Logical(e) => self.logical(e),
+            Expr::Variable(e) => self.look_up_variable(e.id, &e.name),
+            Expr::Call(e) => self.call(e),
+            Expr::Assign(e) => {
+                let value = self.evaluate(&e.value)?;
+                match self.locals.get(&e.id) {
+                    Some(depth) => {
+                        self.environment
+                            .borrow_mut()
+                            .assign_at(*depth, &e.name, value.clone())?
+                    }
+                    None => self.globals.borrow_mut().assign(&e.name, value.clone())?,
+                }
+                // [Note] Assignment is an expression, and its value is the value assigned
+                Ok(value)
+            }
+            Expr::Get(e) => match self.evaluate(&e.object)? {
+                Value::Instance(instance) => Instance::get(&instance, &e.name),
+                object @ (Value::List(_) | Value::Map(_)) => {
+                    match collection::method(&object, &e.name.name) {
+                        Some(method) => Ok(Value::Callable(Rc::new(method))),
+                        None => Err(RuntimeError::new(
+                            e.name.span,
+                            format!(
+                                "Undefined property '{}' on {}.",
+                                e.name.name,
+                                object.type_name()
+                            ),
+                        )),
+                    }
+                }
+                object => Err(RuntimeError::new(
+                    e.name.span,
+                    format!(
+                        "Only instances have properties, tried to read '{}' on {}.",
+                        e.name.name,
+                        object.type_name()
+                    ),
+                )),
+            },
+            Expr::Set(e) => {
+                let object = self.evaluate(&e.object)?;
+                let Value::Instance(instance) = object else {
+                    return Err(RuntimeError::new(
+                        e.name.span,
+                        format!(
+                            "Only instances have fields, tried to set '{}' on {}.",
+                            e.name.name,
+                            object.type_name()
+                        ),
+                    ));
+                };
+                let value = self.evaluate(&e.value)?;
+                instance.borrow_mut().set(&e.name, value.clone());
+                Ok(value)
+            }
+            Expr::Super(e) => self.super_method(e),
+            Expr::List(e) => {
+                let elements = e
+                    .elements
+                    .iter()
+                    .map(|element| self.evaluate(element))
+                    .collect::<Result<Vec<Value>, RuntimeError>>()?;
+                Ok(collection::list(elements))
+            }
+            Expr::Map(e) => {
+                let mut map = LoxMap::new();
+                for (key, value) in &e.entries {
+                    let key_value = self.evaluate(key)?;
+                    let key_value = collection::Key::new(&key_value)
+                        .map_err(|message| RuntimeError::new(key.span(), message))?;
+                    map.insert(key_value, self.evaluate(value)?);
+                }
+                Ok(Value::Map(Rc::new(RefCell::new(map))))
+            }
+            Expr::Index(e) => {
+                let object = self.evaluate(&e.object)?;
+                let index = self.evaluate(&e.index)?;
+                collection::get(&object, &index)
+                    .map_err(|error| self.index_error(&e.object, &e.index, error))
+            }
+            Expr::SetIndex(e) => {
+                let object = self.evaluate(&e.object)?;
+                let index = self.evaluate(&e.index)?;
+                let value = self.evaluate(&e.value)?;
+                collection::set(&object, &index, value.clone())
+                    .map_err(|error| self.index_error(&e.object, &e.index, error))?;
+                Ok(value)
+            }
+            Expr::This(e) => self.look_up_variable(
+                e.id,
+                &Identifier {
+                    name: String::from("this"),
+                    span: e.span,
+                },
+            ),
+        }
+    }
+
+    /// Point an indexing error at the object or the index expression, whichever is to blame.
+    fn index_error(
+        &self,
+        object: &Expr,
+        index: &Expr,
+        error: collection::IndexError,
+    ) -> RuntimeError {
+        let span = match error.part {
+            Part::Object => object.span(),
+            Part::Index => index.span(),
+        };
+        RuntimeError::new(span, error.message)
+    }
+
+    /// Read a variable from the scope the resolver bound it to, or from the globals.
+    fn look_up_variable(&self, id: ExprId, name: &Identifier) -> Result<Value, RuntimeError> {
+        match self.locals.get(&id) {
+            Some(depth) => self.environment.borrow().get_at(*depth, name),
+            None => self.globals.borrow().get(name),
+        }
+    }
+
+    /// Find `super.method` and bind it to `this`.
+    fn super_method(&self, expression: &Super) -> Result<Value, RuntimeError> {
+        let identifier = |name: &str| Identifier {
+            name: String::from(name),
+            span: expression.span,
+        };
+        // [Note] The resolver put `super` in the scope just outside the one holding `this`
+        let depth = *self
+            .locals
+            .get(&expression.id)
+            .expect("super should be resolved");
+        let superclass = self
+            .environment
+            .borrow()
+            .get_at(depth, &identifier("super"))?
+            .as_class()
+            .expect("super should be a class");
+        let Value::Instance(instance) = self
+            .environment
+            .borrow()
+            .get_at(depth - 1, &identifier("this"))?
+        else {
+            unreachable!("this should be an instance");
+        };
+
+        match superclass.find_method(&expression.method.name) {
+            Some(method) => Ok(Value::Callable(Rc::new(method.bind(instance)))),
+            None => Err(RuntimeError::new(
+                expression.method.span,
+                format!(
+                    "Undefined property '{}' on superclass {}.",
+                    expression.method.name, superclass
+                ),
+            )),
+        }
+    }
+
+    fn unary(&mut self, unary: &Unary) -> Result<Value, RuntimeError> {
+        let right = self.evaluate(&unary.right)?;
+        match unary.operator {
+            UnaryOp::Negate => match right {
+                Value::Number(value) => Ok(Value::Number(-value)),
+                _ => Err(RuntimeError::new(
+                    unary.operator_span,
+                    "Operand must be a number.",
+                )),
+            },
+            UnaryOp::Not => Ok(Value::Bool(!right.is_truthy())),
+            UnaryOp::BitNot => match right {
+                Value::Number(value) => bitwise::not(value)
+                    .map(Value::Number)
+                    .map_err(|error| RuntimeError::new(unary.right.span(), error.message)),
+                _ => Err(RuntimeError::new(
+                    unary.operator_span,
+                    "Operand must be a number.",
+                )),
+            },
+        }
+    }
+
+    fn binary(&mut self, binary: &Binary) -> Result<Value, RuntimeError> {
+        // [Note] Both operands are evaluated, left to right, before the operator checks their types
+        let left = self.evaluate(&binary.left)?;
+        let right = self.evaluate(&binary.right)?;
+
+        let value = match (binary.operator, &left, &right) {
+            (BinaryOp::Equal, _, _) => Value::Bool(left == right),
+            (BinaryOp::NotEqual, _, _) => Value::Bool(left != right),
+            (BinaryOp::Add, Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+            (BinaryOp::Add, Value::String(a), Value::String(b)) => {
+                Value::String(Rc::from(format!("{}{}", a, b)))
+            }
+            (BinaryOp::Add, _, _) => {
+                return Err(RuntimeError::new(
+                    binary.operator_span,
+                    "Operands must be two numbers or two strings.",
+                ))
+            }
+            // [Note] Division by zero follows IEEE 754 and produces Infinity or NaN, as in the reference implementation
+            (operator, Value::Number(a), Value::Number(b)) => match operator {
+                BinaryOp::Subtract => Value::Number(a - b),
+                BinaryOp::Multiply => Value::Number(a * b),
+                BinaryOp::Divide => Value::Number(a / b),
+                BinaryOp::Greater => Value::Bool(a > b),
+                BinaryOp::GreaterEqual => Value::Bool(a >= b),
+                BinaryOp::Less => Value::Bool(a < b),
+                BinaryOp::LessEqual => Value::Bool(a <= b),
+                BinaryOp::BitAnd
+                | BinaryOp::BitOr
+                | BinaryOp::BitXor
+                | BinaryOp::ShiftLeft
+                | BinaryOp::ShiftRight => match bitwise::binary(operator, *a, *b) {
+                    Ok(value) => Value::Number(value),
+                    Err(error) => {
+                        let span = match error.operand {
+                            Some(Operand::Left) => binary.left.span(),
+                            Some(Operand::Right) => binary.right.span(),
+                            None => binary.span,
+                        };
+                        return Err(RuntimeError::new(span, error.message));
+                    }
+                },
+                BinaryOp::Add | BinaryOp::Equal | BinaryOp::NotEqual => unreachable!(),
+            },
+            _ => {
+                return Err(RuntimeError::new(
+                    binary.operator_span,
+                    "Operands must be numbers.",
+                ))
+            }
+        };
+        Ok(value)
+    }
+
+    fn call(&mut self, call: &Call) -> Result<Value, RuntimeError> {
+        let callee = self.evaluate(&call.callee)?;
+        let arguments = call
+            .arguments
+            .iter()
+            .map(|argument| self.evaluate(argument))
+            .collect::<Result<Vec<Value>, RuntimeError>>()?;
+
+        let Value::Callable(callable) = callee else {
+            return Err(RuntimeError::new(
+                call.span,
+                format!(
+                    "Can only call functions and classes, not {}.",
+                    callee.type_name()
+                ),
+            ));
+        };
+        if arguments.len() != callable.arity() {
+            return Err(RuntimeError::arity(
+                call.span,
+                callable.arity(),
+                arguments.len(),
+                callable.declaration(),
+            ));
+        }
+        if self.call_depth == MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(call.span, "Stack overflow."));
+        }
+
+        self.call_depth += 1;
+        let result = callable.call(self, call, arguments);
+        self.call_depth -= 1;
+        result
+    }
+
+    fn logical(&mut self, logical: &Logical) -> Result<Value, RuntimeError> {
+        // [Note] `and` and `or` return the operand that decided the result, not a boolean
+        let left = self.evaluate(&logical.left)?;
+        let decided = match logical.operator {
+            LogicalOp::Or => left.is_truthy(),
+            LogicalOp::And => !left.is_truthy(),
+        };
+        if decided {
+            Ok(left)
+        } else {
+            self.evaluate(&logical.right)
+        }
+    }
+}
+
+/// Tests for the interpreter
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::modules::compiler::Compiler;
+    use crate::modules::parser::Parser;
+    use crate::modules::resolver::Resolver;
+    use crate::modules::tokenize::Tokenizer;
+    use crate::modules::vm::Vm;
+
+    /// A Write that can still be read after the interpreter has taken ownership of it.
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        pub(crate) fn contents(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    /// Enum representing what runs the program in a test: this tree-walker, or the bytecode VM.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub(crate) enum Backend {
+        Tree,
+        Vm,
+    }
+
+    /// Run a program on `backend`, printing to `out`.
+    pub(crate) fn interpret_on(
+        backend: Backend,
+        source: &str,
+        out: Box<dyn Write>,
+    ) -> Result<(), RuntimeError> {
+        let statements = Parser::new(Tokenizer::new(source))
+            .parse()
+            .expect("source should parse");
+        let locals = Resolver::new()
+            .resolve(&statements)
+            .expect("source should resolve");
+        match backend {
+            Backend::Tree => {
+                let mut interpreter = Interpreter::with_output(out);
+                interpreter.resolve(locals);
+                interpreter.interpret(&statements)
+            }
+            Backend::Vm => {
+                let script = Compiler::new()
+                    .compile(&statements)
+                    .expect("source should compile");
+                Vm::with_output(out).interpret(script)
+            }
+        }
+    }
+
+    /// Run a program on `backend`, returning everything it printed and the runtime error it stopped
+    /// at, if any.
+    pub(crate) fn run_on(backend: Backend, source: &str) -> (String, Option<String>) {
+        let out = SharedBuffer::default();
+        let error = interpret_on(backend, source, Box::new(out.clone())).err();
+        (out.contents(), error.map(|e| e.to_string()))
+    }
+
+    /// Run a program on `backend` that should succeed, returning the lines it printed.
+    pub(crate) fn output_on(backend: Backend, source: &str) -> Vec<String> {
+        let (out, error) = run_on(backend, source);
+        assert_eq!(error, None);
+        out.lines().map(String::from).collect()
+    }
+
+    /// Run a program on the tree-walker, see run_on().
+    pub(crate) fn run(source: &str) -> (String, Option<String>) {
+        run_on(Backend::Tree, source)
+    }
+
+    /// Run a program on the tree-walker that should succeed, returning the lines it printed.
+    pub(crate) fn output(source: &str) -> Vec<String> {
+        output_on(Backend::Tree, source)
+    }
+
+    /// Run a program that should fail, returning the runtime error.
+    pub(crate) fn runtime_error(source: &str) -> String {
+        run(source).1.expect("source should fail at runtime")
+    }
+
+    #[test]
+    fn similar_names() {
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("same", "same"), 0);
+        let names = ["count", "counter", "clock"];
+        assert_eq!(similar_name("cuont", names), Some("count"));
+        assert_eq!(similar_name("countr", names), Some("count"));
+        // One edit is allowed per three characters, so short names have to be close
+        assert_eq!(similar_name("cl", names), None);
+        assert_eq!(similar_name("conut", ["count"]), Some("count"));
+        assert_eq!(similar_name("count", ["count"]), None);
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(
+            output(
+                "print 1 + 2 * 3; print (1 + 2) * 3; print 10 / 4; print -(2 - 5); print 1 / 0;"
+            ),
+            vec!["7", "9", "2.5", "3", "Infinity"]
+        );
+    }
+
+    #[test]
+    fn comparison_and_equality() {
+        assert_eq!(
+            output("print 1 < 2; print 2 <= 2; print 3 > 4; print 3 >= 4;"),
+            vec!["true", "true", "false", "false"]
+        );
+        assert_eq!(
+            output("print 1 == 1; print \"a\" == \"a\"; print nil == nil; print 1 == \"1\"; print nil != false;"),
+            vec!["true", "true", "true", "false", "true"]
+        );
+        assert_eq!(output("print 0 / 0 == 0 / 0;"), vec!["false"]);
+    }
+
+    #[test]
+    fn truthiness() {
+        assert_eq!(
+            output("print !nil; print !false; print !0; print !\"\"; print !!true;"),
+            vec!["true", "true", "false", "false", "true"]
+        );
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(
+            output("print nil or \"default\"; print 1 or 2; print nil and 1; print 1 and 2;"),
+            vec!["default", "1", "nil", "2"]
+        );
+        // The right operand would fail if it were evaluated
+        assert_eq!(
+            output("print true or -\"x\"; print false and -\"x\";"),
+            vec!["true", "false"]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            output("print \"Hello, \" + \"World\"; print \"\";"),
+            vec!["Hello, World", ""]
+        );
+    }
+
+    #[test]
+    fn expression_statements() {
+        assert_eq!(output("1 + 2; \"unused\";"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn type_errors_point_at_the_operator() {
+        assert_eq!(
+            runtime_error("print \"a\" - 1;"),
+            "[line 1, column 11] Error: Operands must be numbers."
+        );
+        assert_eq!(
+            runtime_error("print 1 +\n  \"a\";"),
+            "[line 1, column 9] Error: Operands must be two numbers or two strings."
+        );
+        assert_eq!(
+            runtime_error("print -nil;"),
+            "[line 1, column 7] Error: Operand must be a number."
+        );
+        assert_eq!(
+            runtime_error("print true < false;"),
+            "[line 1, column 12] Error: Operands must be numbers."
+        );
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(
+            output("var a = 1; var b; print a; print b; a = a + 1; print a; print b = \"set\";"),
+            vec!["1", "nil", "2", "set"]
+        );
+        // Redeclaring a global replaces it
+        assert_eq!(output("var a = 1; var a = \"two\"; print a;"), vec!["two"]);
+    }
+
+    #[test]
+    fn blocks_shadow_outer_variables() {
+        let source = "\
+var a = \"global a\";
+var b = \"global b\";
+{
+  var a = \"outer a\";
+  {
+    var a = \"inner a\";
+    print a;
+    print b;
+  }
+  print a;
+  b = \"assigned b\";
+}
+print a;
+print b;";
+        assert_eq!(
+            output(source),
+            vec!["inner a", "global b", "outer a", "global a", "assigned b"]
+        );
+    }
+
+    #[test]
+    fn blocks_can_redeclare_outer_names() {
+        // [Note] Reading the outer `a` in the inner `a`'s initializer is a resolver error, hence b and c
+        assert_eq!(
+            output("var a = 1; { var b = a + 1; var a = b; print a; { var c = a * 10; var a = c; print a; } print a; } print a;"),
+            vec!["2", "20", "2", "1"]
+        );
+    }
+
+    #[test]
+    fn block_scope_ends_with_the_block() {
+        assert_eq!(
+            runtime_error("{ var hidden = 1; }\nprint hidden;"),
+            "[line 2, column 7] Error: Undefined variable 'hidden'."
+        );
+        // Even when the block ends with an error
+        let source = "{ var a = 1; -nil; }";
+        let statements = Parser::new(Tokenizer::new(source)).parse().unwrap();
+        let mut interpreter = Interpreter::with_output(Box::new(SharedBuffer::default()));
+        assert!(interpreter.interpret(&statements).is_err());
+        assert!(interpreter
+            .environment
+            .borrow()
+            .get(&Identifier {
+                name: String::from("a"),
+                span: Span::default(),
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn undefined_variables_point_at_the_identifier() {
+        assert_eq!(
+            runtime_error("print 1;\nprint 2 + missing;"),
+            "[line 2, column 11] Error: Undefined variable 'missing'."
+        );
+        assert_eq!(
+            runtime_error("{\n  undeclared = 1;\n}"),
+            "[line 2, column 3] Error: Undefined variable 'undeclared'."
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        assert_eq!(
+            output("if (1 < 2) print \"then\"; else print \"else\"; if (nil) print 1; else if (0) print 2;"),
+            vec!["then", "2"]
+        );
+        // A dangling else belongs to the nearest if
+        assert_eq!(
+            output("if (true) if (false) print 1; else print 2;"),
+            vec!["2"]
+        );
+    }
+
+    #[test]
+    fn loops() {
+        assert_eq!(
+            output("var i = 0; while (i < 3) { print i; i = i + 1; }"),
+            vec!["0", "1", "2"]
+        );
+        assert_eq!(
+            output("for (var i = 3; i > 0; i = i - 1) print i; var i = \"outer\"; print i;"),
+            vec!["3", "2", "1", "outer"]
+        );
+        assert_eq!(
+            output(
+                "var a = 0; var b = 1; for (; a < 20;) { print a; var t = a; a = b; b = t + b; }"
+            ),
+            vec!["0", "1", "1", "2", "3", "5", "8", "13"]
+        );
+    }
+
+    #[test]
+    fn break_and_continue() {
+        assert_eq!(
+            output("var i = 0; while (true) { i = i + 1; if (i == 2) continue; if (i > 4) break; print i; }"),
+            vec!["1", "3", "4"]
+        );
+        // `continue` in a for loop still runs the increment
+        assert_eq!(
+            output(
+                "for (var i = 0; i < 5; i = i + 1) { if (i == 1 or i == 3) continue; print i; }"
+            ),
+            vec!["0", "2", "4"]
+        );
+        // `break` only leaves the innermost loop
+        assert_eq!(
+            output("for (var i = 0; i < 2; i = i + 1) for (var j = 0; j < 5; j = j + 1) { if (j == 2) break; print i * 10 + j; }"),
+            vec!["0", "1", "10", "11"]
+        );
+    }
+
+    #[test]
+    fn functions() {
+        assert_eq!(
+            output("fun add(a, b) { return a + b; } print add(1, 2); print add; fun nothing() {} print nothing();"),
+            vec!["3", "<fn add>", "nil"]
+        );
+        // Functions are values like any other
+        assert_eq!(
+            output("fun twice(f, x) { return f(f(x)); } fun inc(n) { return n + 1; } var g = inc; print twice(g, 5);"),
+            vec!["7"]
+        );
+        assert_eq!(
+            output("fun fib(n) { if (n < 2) return n; return fib(n - 2) + fib(n - 1); } print fib(15);"),
+            vec!["610"]
+        );
+    }
+
+    #[test]
+    fn return_unwinds_out_of_loops_and_blocks() {
+        let source = "\
+fun find(limit) {
+  for (var i = 0; i < limit; i = i + 1) {
+    while (true) {
+      { if (i == 3) return i; }
+      break;
+    }
+  }
+  return \"not found\";
+}
+print find(10);
+print find(2);";
+        assert_eq!(output(source), vec!["3", "not found"]);
+    }
+
+    #[test]
+    fn closures() {
+        // The returnFunction example from docs/Lox.md
+        let source = "\
+fun returnFunction() {
+  var outside = \"outside\";
+
+  fun inner() {
+    print outside;
+  }
+
+  return inner;
+}
+
+var fn = returnFunction();
+fn();";
+        assert_eq!(output(source), vec!["outside"]);
+
+        // Each call gets its own variables, which stay shared with the closures made during it
+        let source = "\
+fun makeCounter() {
+  var count = 0;
+  fun counter() {
+    count = count + 1;
+    return count;
+  }
+  return counter;
+}
+var a = makeCounter();
+var b = makeCounter();
+print a();
+print a();
+print b();";
+        assert_eq!(output(source), vec!["1", "2", "1"]);
+    }
+
+    #[test]
+    fn closures_keep_their_binding() {
+        // A later declaration in the closure's block must not change which `a` it sees
+        let source = "\
+var a = \"global\";
+{
+  fun showA() {
+    print a;
+  }
+
+  showA();
+  var a = \"block\";
+  showA();
+  print a;
+}";
+        assert_eq!(output(source), vec!["global", "global", "block"]);
+    }
+
+    #[test]
+    fn call_errors() {
+        assert_eq!(
+            runtime_error("fun f(a, b) {}\nf(1);"),
+            "[line 2, column 1] Error: Expected 2 arguments but got 1."
+        );
+        assert_eq!(
+            runtime_error("var x = \"not a function\";\nprint x();"),
+            "[line 2, column 7] Error: Can only call functions and classes, not string."
+        );
+        // [Note] Test threads have small stacks, this needs as much as rlox's interpreter thread gets
+        let overflow = std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(|| runtime_error("fun forever(n) { return forever(n + 1); }\nforever(0);"))
+            .unwrap()
+            .join()
+            .unwrap();
+        assert_eq!(overflow, "[line 1, column 25] Error: Stack overflow.");
+    }
+
+    #[test]
+    fn classes_and_instances() {
+        let source = "\
+class Breakfast {
+  cook() {
+    print \"Eggs a-fryin'!\";
+  }
+
+  serve(who) {
+    print \"Enjoy your breakfast, \" + who + \".\";
+  }
+}
+print Breakfast;
+var breakfast = Breakfast();
+print breakfast;
+breakfast.cook();
+breakfast.serve(\"Reader\");
+breakfast.eggs = 2;
+print breakfast.eggs;
+print breakfast.eggs = breakfast.eggs + 1;";
+        assert_eq!(
+            output(source),
+            vec![
+                "Breakfast",
+                "Breakfast instance",
+                "Eggs a-fryin'!",
+                "Enjoy your breakfast, Reader.",
+                "2",
+                "3"
+            ]
+        );
+    }
+
+    #[test]
+    fn methods_bind_this() {
+        let source = "\
+class Person {
+  init(name) {
+    this.name = name;
+  }
+  greeter() {
+    fun greet() {
+      print \"Hi, \" + this.name;
+    }
+    return greet;
+  }
+}
+var bob = Person(\"Bob\");
+var method = bob.greeter;
+var greet = method();
+bob.name = \"Robert\";
+greet();
+var alice = Person(\"Alice\");
+alice.greeter = bob.greeter;
+alice.greeter()();";
+        assert_eq!(output(source), vec!["Hi, Robert", "Hi, Robert"]);
+    }
+
+    #[test]
+    fn initializers_return_the_instance() {
+        let source = "\
+class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+    if (x == 0) return;
+    this.far = true;
+  }
+}
+var p = Point(0, 1);
+print p.x + p.y;
+print p.init(3, 4) == p;
+print p.far;";
+        assert_eq!(output(source), vec!["1", "true", "true"]);
+        assert_eq!(
+            runtime_error("class A { init(a) {} }\nA();"),
+            "[line 2, column 1] Error: Expected 1 arguments but got 0."
+        );
+    }
+
+    #[test]
+    fn inheritance() {
+        let source = "\
+class Doughnut {
+  cook() {
+    print \"Fry until golden brown.\";
+  }
+  describe() {
+    return \"a \" + this.kind();
+  }
+  kind() {
+    return \"doughnut\";
+  }
+}
+
+class BostonCream < Doughnut {
+  cook() {
+    super.cook();
+    print \"Pipe full of custard and coat with chocolate.\";
+  }
+  kind() {
+    return \"Boston cream\";
+  }
+}
+
+BostonCream().cook();
+print BostonCream().describe();
+print Doughnut().describe();";
+        assert_eq!(
+            output(source),
+            vec![
+                "Fry until golden brown.",
+                "Pipe full of custard and coat with chocolate.",
+                "a Boston cream",
+                "a doughnut"
+            ]
+        );
+    }
+
+    #[test]
+    fn super_binds_to_the_class_it_is_written_in() {
+        // `super` in B means A, even when the method runs on an instance of C
+        let source = "\
+class A {
+  method() {
+    print \"A method\";
+  }
+}
+class B < A {
+  method() {
+    print \"B method\";
+  }
+  test() {
+    super.method();
+  }
+}
+class C < B {}
+C().test();
+var test = C().test;
+test();";
+        assert_eq!(output(source), vec!["A method", "A method"]);
+    }
+
+    #[test]
+    fn inheritance_errors() {
+        assert_eq!(
+            runtime_error(
+                "var NotAClass = \"I am totally not a class\";\nclass Subclass < NotAClass {}"
+            ),
+            "[line 2, column 18] Error: Superclass must be a class, 'NotAClass' is a string."
+        );
+        assert_eq!(
+            runtime_error("fun f() {}\nclass Subclass < f {}"),
+            "[line 2, column 18] Error: Superclass must be a class, 'f' is a function."
+        );
+        assert_eq!(
+            runtime_error("class A {}\nclass B < A { m() { super.missing(); } }\nB().m();"),
+            "[line 2, column 27] Error: Undefined property 'missing' on superclass A."
+        );
+    }
+
+    #[test]
+    fn property_errors() {
+        assert_eq!(
+            runtime_error("class Box {}\nvar box = Box();\nprint box.missing;"),
+            "[line 3, column 11] Error: Undefined property 'missing' on Box instance."
+        );
+        assert_eq!(
+            runtime_error("var n = 1;\nprint n.field;"),
+            "[line 2, column 9] Error: Only instances have properties, tried to read 'field' on number."
+        );
+        assert_eq!(
+            runtime_error("class Box {}\nBox.field = 1;"),
+            "[line 2, column 5] Error: Only instances have fields, tried to set 'field' on class."
+        );
+    }
+
+    #[test]
+    fn errors_stop_the_program() {
+        let (out, error) = run("print 1; print -\"x\"; print 2;");
+        assert_eq!(out, "1\n");
+        assert_eq!(
+            error.as_deref(),
+            Some("[line 1, column 16] Error: Operand must be a number.")
+        );
+    }
+}
@@ -1,3 +1,20 @@
 // Public modules
+pub mod ast;
+pub mod bitwise;
+pub mod chunk;
+pub mod class;
+pub mod collection;
+pub mod compiler;
+pub mod diagnostic;
+pub mod disassembler;
+pub mod environment;
+pub mod function;
+pub mod interpreter;
+pub mod native;
+pub mod object;
+pub mod parser;
 pub mod reader;
+pub mod repl;
+pub mod resolver;
 pub mod tokenize;
+pub mod vm;
@@ -0,0 +1,509 @@
+use crate::modules::ast::{format_number, Call};
+use crate::modules::bitwise;
+use crate::modules::interpreter::{Callable, Interpreter, RuntimeError, Value};
+use crate::modules::reader::{read_source, write_file};
+use std::cell::Cell;
+use std::fmt;
+use std::io;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+// Native functions: Chapter 10 of "Crafting Interpreters", section 10.2.1
+// [Note] Functions written in Rust that every Lox program can call, defined in the global scope
+// before the program runs. They are called just like functions declared in Lox, and their arity
+// is checked the same way. The functions themselves only see Values, so they do not depend on
+// which backend runs the program.
+
+/// Enum representing why a native function could not return a value.
+#[derive(Debug, PartialEq)]
+pub enum NativeError {
+    /// A problem with the argument at this index, e.g. a number where a string was expected
+    Argument(usize, String),
+    /// A problem with the call as a whole, e.g. a file that cannot be read
+    Call(String),
+    /// The program asked to stop with this exit status
+    Exit(i32),
+}
+
+/// The Rust side of a native function.
+pub type NativeFn = Box<dyn Fn(&[Value]) -> Result<Value, NativeError>>;
+
+/// Struct representing a function built into rlox.
+pub struct NativeFunction {
+    name: &'static str,
+    arity: usize,
+    function: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &'static str, arity: usize, function: NativeFn) -> Self {
+        NativeFunction {
+            name,
+            arity,
+            function,
+        }
+    }
+
+    /// Run the function on arguments that have already been checked against its arity.
+    pub fn apply(&self, arguments: &[Value]) -> Result<Value, NativeError> {
+        (self.function)(arguments)
+    }
+}
+
+// [Note] Written out because a boxed closure cannot derive Debug
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+impl fmt::Display for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Callable for NativeFunction {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn as_native(&self) -> Option<&NativeFunction> {
+        Some(self)
+    }
+
+    fn call(
+        self: Rc<Self>,
+        _interpreter: &mut Interpreter,
+        call: &Call,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        self.apply(&arguments).map_err(|error| match error {
+            NativeError::Argument(index, message) => {
+                RuntimeError::new(call.arguments[index].span(), message)
+            }
+            NativeError::Call(message) => RuntimeError::new(call.span, message),
+            NativeError::Exit(code) => RuntimeError::exit(call.span, code),
+        })
+    }
+}
+
+/// Struct representing the generator behind `random()`: SplitMix64, small and good enough for scripts.
+// [Note] Not for anything secret. `seed(n)` makes the numbers repeat from run to run.
+#[derive(Debug)]
+pub struct Random {
+    state: Cell<u64>,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random {
+            state: Cell::new(seed),
+        }
+    }
+
+    /// Start from the current time, so every run gets different numbers.
+    pub fn from_clock() -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Self::new(now.as_nanos() as u64)
+    }
+
+    pub fn seed(&self, seed: u64) {
+        self.state.set(seed);
+    }
+
+    /// The next number, evenly spread over [0, 1).
+    pub fn next_f64(&self) -> f64 {
+        let state = self.state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        self.state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        // The top 53 bits fill an f64's mantissa exactly
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Every native function, in a fresh set with its own random generator.
+pub fn library() -> Vec<NativeFunction> {
+    let random = Rc::new(Random::from_clock());
+    let seeded = Rc::clone(&random);
+
+    vec![
+        NativeFunction::new("clock", 0, Box::new(|_| Ok(Value::Number(clock())))),
+        NativeFunction::new("input", 0, Box::new(|_| input())),
+        NativeFunction::new(
+            "readFile",
+            1,
+            Box::new(|arguments| {
+                let path = string(arguments, 0, "readFile")?;
+                read_source(path)
+                    .map(|contents| Value::String(contents.into()))
+                    .map_err(|error| NativeError::Call(error.message))
+            }),
+        ),
+        NativeFunction::new(
+            "writeFile",
+            2,
+            Box::new(|arguments| {
+                let path = string(arguments, 0, "writeFile")?;
+                let contents = string(arguments, 1, "writeFile")?;
+                write_file(path, contents)
+                    .map(|()| Value::Nil)
+                    .map_err(|error| NativeError::Call(error.message))
+            }),
+        ),
+        NativeFunction::new(
+            "len",
+            1,
+            Box::new(|arguments| match &arguments[0] {
+                Value::String(text) => Ok(Value::Number(text.chars().count() as f64)),
+                Value::List(list) => Ok(Value::Number(list.borrow().len() as f64)),
+                Value::Map(map) => Ok(Value::Number(map.borrow().len() as f64)),
+                other => Err(type_error(0, "len", "string, list or map", other)),
+            }),
+        ),
+        NativeFunction::new("substr", 3, Box::new(substr)),
+        NativeFunction::new(
+            "str",
+            1,
+            Box::new(|arguments| Ok(Value::String(arguments[0].to_string().into()))),
+        ),
+        NativeFunction::new("num", 1, Box::new(num)),
+        NativeFunction::new(
+            "floor",
+            1,
+            Box::new(|arguments| Ok(Value::Number(number(arguments, 0, "floor")?.floor()))),
+        ),
+        NativeFunction::new(
+            "sqrt",
+            1,
+            Box::new(|arguments| Ok(Value::Number(number(arguments, 0, "sqrt")?.sqrt()))),
+        ),
+        NativeFunction::new(
+            "random",
+            0,
+            Box::new(move |_| Ok(Value::Number(random.next_f64()))),
+        ),
+        NativeFunction::new(
+            "seed",
+            1,
+            Box::new(move |arguments| {
+                // [Note] Any number is a seed, fractions included, so take its bits as they are
+                seeded.seed(number(arguments, 0, "seed")?.to_bits());
+                Ok(Value::Nil)
+            }),
+        ),
+        NativeFunction::new(
+            "exit",
+            1,
+            Box::new(|arguments| {
+                let code = whole_number(arguments, 0, "exit")?;
+                // [Note] A process exit status is a single byte, anything else would be cut down to one
+                match u8::try_from(code) {
+                    Ok(code) => Err(NativeError::Exit(i32::from(code))),
+                    Err(_) => Err(NativeError::Argument(
+                        0,
+                        format!("Exit status {} is out of range.", code),
+                    )),
+                }
+            }),
+        ),
+    ]
+}
+
+// The natives that need more than a line
+
+/// Seconds since the Unix epoch, for timing code.
+fn clock() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |now| now.as_secs_f64())
+}
+
+/// The next line of standard input without its line ending, or nil at the end of the input.
+fn input() -> Result<Value, NativeError> {
+    let mut line = String::new();
+    match io::stdin().read_line(&mut line) {
+        Ok(0) => Ok(Value::Nil),
+        Ok(_) => {
+            let line = line.strip_suffix('\n').unwrap_or(&line);
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            Ok(Value::String(line.into()))
+        }
+        Err(error) => Err(NativeError::Call(format!(
+            "Failed to read standard input: {}",
+            error
+        ))),
+    }
+}
+
+/// `substr(text, start, length)`: the `length` characters of `text` from index `start`.
+fn substr(arguments: &[Value]) -> Result<Value, NativeError> {
+    let text = string(arguments, 0, "substr")?;
+    let start = index(arguments, 1, "substr")?;
+    let length = index(arguments, 2, "substr")?;
+
+    let count = text.chars().count();
+    if start > count {
+        return Err(NativeError::Argument(
+            1,
+            format!(
+                "Start {} is past the end of a string of length {}.",
+                start, count
+            ),
+        ));
+    }
+    if length > count - start {
+        return Err(NativeError::Argument(
+            2,
+            format!(
+                "Length {} from {} is past the end of a string of length {}.",
+                length, start, count
+            ),
+        ));
+    }
+    let slice: String = text.chars().skip(start).take(length).collect();
+    Ok(Value::String(slice.into()))
+}
+
+/// `num(value)`: the number a string spells out, or nil if it is not one. Numbers are returned as they are.
+fn num(arguments: &[Value]) -> Result<Value, NativeError> {
+    match &arguments[0] {
+        Value::Number(value) => Ok(Value::Number(*value)),
+        Value::String(text) => match text.trim().parse::<f64>() {
+            // [Note] Rust also parses "inf" and "NaN", which are not Lox numbers
+            Ok(value) if value.is_finite() => Ok(Value::Number(value)),
+            _ => Ok(Value::Nil),
+        },
+        other => Err(type_error(0, "num", "string or number", other)),
+    }
+}
+
+// Argument checks
+
+fn type_error(index: usize, function: &str, expected: &str, found: &Value) -> NativeError {
+    NativeError::Argument(
+        index,
+        format!(
+            "Argument {} of {}() must be a {}, not {}.",
+            index + 1,
+            function,
+            expected,
+            found.type_name()
+        ),
+    )
+}
+
+/// The argument at `index` as a string.
+fn string<'a>(
+    arguments: &'a [Value],
+    index: usize,
+    function: &str,
+) -> Result<&'a str, NativeError> {
+    match &arguments[index] {
+        Value::String(text) => Ok(text),
+        other => Err(type_error(index, function, "string", other)),
+    }
+}
+
+/// The argument at `index` as a number.
+fn number(arguments: &[Value], index: usize, function: &str) -> Result<f64, NativeError> {
+    match &arguments[index] {
+        Value::Number(value) => Ok(*value),
+        other => Err(type_error(index, function, "number", other)),
+    }
+}
+
+/// The argument at `index` as a number with no fractional part.
+fn whole_number(arguments: &[Value], index: usize, function: &str) -> Result<i64, NativeError> {
+    let value = number(arguments, index, function)?;
+    // [Note] The same range the bitwise operators accept, within which every whole number is exact
+    if value.fract() != 0.0 || value.abs() > bitwise::MAX_EXACT {
+        return Err(NativeError::Argument(
+            index,
+            format!(
+                "Argument {} of {}() must be a whole number, not {}.",
+                index + 1,
+                function,
+                format_number(value)
+            ),
+        ));
+    }
+    Ok(value as i64)
+}
+
+/// The argument at `index` as a position or count, a whole number that is not negative.
+fn index(arguments: &[Value], index: usize, function: &str) -> Result<usize, NativeError> {
+    let value = whole_number(arguments, index, function)?;
+    usize::try_from(value).map_err(|_| {
+        NativeError::Argument(
+            index,
+            format!(
+                "Argument {} of {}() must not be negative, got {}.",
+                index + 1,
+                function,
+                value
+            ),
+        )
+    })
+}
+
+/// Tests for the native functions
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::interpreter::tests::{output, run, runtime_error};
+
+    #[test]
+    fn natives_are_globals() {
+        assert_eq!(
+            output("print clock; print len; print clock() > 0;"),
+            vec!["<native fn clock>", "<native fn len>", "true"]
+        );
+        // They can be shadowed like any other global
+        assert_eq!(
+            output("fun len(x) { return 42; } print len(\"a\");"),
+            vec!["42"]
+        );
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            output(
+                "print len(\"héllo\"); print substr(\"héllo\", 1, 3); print substr(\"abc\", 3, 0);\
+                 print str(1.5) + str(nil) + str(true); print len(str(10));"
+            ),
+            vec!["5", "éll", "", "1.5niltrue", "2"]
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            output(
+                "print num(\" 42 \") + 1; print num(\"1e3\"); print num(\"abc\"); print num(\"inf\");\
+                 print num(7); print floor(2.7); print floor(-2.5); print sqrt(16);"
+            ),
+            vec!["43", "1000", "nil", "nil", "7", "2", "-3", "4"]
+        );
+    }
+
+    #[test]
+    fn random_is_seedable() {
+        let script = "seed(42); var a = random(); var b = random(); seed(42);\
+                      print a == random(); print b == random(); print a != b;\
+                      print a >= 0 and a < 1;";
+        assert_eq!(output(script), vec!["true", "true", "true", "true"]);
+
+        let random = Random::new(1);
+        for _ in 0..1000 {
+            let value = random.next_f64();
+            assert!((0.0..1.0).contains(&value));
+        }
+    }
+
+    #[test]
+    fn files() {
+        let path = std::env::temp_dir().join(format!("rlox-native-{}.txt", std::process::id()));
+        let path = path.to_str().unwrap().replace('\\', "/");
+        let script = format!(
+            "writeFile(\"{0}\", \"line 1\\nline 2\"); var text = readFile(\"{0}\"); print text; print len(text);",
+            path
+        );
+        assert_eq!(output(&script), vec!["line 1", "line 2", "13"]);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            runtime_error("readFile(\"no/such/file.txt\");"),
+            "[line 1, column 1] Error: File 'no/such/file.txt' not found."
+        );
+        assert_eq!(
+            runtime_error("writeFile(\"no/such/dir/file.txt\", \"x\");"),
+            "[line 1, column 1] Error: Folder for file 'no/such/dir/file.txt' not found."
+        );
+    }
+
+    #[test]
+    fn arity_and_type_errors() {
+        assert_eq!(
+            runtime_error("len();"),
+            "[line 1, column 1] Error: Expected 1 arguments but got 0."
+        );
+        assert_eq!(
+            runtime_error("clock(1);"),
+            "[line 1, column 1] Error: Expected 0 arguments but got 1."
+        );
+        // Type errors point at the argument
+        assert_eq!(
+            runtime_error("len(12);"),
+            "[line 1, column 5] Error: Argument 1 of len() must be a string, list or map, not number."
+        );
+        assert_eq!(
+            runtime_error("substr(\"abc\", 0, nil);"),
+            "[line 1, column 18] Error: Argument 3 of substr() must be a number, not nil."
+        );
+        assert_eq!(
+            runtime_error("substr(\"abc\", 1.5, 1);"),
+            "[line 1, column 15] Error: Argument 2 of substr() must be a whole number, not 1.5."
+        );
+        assert_eq!(
+            runtime_error("substr(\"abc\", -1, 1);"),
+            "[line 1, column 15] Error: Argument 2 of substr() must not be negative, got -1."
+        );
+        assert_eq!(
+            runtime_error("substr(\"abc\", 1, 5);"),
+            "[line 1, column 18] Error: Length 5 from 1 is past the end of a string of length 3."
+        );
+        assert_eq!(
+            runtime_error("num(clock);"),
+            "[line 1, column 5] Error: Argument 1 of num() must be a string or number, not function."
+        );
+    }
+
+    #[test]
+    fn exit_stops_the_program() {
+        let (out, error) = run("print 1; exit(3); print 2;");
+        assert_eq!(out, "1\n");
+        assert!(error.is_some());
+        assert_eq!(
+            runtime_error("exit(0.5);"),
+            "[line 1, column 6] Error: Argument 1 of exit() must be a whole number, not 0.5."
+        );
+        assert_eq!(
+            runtime_error("exit(256);"),
+            "[line 1, column 6] Error: Exit status 256 is out of range."
+        );
+        assert_eq!(
+            runtime_error("exit(-1);"),
+            "[line 1, column 6] Error: Exit status -1 is out of range."
+        );
+        assert_eq!(
+            run("exit(255);").1.unwrap(),
+            "[line 1, column 1] Error: Exited with status 255."
+        );
+    }
+
+    #[test]
+    fn whole_numbers_stop_where_the_bitwise_operators_do() {
+        assert_eq!(
+            runtime_error("exit(9007199254740991);"),
+            "[line 1, column 6] Error: Exit status 9007199254740991 is out of range."
+        );
+        assert_eq!(
+            runtime_error("exit(9007199254740992);"),
+            "[line 1, column 6] Error: Argument 1 of exit() must be a whole number, not 9007199254740992."
+        );
+        assert_eq!(
+            runtime_error("print 9007199254740992 | 0;"),
+            "[line 1, column 7] Error: Operand of '|' must be an exact integer, not 9007199254740992."
+        );
+    }
+}
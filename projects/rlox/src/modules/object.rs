@@ -0,0 +1,131 @@
+use crate::modules::chunk::Chunk;
+use crate::modules::interpreter::Value;
+use crate::modules::tokenize::Span;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+// Functions, Closures and Classes of the bytecode VM: Chapters 24 to 28 of "Crafting Interpreters"
+// [Note] The VM's own kinds of value. Strings, lists, maps and native functions are shared with the
+// tree-walking interpreter, these are not: a VM function is a chunk of bytecode, not a piece of AST.
+
+/// Struct representing a compiled function: its bytecode, and what a closure over it needs.
+#[derive(Debug, Default)]
+pub struct ObjFunction {
+    /// Empty for the top level of a script
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /// The name in the function's declaration, for errors to point at. None for a script
+    pub declaration: Option<Span>,
+}
+
+impl fmt::Display for ObjFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+/// Enum representing a variable a closure captured.
+// [Note] While the variable's function is still running the variable lives on the VM's stack, and
+// the upvalue refers to its slot there. When it goes out of scope it is moved into the upvalue itself,
+// "closed", so the closures that captured it keep it alive and keep sharing it.
+#[derive(Debug)]
+pub enum ObjUpvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+/// Struct representing a function value: a compiled function with the variables it captured.
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: Rc<ObjFunction>,
+    pub upvalues: Vec<Rc<RefCell<ObjUpvalue>>>,
+}
+
+/// Struct representing a class, with the methods it declares and inherits.
+// [Note] A subclass starts with a copy of its superclass's methods, so looking one up never has to
+// walk the superclass chain. The methods are added after the class exists, hence the RefCell.
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: String,
+    pub methods: RefCell<HashMap<String, Rc<ObjClosure>>>,
+}
+
+impl ObjClass {
+    pub fn new(name: &str) -> Self {
+        ObjClass {
+            name: String::from(name),
+            methods: RefCell::new(HashMap::new()),
+        }
+    }
+
+    pub fn find_method(&self, name: &str) -> Option<Rc<ObjClosure>> {
+        self.methods.borrow().get(name).cloned()
+    }
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: Rc<ObjClass>,
+    pub fields: HashMap<String, Value>,
+}
+
+/// Struct representing a method read from an instance, which remembers the instance as `this`.
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: Rc<ObjClosure>,
+}
+
+/// Enum representing a value only the VM makes.
+#[derive(Debug, Clone)]
+pub enum Object {
+    Function(Rc<ObjFunction>),
+    Closure(Rc<ObjClosure>),
+    Class(Rc<ObjClass>),
+    Instance(Rc<RefCell<ObjInstance>>),
+    BoundMethod(Rc<ObjBoundMethod>),
+}
+
+impl Object {
+    /// The name of the object's type, as used in error messages. The same as for the tree-walker's values.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Object::Function(_) | Object::Closure(_) | Object::BoundMethod(_) => "function",
+            Object::Class(_) => "class",
+            Object::Instance(_) => "instance",
+        }
+    }
+}
+
+// [Note] Objects are equal only to themselves
+impl PartialEq for Object {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Object::Function(a), Object::Function(b)) => Rc::ptr_eq(a, b),
+            (Object::Closure(a), Object::Closure(b)) => Rc::ptr_eq(a, b),
+            (Object::Class(a), Object::Class(b)) => Rc::ptr_eq(a, b),
+            (Object::Instance(a), Object::Instance(b)) => Rc::ptr_eq(a, b),
+            (Object::BoundMethod(a), Object::BoundMethod(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Object::Function(function) => write!(f, "{}", function),
+            Object::Closure(closure) => write!(f, "{}", closure.function),
+            Object::Class(class) => write!(f, "{}", class.name),
+            Object::Instance(instance) => write!(f, "{} instance", instance.borrow().class.name),
+            Object::BoundMethod(bound) => write!(f, "{}", bound.method.function),
+        }
+    }
+}
@@ -0,0 +1,1228 @@
+use crate::modules::ast::*;
+use crate::modules::tokenize::{self, LexError, Span, Token, TokenType, Tokenizer};
+use std::fmt;
+use std::mem;
+use std::rc::Rc;
+// Parsing Expressions and Statements: Chapters 6 and 8 of "Crafting Interpreters"
+// [Note] The parser is recursive descent: one method per grammar rule, each calling the rule with
+// the next higher precedence. The call stack mirrors the shape of the tree being built.
+//
+// program     → declaration* EOF ;
+// declaration → classDecl | funDecl | varDecl | statement ;
+// statement   → exprStmt | forStmt | ifStmt | printStmt | returnStmt | whileStmt | block
+//             | breakStmt | continueStmt ;
+// forStmt     → "for" "(" ( "var" IDENTIFIER "in" expression
+//                         | ( varDecl | exprStmt | ";" ) expression? ";" expression? ) ")" statement ;
+// expression  → assignment ;
+// assignment  → ( call "." IDENTIFIER | call "[" expression "]" | IDENTIFIER ) "=" assignment
+//             | logic_or ;
+// logic_or    → logic_and ( "or" logic_and )* ;
+// logic_and   → bit_or ( "and" bit_or )* ;
+// bit_or      → bit_xor ( "|" bit_xor )* ;
+// bit_xor     → bit_and ( "^" bit_and )* ;
+// bit_and     → equality ( "&" equality )* ;
+// equality    → comparison ( ( "!=" | "==" ) comparison )* ;
+// comparison  → shift ( ( ">" | ">=" | "<" | "<=" ) shift )* ;
+// shift       → term ( ( "<<" | ">>" ) term )* ;
+// term        → factor ( ( "-" | "+" ) factor )* ;
+// factor      → unary ( ( "/" | "*" ) unary )* ;
+// unary       → ( "!" | "-" | "~" ) unary | call ;
+// call        → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
+// primary     → "true" | "false" | "nil" | "this" | NUMBER | STRING | IDENTIFIER | "(" expression ")"
+//             | "super" "." IDENTIFIER | list | map ;
+// list        → "[" ( expression ( "," expression )* ","? )? "]" ;
+// map         → "{" ( expression ":" expression ( "," expression ":" expression )* ","? )? "}" ;
+
+/// The most arguments a call can pass, and the most parameters a function can declare.
+pub const MAX_ARGUMENTS: usize = 255;
+
+/// Struct representing a syntax error: what the grammar expected at `span`, and what was found there.
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub span: Span,
+    pub expected: String,
+    pub found: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, column {}] Error: Expected {}, found {}.",
+            self.span.line, self.span.column, self.expected, self.found
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// The parser machine responsible for turning tokens into an abstract syntax tree.
+pub struct Parser<'a> {
+    // [Note] The parser pulls tokens from the tokenizer one at a time, so the whole token list
+    // never has to exist in memory. `current` is the one token of lookahead the grammar needs.
+    tokens: Tokenizer<'a>,
+    current: Token<'a>,
+    previous: Token<'a>,
+    errors: Vec<ParseError>,
+    lex_errors: Vec<LexError>,
+    /// How many loops enclose the code being parsed, to check `break` and `continue`
+    loop_depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Parser constructor
+    pub fn new(tokens: Tokenizer<'a>) -> Self {
+        let mut parser = Parser {
+            tokens,
+            current: Token::new(TokenType::EOF, "", Span::default()),
+            previous: Token::new(TokenType::EOF, "", Span::default()),
+            errors: Vec::new(),
+            lex_errors: Vec::new(),
+            loop_depth: 0,
+        };
+        // Load the first token into `current`
+        parser.advance();
+        parser
+    }
+
+    /// Main entry point for parsing. Returns the program's statements, or every syntax error found.
+    // [Note] After a syntax error the parser skips ahead to the next statement and carries on
+    // (see synchronize), so one run reports all the independent errors in a file.
+    pub fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParseError>> {
+        let mut statements = Vec::new();
+        while !self.is_at_end() {
+            statements.extend(self.declaration());
+        }
+
+        if self.errors.is_empty() {
+            Ok(statements)
+        } else {
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    /// Parse source that should be a single expression, with nothing after it. Used by the REPL.
+    pub fn parse_expression(&mut self) -> Result<Expr, Vec<ParseError>> {
+        let result = self.expression().and_then(|expression| {
+            if self.is_at_end() {
+                Ok(expression)
+            } else {
+                Err(self.error_at_current("end of expression"))
+            }
+        });
+
+        match result {
+            Ok(expression) if self.errors.is_empty() => Ok(expression),
+            Ok(_) => Err(mem::take(&mut self.errors)),
+            Err(error) => {
+                self.errors.push(error);
+                Err(mem::take(&mut self.errors))
+            }
+        }
+    }
+
+    /// Lexical errors the tokenizer reported while the parser was pulling tokens.
+    // [Note] Characters the tokenizer rejects never reach the parser, it parses what is left.
+    pub fn lex_errors(&self) -> &[LexError] {
+        &self.lex_errors
+    }
+
+    // Declarations
+
+    /// Parse a declaration, or record the syntax error and skip to the next statement.
+    fn declaration(&mut self) -> Option<Stmt> {
+        match self.try_declaration() {
+            Ok(statement) => Some(statement),
+            Err(error) => {
+                self.errors.push(error);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn try_declaration(&mut self) -> Result<Stmt, ParseError> {
+        if self.matches(TokenType::Class) {
+            return self.class_declaration();
+        }
+        if self.matches(TokenType::Fun) {
+            let start = self.previous.span;
+            return Ok(Stmt::Function(Rc::new(self.function("function", start)?)));
+        }
+        if self.matches(TokenType::Var) {
+            return self.var_declaration();
+        }
+        self.statement()
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        let name = self.identifier("class name")?;
+        let superclass = if self.matches(TokenType::Less) {
+            let name = self.identifier("superclass name")?;
+            Some(Variable {
+                id: ExprId::next(),
+                span: name.span,
+                name,
+            })
+        } else {
+            None
+        };
+        self.consume(TokenType::LeftBrace, "'{' before class body")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            let start = self.current.span;
+            methods.push(Rc::new(self.function("method", start)?));
+        }
+        self.consume(TokenType::RightBrace, "'}' after class body")?;
+
+        Ok(Stmt::Class(Class {
+            name,
+            superclass,
+            methods,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    /// Parse the name, parameters and body of a function or method.
+    /// `kind` is used in error messages, `start` is where the declaration began.
+    fn function(&mut self, kind: &str, start: Span) -> Result<Function, ParseError> {
+        let name = self.identifier(&format!("{} name", kind))?;
+        self.consume(TokenType::LeftParen, &format!("'(' after {} name", kind))?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                params.push(self.identifier("parameter name")?);
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "')' after parameters")?;
+        self.check_limit(&params, |param| param.span, "parameters");
+        self.consume(TokenType::LeftBrace, &format!("'{{' before {} body", kind))?;
+        // A loop around a function declaration does not make `break` valid inside its body
+        let loop_depth = mem::take(&mut self.loop_depth);
+        let body = self.block_statements();
+        self.loop_depth = loop_depth;
+        let body = body?;
+
+        Ok(Function {
+            name,
+            params,
+            body,
+            span: start.to(self.previous.span),
+        })
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        let name = self.identifier("variable name")?;
+        self.var_rest(start, name)
+    }
+
+    /// The rest of a variable declaration, after its name has been consumed.
+    fn var_rest(&mut self, start: Span, name: Identifier) -> Result<Stmt, ParseError> {
+        let initializer = if self.matches(TokenType::Equal) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "';' after variable declaration")?;
+
+        Ok(Stmt::Var(Var {
+            name,
+            initializer,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    // Statements
+
+    fn statement(&mut self) -> Result<Stmt, ParseError> {
+        if self.matches(TokenType::Break) || self.matches(TokenType::Continue) {
+            return self.jump_statement();
+        }
+        if self.matches(TokenType::For) {
+            return self.for_statement();
+        }
+        if self.matches(TokenType::If) {
+            return self.if_statement();
+        }
+        if self.matches(TokenType::Print) {
+            return self.print_statement();
+        }
+        if self.matches(TokenType::Return) {
+            return self.return_statement();
+        }
+        if self.matches(TokenType::While) {
+            return self.while_statement();
+        }
+        if self.matches(TokenType::LeftBrace) {
+            let start = self.previous.span;
+            let statements = self.block_statements()?;
+            return Ok(Stmt::Block(Block {
+                statements,
+                span: start.to(self.previous.span),
+            }));
+        }
+        self.expression_statement()
+    }
+
+    /// `for (initializer; condition; increment) body` has no node of its own. It is desugared into
+    /// `{ initializer; while (condition) { body; increment; } }`.
+    /// `for (var name in iterable) body` is a ForIn node.
+    fn for_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        self.consume(TokenType::LeftParen, "'(' after 'for'")?;
+
+        let initializer = if self.matches(TokenType::Semicolon) {
+            None
+        } else if self.matches(TokenType::Var) {
+            // [Note] Both kinds of loop start with `var name`, only the token after it tells them apart
+            let var_start = self.previous.span;
+            let name = self.identifier("variable name")?;
+            if self.matches(TokenType::In) {
+                return self.for_in_statement(start, name);
+            }
+            Some(self.var_rest(var_start, name)?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::Semicolon, "';' after loop condition")?;
+
+        let increment = if self.check(TokenType::RightParen) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::RightParen, "')' after for clauses")?;
+
+        let body = self.loop_body()?;
+        let span = start.to(self.previous.span);
+
+        // A missing condition loops forever
+        let condition = condition.unwrap_or(Expr::Literal(Literal {
+            value: LiteralValue::Bool(true),
+            span: start,
+        }));
+        let mut body = Stmt::While(While {
+            condition,
+            body: Box::new(body),
+            increment,
+            span,
+        });
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(Block {
+                statements: vec![initializer, body],
+                span,
+            });
+        }
+        Ok(body)
+    }
+
+    /// The rest of a `for (var name in iterable) body` loop, after `in` has been consumed.
+    fn for_in_statement(&mut self, start: Span, name: Identifier) -> Result<Stmt, ParseError> {
+        let iterable = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after for-in iterable")?;
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::ForIn(ForIn {
+            name,
+            iterable,
+            body,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        self.consume(TokenType::LeftParen, "'(' after 'if'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after if condition")?;
+
+        // [Note] An `else` binds to the nearest `if`, which is what parsing it greedily here does
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.matches(TokenType::Else) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(If {
+            condition,
+            then_branch,
+            else_branch,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "';' after value")?;
+        Ok(Stmt::Print(Print {
+            expression,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        let value = if self.check(TokenType::Semicolon) {
+            None
+        } else {
+            Some(self.expression()?)
+        };
+        self.consume(TokenType::Semicolon, "';' after return value")?;
+        Ok(Stmt::Return(Return {
+            value,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, ParseError> {
+        let start = self.previous.span;
+        self.consume(TokenType::LeftParen, "'(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after condition")?;
+        let body = Box::new(self.loop_body()?);
+
+        Ok(Stmt::While(While {
+            condition,
+            body,
+            increment: None,
+            span: start.to(self.previous.span),
+        }))
+    }
+
+    /// The body of a `while` or `for` loop, where `break` and `continue` are allowed.
+    fn loop_body(&mut self) -> Result<Stmt, ParseError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    /// `break;` or `continue;`, after the keyword has been consumed.
+    fn jump_statement(&mut self) -> Result<Stmt, ParseError> {
+        let keyword = self.previous.clone();
+        if self.loop_depth == 0 {
+            // [Note] Reported without unwinding, like an invalid assignment target
+            self.errors.push(ParseError {
+                span: keyword.span,
+                expected: format!("'{}' to be inside a loop", keyword.lexeme),
+                found: format!("'{}' outside of any loop", keyword.lexeme),
+            });
+        }
+        self.consume(
+            TokenType::Semicolon,
+            &format!("';' after '{}'", keyword.lexeme),
+        )?;
+
+        let span = keyword.span.to(self.previous.span);
+        Ok(match keyword.token_type {
+            TokenType::Break => Stmt::Break(Break { span }),
+            _ => Stmt::Continue(Continue { span }),
+        })
+    }
+
+    /// The statements of a block, after its '{' has been consumed.
+    fn block_statements(&mut self) -> Result<Vec<Stmt>, ParseError> {
+        let mut statements = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            statements.extend(self.declaration());
+        }
+        self.consume(TokenType::RightBrace, "'}' after block")?;
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, ParseError> {
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "';' after expression")?;
+        Ok(Stmt::Expression(Expression {
+            span: expression.span().to(self.previous.span),
+            expression,
+        }))
+    }
+
+    // Expressions, from lowest to highest precedence
+
+    pub fn expression(&mut self) -> Result<Expr, ParseError> {
+        self.assignment()
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ParseError> {
+        // [Note] The left side is parsed as an ordinary expression first. Only on seeing '=' do we
+        // know it was an assignment target, so the expression is converted after the fact.
+        let expr = self.or()?;
+
+        if self.matches(TokenType::Equal) {
+            let equals = self.previous.clone();
+            // Assignment is right-associative: a = b = c is a = (b = c)
+            let value = Box::new(self.assignment()?);
+            let span = expr.span().to(value.span());
+
+            return match expr {
+                Expr::Variable(variable) => Ok(Expr::Assign(Assign {
+                    id: variable.id,
+                    name: variable.name,
+                    value,
+                    span,
+                })),
+                Expr::Get(get) => Ok(Expr::Set(Set {
+                    object: get.object,
+                    name: get.name,
+                    value,
+                    span,
+                })),
+                Expr::Index(index) => Ok(Expr::SetIndex(SetIndex {
+                    object: index.object,
+                    index: index.index,
+                    value,
+                    span,
+                })),
+                // [Note] The parser is not confused about where it is, so there is no need to
+                // unwind and synchronize. Record the error and carry on with the value
+                _ => {
+                    self.errors.push(ParseError {
+                        span: expr.span(),
+                        expected: format!("a variable or property before '{}'", equals.lexeme),
+                        found: self.describe(expr.span()),
+                    });
+                    Ok(*value)
+                }
+            };
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        self.logical(Self::and, TokenType::Or, LogicalOp::Or)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        self.logical(Self::bit_or, TokenType::And, LogicalOp::And)
+    }
+
+    // [Note] The bitwise operators sit where C puts them, below equality: `x & 1 == 0` is
+    // `x & (1 == 0)`, so tests of bits need their own parentheses, `(x & 1) == 0`
+    fn bit_or(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Self::bit_xor, &[(TokenType::Pipe, BinaryOp::BitOr)])
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Self::bit_and, &[(TokenType::Caret, BinaryOp::BitXor)])
+    }
+
+    fn bit_and(&mut self) -> Result<Expr, ParseError> {
+        self.binary(Self::equality, &[(TokenType::Ampersand, BinaryOp::BitAnd)])
+    }
+
+    fn equality(&mut self) -> Result<Expr, ParseError> {
+        self.binary(
+            Self::comparison,
+            &[
+                (TokenType::BangEqual, BinaryOp::NotEqual),
+                (TokenType::EqualEqual, BinaryOp::Equal),
+            ],
+        )
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParseError> {
+        self.binary(
+            Self::shift,
+            &[
+                (TokenType::Greater, BinaryOp::Greater),
+                (TokenType::GreaterEqual, BinaryOp::GreaterEqual),
+                (TokenType::Less, BinaryOp::Less),
+                (TokenType::LessEqual, BinaryOp::LessEqual),
+            ],
+        )
+    }
+
+    fn shift(&mut self) -> Result<Expr, ParseError> {
+        self.binary(
+            Self::term,
+            &[
+                (TokenType::LessLess, BinaryOp::ShiftLeft),
+                (TokenType::GreaterGreater, BinaryOp::ShiftRight),
+            ],
+        )
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        self.binary(
+            Self::factor,
+            &[
+                (TokenType::Minus, BinaryOp::Subtract),
+                (TokenType::Plus, BinaryOp::Add),
+            ],
+        )
+    }
+
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        self.binary(
+            Self::unary,
+            &[
+                (TokenType::Slash, BinaryOp::Divide),
+                (TokenType::Star, BinaryOp::Multiply),
+            ],
+        )
+    }
+
+    /// One left-associative precedence level: `operand ( operator operand )*`.
+    // [Note] Every binary level has the same shape, only the operators and the next level differ,
+    // so they share this helper. The loop (rather than recursion) is what makes them left-associative.
+    fn binary(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expr, ParseError>,
+        operators: &[(TokenType, BinaryOp)],
+    ) -> Result<Expr, ParseError> {
+        let mut expr = operand(self)?;
+
+        while let Some(&(_, operator)) = operators.iter().find(|(t, _)| self.check(*t)) {
+            self.advance();
+            let operator_span = self.previous.span;
+            let right = operand(self)?;
+            let span = expr.span().to(right.span());
+            expr = Expr::Binary(Binary {
+                left: Box::new(expr),
+                operator,
+                operator_span,
+                right: Box::new(right),
+                span,
+            });
+        }
+        Ok(expr)
+    }
+
+    /// Same as binary(), for the short-circuiting `and` and `or`.
+    fn logical(
+        &mut self,
+        operand: fn(&mut Self) -> Result<Expr, ParseError>,
+        token_type: TokenType,
+        operator: LogicalOp,
+    ) -> Result<Expr, ParseError> {
+        let mut expr = operand(self)?;
+
+        while self.matches(token_type) {
+            let operator_span = self.previous.span;
+            let right = operand(self)?;
+            let span = expr.span().to(right.span());
+            expr = Expr::Logical(Logical {
+                left: Box::new(expr),
+                operator,
+                operator_span,
+                right: Box::new(right),
+                span,
+            });
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let operator = match self.current.token_type {
+            TokenType::Bang => UnaryOp::Not,
+            TokenType::Minus => UnaryOp::Negate,
+            TokenType::Tilde => UnaryOp::BitNot,
+            _ => return self.call(),
+        };
+        self.advance();
+        let operator_span = self.previous.span;
+        let right = self.unary()?;
+
+        Ok(Expr::Unary(Unary {
+            operator,
+            operator_span,
+            span: operator_span.to(right.span()),
+            right: Box::new(right),
+        }))
+    }
+
+    fn call(&mut self) -> Result<Expr, ParseError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.matches(TokenType::LeftParen) {
+                expr = self.finish_call(expr)?;
+            } else if self.matches(TokenType::Dot) {
+                let name = self.identifier("property name after '.'")?;
+                expr = Expr::Get(Get {
+                    span: expr.span().to(name.span),
+                    object: Box::new(expr),
+                    name,
+                });
+            } else if self.matches(TokenType::LeftBracket) {
+                let index = self.expression()?;
+                self.consume(TokenType::RightBracket, "']' after index")?;
+                expr = Expr::Index(Index {
+                    span: expr.span().to(self.previous.span),
+                    object: Box::new(expr),
+                    index: Box::new(index),
+                });
+            } else {
+                break;
+            }
+        }
+        Ok(expr)
+    }
+
+    /// The arguments of a call, after its '(' has been consumed.
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParseError> {
+        let mut arguments = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                arguments.push(self.expression()?);
+                if !self.matches(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "')' after arguments")?;
+        self.check_limit(&arguments, Expr::span, "arguments");
+
+        Ok(Expr::Call(Call {
+            span: callee.span().to(self.previous.span),
+            callee: Box::new(callee),
+            arguments,
+        }))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let span = self.current.span;
+        let value = match self.current.token_type {
+            TokenType::False => LiteralValue::Bool(false),
+            TokenType::True => LiteralValue::Bool(true),
+            TokenType::Nil => LiteralValue::Nil,
+            TokenType::Number | TokenType::String => match &self.current.literal {
+                Some(tokenize::Literal::Number(value)) => LiteralValue::Number(*value),
+                Some(tokenize::Literal::String(value)) => LiteralValue::String(value.to_string()),
+                None => return Err(self.error_at_current("literal value")),
+            },
+            TokenType::This => {
+                self.advance();
+                return Ok(Expr::This(This {
+                    id: ExprId::next(),
+                    span,
+                }));
+            }
+            TokenType::Super => {
+                self.advance();
+                self.consume(TokenType::Dot, "'.' after 'super'")?;
+                let method = self.identifier("superclass method name")?;
+                return Ok(Expr::Super(Super {
+                    id: ExprId::next(),
+                    span: span.to(method.span),
+                    method,
+                }));
+            }
+            TokenType::Identifier => {
+                let name = self.identifier("variable name")?;
+                return Ok(Expr::Variable(Variable {
+                    id: ExprId::next(),
+                    name,
+                    span,
+                }));
+            }
+            TokenType::LeftParen => {
+                self.advance();
+                let expression = Box::new(self.expression()?);
+                self.consume(TokenType::RightParen, "')' after expression")?;
+                return Ok(Expr::Grouping(Grouping {
+                    expression,
+                    span: span.to(self.previous.span),
+                }));
+            }
+            TokenType::LeftBracket => {
+                self.advance();
+                let elements = self.elements(
+                    TokenType::RightBracket,
+                    "']' after list elements",
+                    Self::expression,
+                )?;
+                return Ok(Expr::List(List {
+                    elements,
+                    span: span.to(self.previous.span),
+                }));
+            }
+            TokenType::LeftBrace => {
+                self.advance();
+                let entries =
+                    self.elements(TokenType::RightBrace, "'}' after map entries", |parser| {
+                        let key = parser.expression()?;
+                        parser.consume(TokenType::Colon, "':' after map key")?;
+                        Ok((key, parser.expression()?))
+                    })?;
+                return Ok(Expr::Map(Map {
+                    entries,
+                    span: span.to(self.previous.span),
+                }));
+            }
+            _ => return Err(self.error_at_current("expression")),
+        };
+        self.advance();
+        Ok(Expr::Literal(Literal { value, span }))
+    }
+
+    // Helpers
+
+    /// The comma separated items of a list or map literal, up to and including the closing token.
+    /// A trailing comma is allowed, so literals written one item per line are easy to extend.
+    fn elements<T>(
+        &mut self,
+        close: TokenType,
+        expected: &str,
+        mut item: impl FnMut(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<Vec<T>, ParseError> {
+        let mut items = Vec::new();
+        while !self.check(close) {
+            items.push(item(self)?);
+            if !self.matches(TokenType::Comma) {
+                break;
+            }
+        }
+        self.consume(close, expected)?;
+        Ok(items)
+    }
+
+    /// Report a list of parameters or arguments that is longer than MAX_ARGUMENTS.
+    /// The list itself is fine to keep parsing with, so this does not unwind.
+    fn check_limit<T>(&mut self, items: &[T], span: impl Fn(&T) -> Span, what: &str) {
+        if items.len() > MAX_ARGUMENTS {
+            let extra = span(&items[MAX_ARGUMENTS]).to(span(&items[items.len() - 1]));
+            self.errors.push(ParseError {
+                span: extra,
+                expected: format!("at most {} {}", MAX_ARGUMENTS, what),
+                found: format!("{} {}", items.len(), what),
+            });
+        }
+    }
+
+    /// Skip tokens until the start of the next statement, after a syntax error.
+    // [Note] This is "panic mode" recovery. The tokens up to a statement boundary are thrown away,
+    // because errors in them are most likely knock-on effects of the first one.
+    fn synchronize(&mut self) {
+        while !self.is_at_end() {
+            // The error may have stopped right on a keyword that starts the next statement
+            if matches!(
+                self.current.token_type,
+                TokenType::Class
+                    | TokenType::Fun
+                    | TokenType::Var
+                    | TokenType::For
+                    | TokenType::If
+                    | TokenType::While
+                    | TokenType::Print
+                    | TokenType::Return
+            ) {
+                return;
+            }
+            self.advance();
+            if self.previous.token_type == TokenType::Semicolon {
+                return;
+            }
+        }
+    }
+
+    /// Consume an identifier token and turn it into an AST identifier.
+    fn identifier(&mut self, expected: &str) -> Result<Identifier, ParseError> {
+        self.consume(TokenType::Identifier, expected)?;
+        Ok(Identifier {
+            name: self.previous.lexeme.to_string(),
+            span: self.previous.span,
+        })
+    }
+
+    /// Consume the current token if it has the expected type, otherwise fail with what was `expected`.
+    fn consume(&mut self, token_type: TokenType, expected: &str) -> Result<(), ParseError> {
+        if self.check(token_type) {
+            self.advance();
+            Ok(())
+        } else {
+            Err(self.error_at_current(expected))
+        }
+    }
+
+    /// Consume the current token if it has the given type.
+    fn matches(&mut self, token_type: TokenType) -> bool {
+        if self.check(token_type) {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        self.current.token_type == token_type
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.check(TokenType::EOF)
+    }
+
+    /// Move on to the next token, setting aside any lexical errors on the way.
+    fn advance(&mut self) {
+        let next = loop {
+            match self.tokens.next() {
+                // Comments are only there for tools that asked the tokenizer to keep them
+                Some(Ok(token)) if token.token_type == TokenType::Comment => continue,
+                Some(Ok(token)) => break token,
+                Some(Err(error)) => self.lex_errors.push(error),
+                // The tokenizer is done after EOF. Stay on EOF from here on
+                None => break self.current.clone(),
+            }
+        };
+        self.previous = mem::replace(&mut self.current, next);
+    }
+
+    fn error_at_current(&self, expected: &str) -> ParseError {
+        ParseError {
+            span: self.current.span,
+            expected: String::from(expected),
+            found: self.describe(self.current.span),
+        }
+    }
+
+    /// How a piece of source shows up in an error message: quoted, or "end" at the end of input.
+    fn describe(&self, span: Span) -> String {
+        if span.start == span.end {
+            String::from("end")
+        } else {
+            format!("'{}'", &self.tokens.source()[span.start..span.end])
+        }
+    }
+}
+
+/// Tests for the parser
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse a program and print every statement as an S-expression.
+    fn parse(source: &str) -> Vec<String> {
+        let mut parser = Parser::new(Tokenizer::new(source));
+        let statements = parser.parse().expect("source should parse");
+        statements.iter().map(|s| s.to_string()).collect()
+    }
+
+    /// Parse a broken program and print every syntax error it reports.
+    fn parse_errors(source: &str) -> Vec<String> {
+        let mut parser = Parser::new(Tokenizer::new(source));
+        let errors = parser.parse().expect_err("source should not parse");
+        errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(parse("1 + 2 * 3;"), vec!["(; (+ 1 (* 2 3)))"]);
+        assert_eq!(parse("1 - 2 - 3;"), vec!["(; (- (- 1 2) 3))"]);
+        assert_eq!(parse("(1 + 2) * 3;"), vec!["(; (* (group (+ 1 2)) 3))"]);
+        assert_eq!(
+            parse("!a == b < -c / 2;"),
+            vec!["(; (== (! a) (< b (/ (- c) 2))))"]
+        );
+        assert_eq!(
+            parse("a or b and c or d;"),
+            vec!["(; (or (or a (and b c)) d))"]
+        );
+        assert_eq!(parse("a = b = 1 >= 2;"), vec!["(; (= a (= b (>= 1 2))))"]);
+        assert_eq!(parse("!!true;"), vec!["(; (! (! true)))"]);
+    }
+
+    #[test]
+    fn bitwise_precedence() {
+        // C's order, loosest first: | ^ & then equality, comparison, shifts, + and -
+        assert_eq!(parse("a | b ^ c & d;"), vec!["(; (| a (^ b (& c d))))"]);
+        assert_eq!(parse("a & b == c;"), vec!["(; (& a (== b c)))"]);
+        assert_eq!(
+            parse("1 << 2 + 3 < 4 >> 1;"),
+            vec!["(; (< (<< 1 (+ 2 3)) (>> 4 1)))"]
+        );
+        assert_eq!(parse("~a & ~-b;"), vec!["(; (& (~ a) (~ (- b))))"]);
+        assert_eq!(parse("a and b | c;"), vec!["(; (and a (| b c)))"]);
+        assert_eq!(parse("a << b << c;"), vec!["(; (<< (<< a b) c))"]);
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(
+            parse("print nil; print false; print 0x10; print \"Hello, World\";"),
+            vec![
+                "(print nil)",
+                "(print false)",
+                "(print 16)",
+                "(print \"Hello, World\")"
+            ]
+        );
+    }
+
+    #[test]
+    fn calls_and_properties() {
+        assert_eq!(
+            parse("a.b(1, c)(2).d = this.e;"),
+            vec!["(; (= (. (call (call (. a b) 1 c) 2) d) (. this e)))"]
+        );
+        assert_eq!(parse("f();"), vec!["(; (call f))"]);
+    }
+
+    #[test]
+    fn lists_maps_and_indexing() {
+        assert_eq!(
+            parse("print [1, [], \"a\",]; print {\"k\": [2], 1: {}};"),
+            vec![
+                "(print (list 1 (list) \"a\"))",
+                "(print (map (\"k\" (list 2)) (1 (map))))"
+            ]
+        );
+        assert_eq!(
+            parse("a[i + 1][0] = m[\"k\"].len();"),
+            vec!["(; (= ([] ([] a (+ i 1)) 0) (call (. ([] m \"k\") len))))"]
+        );
+        assert_eq!(
+            parse("for (var x in xs) print x; for (var i = 0; i < 1;) {}"),
+            vec![
+                "(for x in xs (print x))",
+                "(block (var i 0) (while (< i 1) (block)))"
+            ]
+        );
+        assert_eq!(
+            parse_errors("print {\"k\" 1};\nprint [1, 2;"),
+            vec![
+                "[line 1, column 12] Error: Expected ':' after map key, found '1'.",
+                "[line 2, column 12] Error: Expected ']' after list elements, found ';'."
+            ]
+        );
+        assert_eq!(
+            parse_errors("for (var x in xs;) {}")[0],
+            "[line 1, column 17] Error: Expected ')' after for-in iterable, found ';'."
+        );
+    }
+
+    #[test]
+    fn declarations_and_blocks() {
+        assert_eq!(
+            parse("var greet = \"Hello, World\"; var empty; { var x = 1; print x; }"),
+            vec![
+                "(var greet \"Hello, World\")",
+                "(var empty)",
+                "(block (var x 1) (print x))"
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        assert_eq!(
+            parse("if (a) if (b) print 1; else print 2;"),
+            vec!["(if a (if b (print 1) (print 2)))"]
+        );
+        assert_eq!(
+            parse("while (i < 3) i = i + 1;"),
+            vec!["(while (< i 3) (; (= i (+ i 1))))"]
+        );
+    }
+
+    #[test]
+    fn for_loops_are_desugared() {
+        assert_eq!(
+            parse("for (var i = 0; i < 3; i = i + 1) print i;"),
+            vec!["(block (var i 0) (while (< i 3) (print i) (= i (+ i 1))))"]
+        );
+        assert_eq!(parse("for (;;) f();"), vec!["(while true (; (call f)))"]);
+    }
+
+    #[test]
+    fn break_and_continue() {
+        assert_eq!(
+            parse("while (true) { if (a) break; continue; }"),
+            vec!["(while true (block (if a (break)) (continue)))"]
+        );
+        assert_eq!(
+            parse("for (;;) for (;;) break;"),
+            vec!["(while true (while true (break)))"]
+        );
+    }
+
+    #[test]
+    fn break_and_continue_outside_loops() {
+        let source = "\
+break;
+if (true) continue;
+while (true) { fun f() { break; } }
+for (;;) {}
+continue;";
+
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "[line 1, column 1] Error: Expected 'break' to be inside a loop, found 'break' outside of any loop.",
+                "[line 2, column 11] Error: Expected 'continue' to be inside a loop, found 'continue' outside of any loop.",
+                "[line 3, column 26] Error: Expected 'break' to be inside a loop, found 'break' outside of any loop.",
+                "[line 5, column 1] Error: Expected 'continue' to be inside a loop, found 'continue' outside of any loop.",
+            ]
+        );
+    }
+
+    #[test]
+    fn functions_and_classes() {
+        assert_eq!(
+            parse(
+                "fun returnFunction() {
+                    var outside = \"outside\";
+                    fun inner() { print outside; }
+                    return inner;
+                }"
+            ),
+            vec![
+                "(fun returnFunction () (var outside \"outside\") (fun inner () (print outside)) (return inner))"
+            ]
+        );
+        assert_eq!(
+            parse("class Breakfast { cook() { return; } serve(who) { print who; } }"),
+            vec!["(class Breakfast (fun cook () (return)) (fun serve (who) (print who)))"]
+        );
+        assert_eq!(
+            parse("class Brunch < Breakfast { cook() { super.cook(); } }"),
+            vec!["(class Brunch < Breakfast (fun cook () (; (call (super cook)))))"]
+        );
+    }
+
+    #[test]
+    fn nodes_carry_spans() {
+        let source = "print 1 +\n  foo(2);";
+        let mut parser = Parser::new(Tokenizer::new(source));
+        let statements = parser.parse().unwrap();
+
+        assert_eq!(statements[0].span(), Span::new(0, source.len(), 1, 1));
+        let Stmt::Print(print) = &statements[0] else {
+            panic!("expected a print statement");
+        };
+        let Expr::Binary(binary) = &print.expression else {
+            panic!("expected a binary expression");
+        };
+        assert_eq!(binary.span, Span::new(6, 18, 1, 7));
+        assert_eq!(binary.operator_span, Span::new(8, 9, 1, 9));
+        assert_eq!(binary.right.span(), Span::new(12, 18, 2, 3));
+        assert_eq!(
+            &source[binary.right.span().start..binary.right.span().end],
+            "foo(2)"
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            parse_errors("print 1"),
+            vec!["[line 1, column 8] Error: Expected ';' after value, found end."]
+        );
+        assert_eq!(
+            parse_errors("var 1 = 2;"),
+            vec!["[line 1, column 5] Error: Expected variable name, found '1'."]
+        );
+        assert_eq!(
+            parse_errors("a + b = c;"),
+            vec!["[line 1, column 1] Error: Expected a variable or property before '=', found 'a + b'."]
+        );
+        assert_eq!(
+            parse_errors("print (1;"),
+            vec!["[line 1, column 9] Error: Expected ')' after expression, found ';'."]
+        );
+    }
+
+    #[test]
+    fn recovers_at_statement_boundaries() {
+        let source = "\
+var a = ;
+print a
+var b = 2;
+fun (x) { return x; }
+while (true) print b;
+class { }
+print );";
+
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "[line 1, column 9] Error: Expected expression, found ';'.",
+                "[line 3, column 1] Error: Expected ';' after value, found 'var'.",
+                "[line 4, column 5] Error: Expected function name, found '('.",
+                // [Note] Recovery resumes at `return`, so the body's closing brace is left dangling
+                "[line 4, column 21] Error: Expected expression, found '}'.",
+                "[line 6, column 7] Error: Expected class name, found '{'.",
+                "[line 7, column 7] Error: Expected expression, found ')'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn recovers_inside_blocks() {
+        let source = "\
+fun f() {
+  var x = 1 +;
+  print x;
+  if (x print x;
+}
+f(;";
+
+        assert_eq!(
+            parse_errors(source),
+            vec![
+                "[line 2, column 14] Error: Expected expression, found ';'.",
+                "[line 4, column 9] Error: Expected ')' after if condition, found 'print'.",
+                "[line 6, column 3] Error: Expected expression, found ';'.",
+            ]
+        );
+    }
+
+    #[test]
+    fn errors_that_do_not_need_recovery() {
+        // Bad assignment targets and overlong argument lists are reported, and parsing carries on
+        let arguments = vec!["1"; MAX_ARGUMENTS + 2].join(", ");
+        let source = format!("1 = 2;\nf({});\n(a) = 3;\nprint 4", arguments);
+
+        assert_eq!(
+            parse_errors(&source),
+            vec![
+                "[line 1, column 1] Error: Expected a variable or property before '=', found '1'.",
+                "[line 2, column 768] Error: Expected at most 255 arguments, found 257 arguments.",
+                "[line 3, column 1] Error: Expected a variable or property before '=', found '(a)'.",
+                "[line 4, column 8] Error: Expected ';' after value, found end.",
+            ]
+        );
+    }
+
+    #[test]
+    fn single_expressions() {
+        let mut parser = Parser::new(Tokenizer::new("a = 1 + 2"));
+        assert_eq!(
+            parser.parse_expression().unwrap().to_string(),
+            "(= a (+ 1 2))"
+        );
+
+        let mut parser = Parser::new(Tokenizer::new("1 + 2;"));
+        let errors = parser.parse_expression().unwrap_err();
+        assert_eq!(
+            errors[0].to_string(),
+            "[line 1, column 6] Error: Expected end of expression, found ';'."
+        );
+    }
+
+    #[test]
+    fn lexical_errors_are_set_aside() {
+        let mut parser = Parser::new(Tokenizer::new("print 1 @ ;"));
+        let statements = parser.parse().unwrap();
+
+        assert_eq!(statements.len(), 1);
+        assert_eq!(parser.lex_errors().len(), 1);
+    }
+}
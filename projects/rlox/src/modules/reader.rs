@@ -1,22 +1,33 @@
+use crate::modules::diagnostic::Diagnostic;
 use std::fs;
 use std::io;
 use std::io::Read;
 
 /// Read file and return contents if possible.
-pub fn read_source(filename: &String) -> Result<String, Box<dyn std::error::Error>> {
+// [Note] The error is boxed so a large Diagnostic does not make every Result as big as itself
+pub fn read_source(filename: &str) -> Result<String, Box<Diagnostic>> {
     // We use `match` here to handle both successful and error cases.
     let mut source = match fs::File::open(filename) {
         Ok(source) => source,
         // Handle errors accessing file on filesystem
         Err(error) => match error.kind() {
             io::ErrorKind::NotFound => {
-                return Err(format!("ERROR: File '{}' not found.", filename).into());
+                return Err(Box::new(Diagnostic::error(
+                    "E001",
+                    format!("File '{}' not found.", filename),
+                )));
             }
             io::ErrorKind::PermissionDenied => {
-                return Err(format!("ERROR: No permission to read file '{}'.", filename).into());
+                return Err(Box::new(Diagnostic::error(
+                    "E002",
+                    format!("No permission to read file '{}'.", filename),
+                )));
             }
             _ => {
-                return Err(format!("ERROR: Unable to open file '{}': {}", filename, error).into());
+                return Err(Box::new(Diagnostic::error(
+                    "E003",
+                    format!("Unable to open file '{}': {}", filename, error),
+                )));
             }
         },
     };
@@ -27,16 +38,59 @@ pub fn read_source(filename: &String) -> Result<String, Box<dyn std::error::Erro
     if let Err(error) = source.read_to_string(&mut contents) {
         // Ff no issues with don't enter the below, move to returning contents as our Ok value.
         if error.kind() == io::ErrorKind::InvalidData {
-            return Err(
-                format!("ERROR: File '{}' is not a valid UTF-8 text file.", filename).into(),
-            );
+            return Err(Box::new(Diagnostic::error(
+                "E004",
+                format!("File '{}' is not a valid UTF-8 text file.", filename),
+            )));
         } else {
-            return Err(format!("ERROR: Failed to read file '{}': {}", filename, error).into());
+            return Err(Box::new(Diagnostic::error(
+                "E005",
+                format!("Failed to read file '{}': {}", filename, error),
+            )));
         }
     }
-    // Debug output
-    dbg!(&contents);
 
     // Send the contents on
     Ok(contents)
 }
+
+/// Read a whole script from standard input, for `rlox -`.
+pub fn read_stdin() -> Result<String, Box<Diagnostic>> {
+    let mut contents = String::new();
+    if let Err(error) = io::stdin().read_to_string(&mut contents) {
+        if error.kind() == io::ErrorKind::InvalidData {
+            return Err(Box::new(Diagnostic::error(
+                "E004",
+                "Standard input is not valid UTF-8 text.",
+            )));
+        } else {
+            return Err(Box::new(Diagnostic::error(
+                "E005",
+                format!("Failed to read standard input: {}", error),
+            )));
+        }
+    }
+    Ok(contents)
+}
+
+/// Write `contents` to a file, replacing anything already in it.
+pub fn write_file(filename: &str, contents: &str) -> Result<(), Box<Diagnostic>> {
+    // [Note] The same error categories as reading, so scripts see the same kinds of messages
+    if let Err(error) = fs::write(filename, contents) {
+        let diagnostic = match error.kind() {
+            io::ErrorKind::NotFound => {
+                Diagnostic::error("E001", format!("Folder for file '{}' not found.", filename))
+            }
+            io::ErrorKind::PermissionDenied => Diagnostic::error(
+                "E002",
+                format!("No permission to write file '{}'.", filename),
+            ),
+            _ => Diagnostic::error(
+                "E005",
+                format!("Failed to write file '{}': {}", filename, error),
+            ),
+        };
+        return Err(Box::new(diagnostic));
+    }
+    Ok(())
+}
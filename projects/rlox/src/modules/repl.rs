@@ -0,0 +1,338 @@
+use crate::modules::ast::{Print, Stmt};
+use crate::modules::compiler::Compiler;
+use crate::modules::diagnostic::Diagnostic;
+use crate::modules::interpreter::Interpreter;
+use crate::modules::parser::Parser;
+use crate::modules::resolver::Resolver;
+use crate::modules::tokenize::{LexErrorKind, TokenType, Tokenizer};
+use crate::modules::vm::Vm;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::env;
+use std::mem;
+use std::path::PathBuf;
+// [Note] REPL: Read a line, Evaluate it, Print the result, Loop. Started when rlox is given no file.
+// Every line runs in the same interpreter, so declarations stay around for the lines after them.
+
+/// Prompt for a new piece of input
+pub const PROMPT: &str = "> ";
+
+/// Prompt for the next line of input that is not complete yet, e.g. inside an open `{`
+pub const CONTINUATION_PROMPT: &str = "... ";
+
+/// Name of the file, in the home directory, that keeps the input history between sessions
+pub const HISTORY_FILE: &str = ".rlox_history";
+
+/// What runs the input of a session. Either one keeps the globals of every earlier input.
+enum Backend {
+    Tree(Interpreter),
+    Vm(Vm),
+}
+
+/// Struct representing a REPL session: an interpreter that outlives each piece of input.
+pub struct Session {
+    backend: Backend,
+    /// Set once the input calls `exit(code)`
+    exit_code: Option<i32>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    /// Create a session that prints to stdout.
+    pub fn new() -> Self {
+        Self::with_interpreter(Interpreter::new())
+    }
+
+    /// Create a session that runs its input on `interpreter`.
+    pub fn with_interpreter(interpreter: Interpreter) -> Self {
+        Session {
+            backend: Backend::Tree(interpreter),
+            exit_code: None,
+        }
+    }
+
+    /// Create a session that compiles its input to bytecode and runs it on `vm`.
+    pub fn with_vm(vm: Vm) -> Self {
+        Session {
+            backend: Backend::Vm(vm),
+            exit_code: None,
+        }
+    }
+
+    /// Whether `source` is complete, or the REPL should read more lines before running it.
+    // [Note] Input is incomplete while it has more '{', '(' or '[' than closing ones, or ends inside a
+    // string or block comment. Counting tokens, not characters, skips brackets inside strings.
+    pub fn is_complete(source: &str) -> bool {
+        let mut depth: i64 = 0;
+        for result in Tokenizer::new(source) {
+            match result {
+                Ok(token) => match token.token_type {
+                    TokenType::LeftBrace | TokenType::LeftParen | TokenType::LeftBracket => {
+                        depth += 1
+                    }
+                    TokenType::RightBrace | TokenType::RightParen | TokenType::RightBracket => {
+                        depth -= 1
+                    }
+                    _ => {}
+                },
+                Err(error) => {
+                    if matches!(
+                        error.kind,
+                        LexErrorKind::UnterminatedString | LexErrorKind::UnterminatedComment
+                    ) {
+                        return false;
+                    }
+                }
+            }
+        }
+        depth <= 0
+    }
+
+    /// Run one piece of input. Returns the errors and warnings to show, the session carries on regardless.
+    pub fn run(&mut self, source: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+
+        let mut parser = Parser::new(Tokenizer::new(source));
+        let result = parser.parse();
+        let statements = match (result, Self::bare_expression(source)) {
+            (Ok(statements), _) if parser.lex_errors().is_empty() => statements,
+            // Not a program, but a bare expression (no ';'): print its value
+            (_, Some(statement)) => vec![statement],
+            (result, None) => {
+                diagnostics.extend(parser.lex_errors().iter().map(Diagnostic::from));
+                if let Err(errors) = result {
+                    diagnostics.extend(errors.iter().map(Diagnostic::from));
+                }
+                return diagnostics;
+            }
+        };
+
+        let mut resolver = Resolver::new();
+        let result = resolver.resolve(&statements);
+        diagnostics.extend(resolver.warnings().iter().map(Diagnostic::from));
+        let locals = match result {
+            Ok(locals) => locals,
+            Err(errors) => {
+                diagnostics.extend(errors.iter().map(Diagnostic::from));
+                return diagnostics;
+            }
+        };
+
+        let result = match &mut self.backend {
+            Backend::Tree(interpreter) => {
+                interpreter.resolve(locals);
+                interpreter.interpret(&statements)
+            }
+            Backend::Vm(vm) => match Compiler::new().compile(&statements) {
+                Ok(script) => vm.interpret(script),
+                Err(errors) => {
+                    diagnostics.extend(errors.iter().map(Diagnostic::from));
+                    return diagnostics;
+                }
+            },
+        };
+        match result {
+            Ok(()) => {}
+            Err(error) if error.exit_code.is_some() => self.exit_code = error.exit_code,
+            Err(error) => diagnostics.push(Diagnostic::from(&error)),
+        }
+        diagnostics
+    }
+
+    /// The status the input asked to exit with, if it called `exit(code)`.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// If `source` is a single expression, a print statement for it.
+    fn bare_expression(source: &str) -> Option<Stmt> {
+        let mut parser = Parser::new(Tokenizer::new(source));
+        let expression = parser.parse_expression().ok()?;
+        if !parser.lex_errors().is_empty() {
+            return None;
+        }
+        Some(Stmt::Print(Print {
+            span: expression.span(),
+            expression,
+        }))
+    }
+}
+
+/// Where the input history is kept, if there is a home directory.
+fn history_path() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Run the interactive prompt on `session` until end of input (Ctrl-D) or `exit(code)`, returning the
+/// exit status. Ctrl-C abandons the current input. Errors and warnings are handed to `report` along with
+/// the input that caused them.
+pub fn run_repl(
+    mut session: Session,
+    report: impl Fn(Diagnostic, &str),
+) -> Result<i32, ReadlineError> {
+    // [Note] rustyline provides line editing (arrow keys, Ctrl-R search...) and the history
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+    if let Some(path) = &history {
+        // There is no history file yet the first time the REPL runs
+        let _ = editor.load_history(path);
+    }
+
+    let mut input = String::new();
+    loop {
+        let prompt = if input.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                input.push_str(&line);
+                input.push('\n');
+                if !Session::is_complete(&input) {
+                    continue;
+                }
+
+                let source = mem::take(&mut input);
+                if source.trim().is_empty() {
+                    continue;
+                }
+                editor.add_history_entry(source.trim_end())?;
+                for diagnostic in session.run(&source) {
+                    report(diagnostic, &source);
+                }
+                if session.exit_code().is_some() {
+                    break;
+                }
+            }
+            Err(ReadlineError::Interrupted) => input.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(error) => return Err(error),
+        }
+    }
+
+    if let Some(path) = &history {
+        editor.save_history(path)?;
+    }
+    Ok(session.exit_code().unwrap_or(0))
+}
+
+/// Tests for the REPL session
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::interpreter::tests::SharedBuffer;
+
+    fn run(session: &mut Session, source: &str) -> Vec<String> {
+        session.run(source).iter().map(|d| d.to_string()).collect()
+    }
+
+    fn session() -> (Session, SharedBuffer) {
+        let out = SharedBuffer::default();
+        let interpreter = Interpreter::with_output(Box::new(out.clone()));
+        (Session::with_interpreter(interpreter), out)
+    }
+
+    #[test]
+    fn globals_persist_between_inputs() {
+        let (mut session, out) = session();
+        assert!(session.run("var a = 1;").is_empty());
+        assert!(session.run("fun inc() { a = a + 1; }").is_empty());
+        assert!(session.run("inc(); inc();").is_empty());
+        assert!(session.run("print a;").is_empty());
+        assert_eq!(out.contents(), "3\n");
+    }
+
+    #[test]
+    fn bare_expressions_are_printed() {
+        let (mut session, out) = session();
+        session.run("var greeting = \"hi\";");
+        assert!(session.run("1 + 2").is_empty());
+        assert!(session.run("greeting + \" there\"").is_empty());
+        // With a ';' it is an ordinary expression statement
+        assert!(session.run("1 + 2;").is_empty());
+        assert_eq!(out.contents(), "3\nhi there\n");
+    }
+
+    #[test]
+    fn errors_do_not_end_the_session() {
+        let (mut session, out) = session();
+        assert_eq!(
+            run(&mut session, "print missing;"),
+            vec!["[line 1, column 7] Error: Undefined variable 'missing'."]
+        );
+        assert_eq!(
+            run(&mut session, "var = 1;"),
+            vec!["[line 1, column 5] Error: Expected variable name, found '='."]
+        );
+        assert_eq!(
+            run(&mut session, "return 1;"),
+            vec!["[line 1, column 1] Error: Can't return from top-level code."]
+        );
+        assert_eq!(
+            run(&mut session, "fun f() { var unused; }"),
+            vec!["[line 1, column 15] Warning: Local variable 'unused' is never used."]
+        );
+        assert!(session.run("print \"still here\";").is_empty());
+        assert_eq!(out.contents(), "still here\n");
+    }
+
+    #[test]
+    fn closures_from_earlier_inputs_keep_their_bindings() {
+        let (mut session, out) = session();
+        session
+            .run("fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }");
+        session.run("var next = counter();");
+        session.run("next();");
+        session.run("next()");
+        assert_eq!(out.contents(), "2\n");
+    }
+
+    #[test]
+    fn exit_ends_the_session() {
+        let (mut session, out) = session();
+        assert!(session.run("print 1; exit(2); print 3;").is_empty());
+        assert_eq!(session.exit_code(), Some(2));
+        assert_eq!(out.contents(), "1\n");
+    }
+
+    #[test]
+    fn vm_sessions_keep_globals_between_inputs() {
+        let out = SharedBuffer::default();
+        let mut session = Session::with_vm(Vm::with_output(Box::new(out.clone())));
+        assert!(session.run("var a = 1;").is_empty());
+        assert!(session.run("fun inc() { a = a + 1; }").is_empty());
+        assert!(session.run("inc(); inc();").is_empty());
+        assert!(session.run("a").is_empty());
+        assert_eq!(
+            run(&mut session, "print missing;"),
+            vec!["[line 1, column 7] Error: Undefined variable 'missing'."]
+        );
+        assert!(session.run("exit(a);").is_empty());
+        assert_eq!(session.exit_code(), Some(3));
+        assert_eq!(out.contents(), "3\n");
+    }
+
+    #[test]
+    fn incomplete_input() {
+        assert!(Session::is_complete("print 1;"));
+        assert!(Session::is_complete(""));
+        assert!(!Session::is_complete("fun f() {\n"));
+        assert!(!Session::is_complete("if (a) {\n  print a;\n"));
+        assert!(Session::is_complete("if (a) {\n  print a;\n}\n"));
+        assert!(!Session::is_complete("print (1 +\n"));
+        assert!(!Session::is_complete("print \"multi\nline"));
+        assert!(!Session::is_complete("/* still\ncommenting"));
+        // Brackets inside strings and comments do not count
+        assert!(Session::is_complete("print \"{\"; // {"));
+        // Too many closing brackets is an error for the parser to report, not a reason to wait
+        assert!(Session::is_complete("}"));
+    }
+}
@@ -0,0 +1,643 @@
+use crate::modules::ast::*;
+use crate::modules::diagnostic::Label;
+use crate::modules::tokenize::Span;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
+// Resolving and Binding: Chapter 11 of "Crafting Interpreters"
+// [Note] The resolver walks the whole AST once, between parsing and running it. For every use of a
+// local variable it works out how many scopes out the declaration is, so the interpreter can go
+// straight to the right environment instead of searching the chain by name at run time. Searching by
+// name gets closures wrong: a declaration added to an enclosing block after the closure was created
+// would shadow the variable the closure was meant to see.
+// Anything not found in a local scope is assumed to be a global, and left to the interpreter.
+
+/// Struct representing a mistake the resolver found in the program, which stops it from running.
+#[derive(Debug, PartialEq)]
+pub struct ResolveError {
+    pub span: Span,
+    pub message: String,
+    /// Other code that explains the error, e.g. the earlier declaration of a name declared twice
+    pub label: Option<Label>,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, column {}] Error: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ResolveError {}
+
+/// Struct representing suspicious code that does not stop the program from running.
+#[derive(Debug, PartialEq)]
+pub struct Warning {
+    pub span: Span,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, column {}] Warning: {}",
+            self.span.line, self.span.column, self.message
+        )
+    }
+}
+
+/// How many scopes out from its use each local variable was declared. Globals are not included.
+pub type Locals = HashMap<ExprId, usize>;
+
+/// Enum representing the kind of function body being resolved, to check `return`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FunctionKind {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+/// Enum representing the kind of class body being resolved, to check `this`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ClassKind {
+    None,
+    Class,
+    Subclass,
+}
+
+/// Struct representing what the resolver knows about a local variable.
+#[derive(Debug)]
+struct Local {
+    span: Span,
+    /// False between the declaration and the end of its initializer
+    defined: bool,
+    /// Whether the variable is ever read
+    used: bool,
+    /// Whether to warn if it is never read. Parameters and `this` are left out.
+    check_unused: bool,
+}
+
+/// The resolver machine, responsible for binding variable uses to their declarations.
+#[derive(Debug)]
+pub struct Resolver {
+    // [Note] A stack of the block scopes around the code being resolved, innermost last
+    scopes: Vec<HashMap<String, Local>>,
+    function: FunctionKind,
+    class: ClassKind,
+    locals: Locals,
+    errors: Vec<ResolveError>,
+    warnings: Vec<Warning>,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver {
+    // Resolver constructor
+    pub fn new() -> Self {
+        Resolver {
+            scopes: Vec::new(),
+            function: FunctionKind::None,
+            class: ClassKind::None,
+            locals: Locals::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Main entry point for resolving. Returns the depth of every local variable use, or every error found.
+    pub fn resolve(&mut self, statements: &[Stmt]) -> Result<Locals, Vec<ResolveError>> {
+        self.resolve_statements(statements);
+        self.warnings.sort_by_key(|warning| warning.span.start);
+
+        if self.errors.is_empty() {
+            Ok(mem::take(&mut self.locals))
+        } else {
+            self.errors.sort_by_key(|error| error.span.start);
+            Err(mem::take(&mut self.errors))
+        }
+    }
+
+    /// The warnings found while resolving.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // Statements
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            self.resolve_statement(statement);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Stmt) {
+        match statement {
+            Stmt::Expression(s) => self.resolve_expression(&s.expression),
+            Stmt::Print(s) => self.resolve_expression(&s.expression),
+            Stmt::Var(s) => {
+                // [Note] Declared before and defined after the initializer, so the initializer
+                // can tell when it refers to the variable being declared
+                self.declare(&s.name, true);
+                if let Some(initializer) = &s.initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(&s.name);
+            }
+            Stmt::Block(s) => {
+                self.begin_scope();
+                self.resolve_statements(&s.statements);
+                self.end_scope();
+            }
+            Stmt::If(s) => {
+                self.resolve_expression(&s.condition);
+                self.resolve_statement(&s.then_branch);
+                if let Some(else_branch) = &s.else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            Stmt::While(s) => {
+                self.resolve_expression(&s.condition);
+                self.resolve_statement(&s.body);
+                if let Some(increment) = &s.increment {
+                    self.resolve_expression(increment);
+                }
+            }
+            Stmt::ForIn(s) => {
+                self.resolve_expression(&s.iterable);
+                // [Note] The loop variable gets a scope of its own, a fresh one for every element at
+                // run time. A loop that only counts its elements need not use it, so no warning
+                self.begin_scope();
+                self.declare(&s.name, false);
+                self.define(&s.name);
+                self.resolve_statement(&s.body);
+                self.end_scope();
+            }
+            Stmt::Break(_) | Stmt::Continue(_) => {}
+            Stmt::Function(s) => {
+                // Defined straight away, so the function can call itself
+                self.declare(&s.name, true);
+                self.define(&s.name);
+                self.resolve_function(s, FunctionKind::Function);
+            }
+            Stmt::Return(s) => {
+                if self.function == FunctionKind::None {
+                    self.error(s.span, "Can't return from top-level code.");
+                }
+                if let Some(value) = &s.value {
+                    if self.function == FunctionKind::Initializer {
+                        self.error(s.span, "Can't return a value from an initializer.");
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            Stmt::Class(s) => {
+                let enclosing = mem::replace(&mut self.class, ClassKind::Class);
+                self.declare(&s.name, true);
+                self.define(&s.name);
+
+                if let Some(superclass) = &s.superclass {
+                    if superclass.name.name == s.name.name {
+                        self.error(superclass.span, "A class can't inherit from itself.");
+                    }
+                    self.class = ClassKind::Subclass;
+                    self.resolve_local(superclass.id, &superclass.name.name, true);
+
+                    // Methods of a subclass are closures over a scope that holds `super`...
+                    self.begin_scope();
+                    self.declare_implicit("super", superclass.span);
+                }
+
+                // ...and methods of every class over a scope that holds `this`
+                self.begin_scope();
+                self.declare_implicit("this", s.name.span);
+                for method in &s.methods {
+                    let kind = if method.name.name == "init" {
+                        FunctionKind::Initializer
+                    } else {
+                        FunctionKind::Method
+                    };
+                    self.resolve_function(method, kind);
+                }
+                self.end_scope();
+                if s.superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.class = enclosing;
+            }
+        }
+    }
+
+    fn resolve_function(&mut self, function: &Function, kind: FunctionKind) {
+        let enclosing = mem::replace(&mut self.function, kind);
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param, false);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+        self.function = enclosing;
+    }
+
+    // Expressions
+
+    fn resolve_expression(&mut self, expression: &Expr) {
+        match expression {
+            Expr::Literal(_) => {}
+            Expr::Grouping(e) => self.resolve_expression(&e.expression),
+            Expr::Unary(e) => self.resolve_expression(&e.right),
+            Expr::Binary(e) => {
+                self.resolve_expression(&e.left);
+                self.resolve_expression(&e.right);
+            }
+            Expr::Logical(e) => {
+                self.resolve_expression(&e.left);
+                self.resolve_expression(&e.right);
+            }
+            Expr::Variable(e) => {
+                let declared_here = self.scopes.last().and_then(|scope| scope.get(&e.name.name));
+                if let Some(local) = declared_here.filter(|local| !local.defined) {
+                    let label = Label {
+                        span: local.span,
+                        message: String::from("declared here"),
+                    };
+                    self.errors.push(ResolveError {
+                        span: e.name.span,
+                        message: String::from("Can't read local variable in its own initializer."),
+                        label: Some(label),
+                    });
+                }
+                self.resolve_local(e.id, &e.name.name, true);
+            }
+            Expr::Assign(e) => {
+                self.resolve_expression(&e.value);
+                // [Note] Assigning to a variable does not count as using it
+                self.resolve_local(e.id, &e.name.name, false);
+            }
+            Expr::Call(e) => {
+                self.resolve_expression(&e.callee);
+                for argument in &e.arguments {
+                    self.resolve_expression(argument);
+                }
+            }
+            // [Note] Properties are looked up dynamically, only the object expression needs resolving
+            Expr::Get(e) => self.resolve_expression(&e.object),
+            Expr::Set(e) => {
+                self.resolve_expression(&e.value);
+                self.resolve_expression(&e.object);
+            }
+            Expr::List(e) => {
+                for element in &e.elements {
+                    self.resolve_expression(element);
+                }
+            }
+            Expr::Map(e) => {
+                for (key, value) in &e.entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            Expr::Index(e) => {
+                self.resolve_expression(&e.object);
+                self.resolve_expression(&e.index);
+            }
+            Expr::SetIndex(e) => {
+                self.resolve_expression(&e.object);
+                self.resolve_expression(&e.index);
+                self.resolve_expression(&e.value);
+            }
+            Expr::This(e) => {
+                if self.class == ClassKind::None {
+                    self.error(e.span, "Can't use 'this' outside of a class.");
+                    return;
+                }
+                self.resolve_local(e.id, "this", true);
+            }
+            Expr::Super(e) => match self.class {
+                ClassKind::None => {
+                    self.error(e.span, "Can't use 'super' outside of a class.");
+                }
+                ClassKind::Class => {
+                    self.error(e.span, "Can't use 'super' in a class with no superclass.");
+                }
+                ClassKind::Subclass => self.resolve_local(e.id, "super", true),
+            },
+        }
+    }
+
+    // Scopes
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().expect("scopes should be balanced");
+        for (name, local) in scope {
+            if local.check_unused && !local.used {
+                self.warnings.push(Warning {
+                    span: local.span,
+                    message: format!("Local variable '{}' is never used.", name),
+                });
+            }
+        }
+    }
+
+    /// Add a variable to the innermost scope, not yet ready to be read.
+    fn declare(&mut self, name: &Identifier, check_unused: bool) {
+        let Some(scope) = self.scopes.last_mut() else {
+            // Globals are not tracked
+            return;
+        };
+        if let Some(previous) = scope.get(&name.name) {
+            self.errors.push(ResolveError {
+                span: name.span,
+                message: String::from("Already a variable with this name in this scope."),
+                label: Some(Label {
+                    span: previous.span,
+                    message: String::from("first declared here"),
+                }),
+            });
+            return;
+        }
+        scope.insert(
+            name.name.clone(),
+            Local {
+                span: name.span,
+                defined: false,
+                used: false,
+                check_unused,
+            },
+        );
+    }
+
+    /// Add a variable the program does not declare itself, like `this`, to the innermost scope.
+    fn declare_implicit(&mut self, name: &str, span: Span) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(
+                String::from(name),
+                Local {
+                    span,
+                    defined: true,
+                    used: false,
+                    check_unused: false,
+                },
+            );
+        }
+    }
+
+    /// Mark a declared variable as ready to be read.
+    fn define(&mut self, name: &Identifier) {
+        if let Some(local) = self
+            .scopes
+            .last_mut()
+            .and_then(|scope| scope.get_mut(&name.name))
+        {
+            local.defined = true;
+        }
+    }
+
+    /// Record how many scopes out the variable `name` is declared, if it is a local.
+    fn resolve_local(&mut self, id: ExprId, name: &str, read: bool) {
+        for (depth, scope) in self.scopes.iter_mut().rev().enumerate() {
+            if let Some(local) = scope.get_mut(name) {
+                local.used |= read;
+                self.locals.insert(id, depth);
+                return;
+            }
+        }
+    }
+
+    fn error(&mut self, span: Span, message: &str) {
+        self.errors.push(ResolveError {
+            span,
+            message: String::from(message),
+            label: None,
+        });
+    }
+}
+
+/// Tests for the resolver
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::parser::Parser;
+    use crate::modules::tokenize::Tokenizer;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::new(Tokenizer::new(source))
+            .parse()
+            .expect("source should parse")
+    }
+
+    /// Resolve a program, returning the depths of its variable uses in source order.
+    fn depths(source: &str) -> Vec<(String, Option<usize>)> {
+        let statements = parse(source);
+        let locals = Resolver::new()
+            .resolve(&statements)
+            .expect("source should resolve");
+
+        // Collect every variable use in the order it appears in the source
+        fn visit_expr(e: &Expr, locals: &Locals, found: &mut Vec<(usize, String, Option<usize>)>) {
+            match e {
+                Expr::Variable(v) => found.push((
+                    v.span.start,
+                    v.name.name.clone(),
+                    locals.get(&v.id).copied(),
+                )),
+                Expr::Assign(a) => {
+                    visit_expr(&a.value, locals, found);
+                    found.push((
+                        a.span.start,
+                        a.name.name.clone(),
+                        locals.get(&a.id).copied(),
+                    ));
+                }
+                Expr::Binary(b) => {
+                    visit_expr(&b.left, locals, found);
+                    visit_expr(&b.right, locals, found);
+                }
+                Expr::Call(c) => {
+                    visit_expr(&c.callee, locals, found);
+                    c.arguments
+                        .iter()
+                        .for_each(|a| visit_expr(a, locals, found));
+                }
+                _ => {}
+            }
+        }
+        fn visit_stmt(s: &Stmt, locals: &Locals, found: &mut Vec<(usize, String, Option<usize>)>) {
+            match s {
+                Stmt::Expression(s) => visit_expr(&s.expression, locals, found),
+                Stmt::Print(s) => visit_expr(&s.expression, locals, found),
+                Stmt::Var(s) => s
+                    .initializer
+                    .iter()
+                    .for_each(|e| visit_expr(e, locals, found)),
+                Stmt::Block(s) => s
+                    .statements
+                    .iter()
+                    .for_each(|s| visit_stmt(s, locals, found)),
+                Stmt::Function(f) => f.body.iter().for_each(|s| visit_stmt(s, locals, found)),
+                Stmt::Return(s) => s.value.iter().for_each(|e| visit_expr(e, locals, found)),
+                _ => {}
+            }
+        }
+
+        let mut found = Vec::new();
+        statements
+            .iter()
+            .for_each(|s| visit_stmt(s, &locals, &mut found));
+        found.sort_by_key(|(start, _, _)| *start);
+        found
+            .into_iter()
+            .map(|(_, name, depth)| (name, depth))
+            .collect()
+    }
+
+    fn errors(source: &str) -> Vec<String> {
+        let errors = Resolver::new()
+            .resolve(&parse(source))
+            .expect_err("source should not resolve");
+        errors.iter().map(|e| e.to_string()).collect()
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        let mut resolver = Resolver::new();
+        resolver
+            .resolve(&parse(source))
+            .expect("source should resolve");
+        resolver.warnings().iter().map(|w| w.to_string()).collect()
+    }
+
+    fn local(name: &str, depth: usize) -> (String, Option<usize>) {
+        (String::from(name), Some(depth))
+    }
+
+    fn global(name: &str) -> (String, Option<usize>) {
+        (String::from(name), None)
+    }
+
+    #[test]
+    fn scope_depths() {
+        assert_eq!(
+            depths("var g = 1; { var a = g; { var b = a; print a + b; } print a; }"),
+            vec![
+                global("g"),
+                local("a", 1),
+                local("a", 1),
+                local("b", 0),
+                local("a", 0),
+            ]
+        );
+        assert_eq!(
+            depths("fun f(x) { var y = x; fun g() { return x + y + z; } return g; }"),
+            vec![
+                local("x", 0),
+                local("x", 1),
+                local("y", 1),
+                global("z"),
+                local("g", 0),
+            ]
+        );
+        assert_eq!(
+            depths("{ var a = 1; a = 2; print a; }"),
+            vec![local("a", 0), local("a", 0)]
+        );
+    }
+
+    #[test]
+    fn closures_bind_to_the_declaration_in_scope() {
+        // Without the resolver, the second call would find the block's `a` instead of the global
+        assert_eq!(
+            depths("var a = 1; { fun show() { print a; } show(); var a = 2; show(); print a; }"),
+            vec![
+                global("a"),
+                local("show", 0),
+                local("show", 0),
+                local("a", 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn static_errors() {
+        assert_eq!(
+            errors("var a = 1; { var a = a + 1; print a; }"),
+            vec!["[line 1, column 22] Error: Can't read local variable in its own initializer."]
+        );
+        assert_eq!(
+            errors("fun f(a, a) { var b; { var b; } var b; print a + b; }"),
+            vec![
+                "[line 1, column 10] Error: Already a variable with this name in this scope.",
+                "[line 1, column 37] Error: Already a variable with this name in this scope.",
+            ]
+        );
+        assert_eq!(
+            errors("print 1;\nreturn 2;"),
+            vec!["[line 2, column 1] Error: Can't return from top-level code."]
+        );
+        assert_eq!(
+            errors("print this;\nfun f() { return this; }"),
+            vec![
+                "[line 1, column 7] Error: Can't use 'this' outside of a class.",
+                "[line 2, column 18] Error: Can't use 'this' outside of a class.",
+            ]
+        );
+        assert_eq!(
+            errors("class A < A {}\nclass B { m() { super.m(); } }\nsuper.m();"),
+            vec![
+                "[line 1, column 11] Error: A class can't inherit from itself.",
+                "[line 2, column 17] Error: Can't use 'super' in a class with no superclass.",
+                "[line 3, column 1] Error: Can't use 'super' outside of a class.",
+            ]
+        );
+        assert_eq!(
+            errors("class A { init() { return 1; } }"),
+            vec!["[line 1, column 20] Error: Can't return a value from an initializer."]
+        );
+    }
+
+    #[test]
+    fn allowed_declarations() {
+        // Globals can be redeclared, and read in their own initializer (where they are the old value)
+        assert_eq!(
+            depths("var a = 1; var a = a; class A { m() { return this; } init() { return; } }"),
+            vec![global("a")]
+        );
+    }
+
+    #[test]
+    fn unused_locals_are_warnings() {
+        assert_eq!(
+            warnings("var unused_global; fun f(unused_param) { var a; var b = 1; print b; { var a; a = 1; } }"),
+            vec![
+                "[line 1, column 46] Warning: Local variable 'a' is never used.",
+                "[line 1, column 75] Warning: Local variable 'a' is never used.",
+            ]
+        );
+        assert!(warnings("{ var a = 1; fun f() { print a; } f(); }").is_empty());
+    }
+
+    #[test]
+    fn unused_local_functions_and_classes_are_warnings() {
+        assert_eq!(
+            warnings("fun global() {} class Global {} { fun f() {} class A {} }"),
+            vec![
+                "[line 1, column 39] Warning: Local variable 'f' is never used.",
+                "[line 1, column 52] Warning: Local variable 'A' is never used.",
+            ]
+        );
+        assert!(warnings("{ fun f() {} class A {} f(); print A; }").is_empty());
+    }
+}
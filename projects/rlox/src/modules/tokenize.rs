@@ -1,17 +1,24 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
 use std::iter::Peekable;
 use std::str::Chars;
-/// Scanning: Chapter 4 of "Crafting Interpreters"
+use unicode_xid::UnicodeXID;
+// Scanning: Chapter 4 of "Crafting Interpreters"
 // [Note] The tokenizer (scanner) takes in raw source code as a series of characters and groups it into a series of chunks we call tokens.
 // [Note] Tokens are the meaningful "words" and "symbols" that make up the language's grammar.
 
 /// Enum representing the different types of tokens that can be produced by the tokenizer.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -19,6 +26,10 @@ pub enum TokenType {
     Semicolon,
     Slash,
     Star,
+    Ampersand,
+    Caret,
+    Pipe,
+    Tilde,
 
     // One or two character tokens.
     Bang,
@@ -27,8 +38,10 @@ pub enum TokenType {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
 
     // Literals.
     Identifier,
@@ -37,12 +50,15 @@ pub enum TokenType {
 
     // Keywords.
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
     For,
     If,
+    In,
     Nil,
     Or,
     Print,
@@ -53,36 +69,187 @@ pub enum TokenType {
     Var,
     While,
 
-    Illegal,
+    // Trivia. Only produced when comments are preserved.
+    Comment,
 
     EOF,
 }
 
-/// Struct representing a token produced by the tokenizer.
-/// Tokens produced by the tokenizer have a type, a lexeme (the actual text of the token), and a line number.
-#[derive(Debug, PartialEq)] // [Note] Derive must be implemented for enclosed structs. Here, TokenType
-pub struct Token {
-    pub token_type: TokenType,
-    pub lexeme: String,
+/// Enum representing the runtime value carried by a literal token.
+// [Note] The lexeme is the raw source text, e.g. "a\tb" including the quotes and the backslash.
+// The literal is the decoded value the interpreter will use, e.g. a<TAB>b.
+// A string without escapes decodes to the text between its quotes, so it is borrowed too.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal<'a> {
+    String(Cow<'a, str>),
+    Number(f64),
+}
+
+impl Literal<'_> {
+    /// Copy any borrowed text so the literal no longer depends on the source.
+    pub fn into_owned(self) -> Literal<'static> {
+        match self {
+            Literal::String(value) => Literal::String(Cow::Owned(value.into_owned())),
+            Literal::Number(value) => Literal::Number(value),
+        }
+    }
+}
+
+/// Struct representing a region of the source code.
+/// `start` and `end` are byte offsets (end exclusive), so `&source[span.start..span.end]` is the exact text.
+/// `line` and `column` are where the region begins, for display. Columns count chars from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
     pub line: u32,
     pub column: usize,
 }
 
-impl Token {
+impl Span {
+    // Span constructor
+    pub fn new(start: usize, end: usize, line: u32, column: usize) -> Self {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    /// The span covering both this span and `other`, which must come after it.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            ..self
+        }
+    }
+}
+
+/// Struct representing a token produced by the tokenizer.
+/// Tokens produced by the tokenizer have a type, a lexeme (the actual text of the token), and a span.
+// [Note] The lexeme is a Cow ("clone on write"). The tokenizer only ever hands out Cow::Borrowed slices
+// of the source, so scanning does not allocate per token. into_owned() gives a Token<'static>
+// for the rare cases where a token has to outlive its source.
+#[derive(Debug, Clone, PartialEq)] // [Note] Derive must be implemented for enclosed structs. Here, TokenType
+pub struct Token<'a> {
+    pub token_type: TokenType,
+    pub lexeme: Cow<'a, str>,
+    pub literal: Option<Literal<'a>>,
+    pub span: Span,
+}
+
+impl<'a> Token<'a> {
     // Token constructor
-    pub fn new(token_type: TokenType, lexeme: String, line: u32, column: usize) -> Self {
+    pub fn new(token_type: TokenType, lexeme: impl Into<Cow<'a, str>>, span: Span) -> Self {
         Token {
             token_type,
-            lexeme,
-            line,
-            column,
+            lexeme: lexeme.into(),
+            literal: None,
+            span,
         }
     }
+
+    // Token constructor for literal tokens (strings, numbers)
+    pub fn with_literal(
+        token_type: TokenType,
+        lexeme: impl Into<Cow<'a, str>>,
+        literal: Literal<'a>,
+        span: Span,
+    ) -> Self {
+        Token {
+            token_type,
+            lexeme: lexeme.into(),
+            literal: Some(literal),
+            span,
+        }
+    }
+
+    /// Copy the lexeme and literal so the token no longer borrows from the source.
+    pub fn into_owned(self) -> Token<'static> {
+        Token {
+            token_type: self.token_type,
+            lexeme: Cow::Owned(self.lexeme.into_owned()),
+            literal: self.literal.map(Literal::into_owned),
+            span: self.span,
+        }
+    }
+}
+
+/// Enum representing the kinds of problems the tokenizer can find in the source.
+#[derive(Debug, PartialEq)]
+pub enum LexErrorKind {
+    UnexpectedCharacter(char),
+    UnterminatedString,
+    InvalidEscape(String),
+    MalformedNumber(String),
+    UnterminatedComment,
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LexErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'.", c),
+            LexErrorKind::UnterminatedString => write!(f, "Unterminated string."),
+            LexErrorKind::InvalidEscape(message) => write!(f, "{}", message),
+            LexErrorKind::MalformedNumber(message) => write!(f, "{}", message),
+            LexErrorKind::UnterminatedComment => write!(f, "Unterminated block comment."),
+        }
+    }
+}
+
+/// Struct representing a lexical error and the span of the offending text.
+#[derive(Debug, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "[line {}, column {}] Error: {}",
+            self.span.line, self.span.column, self.kind
+        )
+    }
+}
+
+// [Note] Implementing std::error::Error lets a LexError travel through Box<dyn Error> like read_source's errors
+impl std::error::Error for LexError {}
+
+/// Look up the keyword token type for a reserved word, if it is one.
+pub fn keyword(text: &str) -> Option<TokenType> {
+    // [Note] A match on &str compiles to a decision tree, no HashMap needed for 19 keywords.
+    // `break` and `continue` are an extension, the reference implementation does not have them.
+    match text {
+        "and" => Some(TokenType::And),
+        "break" => Some(TokenType::Break),
+        "class" => Some(TokenType::Class),
+        "continue" => Some(TokenType::Continue),
+        "else" => Some(TokenType::Else),
+        "false" => Some(TokenType::False),
+        "fun" => Some(TokenType::Fun),
+        "for" => Some(TokenType::For),
+        "if" => Some(TokenType::If),
+        "in" => Some(TokenType::In),
+        "nil" => Some(TokenType::Nil),
+        "or" => Some(TokenType::Or),
+        "print" => Some(TokenType::Print),
+        "return" => Some(TokenType::Return),
+        "super" => Some(TokenType::Super),
+        "this" => Some(TokenType::This),
+        "true" => Some(TokenType::True),
+        "var" => Some(TokenType::Var),
+        "while" => Some(TokenType::While),
+        _ => None,
+    }
 }
 
 /// The tokenizer machine responsible for converting source code into tokens.
 pub struct Tokenizer<'a> {
-    // source: &'a String,  // Don't need to store the source string anymore
+    // [Note] The source is kept so lexemes can be sliced out of it instead of copied.
+    source: &'a str,
     // [Note] 'a is a lifetime annotation.  It indicates that the
     // Tokenizer struct cannot outlive the String it borrows.
     // In other words, the Tokenizer's reference to the String
@@ -90,13 +257,19 @@ pub struct Tokenizer<'a> {
 
     // State variables
     chars: Peekable<Chars<'a>>,
-    start: usize,
+    start: usize, // Byte offset, line and column where the token being scanned begins
+    start_line: u32,
+    start_column: usize,
     current: usize, // [Question] Why is usize used here for the current position in the source code?
-    // [Answer] ...
+    // [Answer] It is a byte offset into the source, and usize is the type Rust uses to index into memory.
     line: u32, // [Question] What is the difference between a usize and a u32?
     // [Answer] usize is an unsigned integer type that can hold the maximum value of the system's pointer size.
     // u32 is an unsigned integer type that can hold values from 0 to 4294967295.
     column: usize,
+    pending: VecDeque<Result<Token<'a>, LexError>>, // Scanned but not yet handed out, in source order
+    finished: bool,                                 // Set once EOF has been scanned
+    preserve_comments: bool,
+    trace: bool,
 }
 
 impl<'a> Tokenizer<'a> {
@@ -105,167 +278,591 @@ impl<'a> Tokenizer<'a> {
     // will be constrained by this lifetime.
 
     // Tokenizer constructor
-    pub fn new(source: &'a String) -> Self {
+    pub fn new(source: &'a str) -> Self {
         // [Note] The input source String also has
         // the lifetime 'a, tying it to the
         // Tokenizer's lifetime.
         Tokenizer {
+            source,
             chars: source.chars().peekable(), // Create a peekable iterator handler for the source
             start: 0,
+            start_line: 1,
+            start_column: 1,
             current: 0,
             line: 1,
             column: 0,
+            pending: VecDeque::new(),
+            finished: false,
+            preserve_comments: false,
+            trace: false,
         }
     }
 
-    /// Main entry point for scanning tokens
-    //  [Note] scan_tokens does not move the iterator forward
-    pub fn scan_tokens(&mut self) -> Vec<Token> {
+    /// Keep comments as `TokenType::Comment` trivia tokens instead of discarding them.
+    // [Note] Takes and returns self so it can be chained: Tokenizer::new(&source).preserve_comments(true)
+    pub fn preserve_comments(mut self, preserve: bool) -> Self {
+        self.preserve_comments = preserve;
+        self
+    }
+
+    /// The source being scanned.
+    pub fn source(&self) -> &'a str {
+        self.source
+    }
+
+    /// Print every token to stderr as it is scanned. Off by default.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Scan the whole source eagerly.
+    /// Returns every token scanned along with every lexical error found. Scanning carries on past
+    /// errors so all the problems in a file are reported in one pass.
+    //  [Note] This just drains the iterator below. The parser can pull tokens lazily instead.
+    pub fn scan_tokens(&mut self) -> (Vec<Token<'a>>, Vec<LexError>) {
         // Collection for scanned tokens
-        let mut tokens: Vec<Token> = Vec::new();
-
-        while let Some(&c) = self.chars.peek() {
-            // Scanning here
-            self.start = self.current;
-            let token = self.scan_token(c);
-            match token {
-                Some(token) => tokens.push(token),
-                None => (),
+        let mut tokens: Vec<Token<'a>> = Vec::new();
+        let mut errors: Vec<LexError> = Vec::new();
+
+        for result in self.by_ref() {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(error) => errors.push(error),
             }
         }
-        // When scanning is complete append the EOF token to tokens
-        tokens.push(self.create_token(TokenType::EOF, String::from("")));
+        (tokens, errors)
+    }
 
-        for t in &tokens {
-            eprintln!(" = {:?}", t);
+    /// Look at the next token (or error) without consuming it.
+    // [Note] Peekable<Tokenizer> would do the same, but then the tokenizer's own methods
+    // (like scan_tokens) would be out of reach. The pending queue already holds scanned items.
+    pub fn peek(&mut self) -> Option<&Result<Token<'a>, LexError>> {
+        self.fill_pending();
+        self.pending.front()
+    }
+
+    /// Scan until a token or error is pending, or the source is exhausted.
+    /// The last token is always EOF, an empty span just past the last character.
+    fn fill_pending(&mut self) {
+        while self.pending.is_empty() {
+            match self.chars.peek() {
+                Some(&c) => {
+                    // Scanning here. Errors found along the way are queued by error() ahead of the token
+                    self.mark_start();
+                    if let Some(token) = self.scan_token(c) {
+                        self.pending.push_back(Ok(token));
+                    }
+                }
+                None if !self.finished => {
+                    self.finished = true;
+                    self.mark_start();
+                    let eof = self.create_token(TokenType::EOF);
+                    self.pending.push_back(Ok(eof));
+                }
+                None => break,
+            }
         }
-        tokens
     }
 
-    pub fn create_token(&mut self, token_type: TokenType, lexeme: String) -> Token {
-        Token::new(token_type, lexeme, self.line, self.column)
+    pub fn create_token(&self, token_type: TokenType) -> Token<'a> {
+        Token::new(token_type, self.lexeme(), self.token_span())
+    }
+
+    /// The source text of the token being scanned, borrowed from the source.
+    pub fn lexeme(&self) -> &'a str {
+        &self.source[self.start..self.current]
+    }
+
+    /// Remember where the next token begins.
+    pub fn mark_start(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = self.column + 1;
+    }
+
+    /// The span from the start of the current token up to what has been consumed so far.
+    pub fn token_span(&self) -> Span {
+        Span::new(self.start, self.current, self.start_line, self.start_column)
     }
 
     // [Note] This function does the below,
     // 1. Classifies and creates a valid token
     // 2. Each of its sub-routines moves the iterator forward.
-    pub fn scan_token(&mut self, c: char) -> Option<Token> {
+    pub fn scan_token(&mut self, c: char) -> Option<Token<'a>> {
         // 1. Match and skip whitespace and escape characters
         if self.skip_whitespace_and_escape_characters(&c) {
             return None;
         }
-        // 2. Match and tokenize single and operator tokens
-        Some(self.match_single_and_operator_tokens(&c))
+        // 2. Match comments. They are skipped unless preserved as trivia
+        if c == '/' && matches!(self.peek_next(), Some('/' | '*')) {
+            return self.scan_comment();
+        }
+        // 3. Match literals, identifiers and keywords
+        match c {
+            '"' => return self.scan_string(),
+            '0'..='9' => return self.scan_number(),
+            c if c == '_' || c.is_xid_start() => return Some(self.scan_identifier()),
+            _ => (),
+        }
+        // 4. Match and tokenize single and operator tokens
+        self.match_single_and_operator_tokens(&c)
     }
 
     pub fn skip_whitespace_and_escape_characters(&mut self, c: &char) -> bool {
         match c {
-            ' ' | '\r' | '\t' => {
+            ' ' | '\r' | '\t' | '\n' => {
+                // [Note] advance() takes care of moving to the next line on '\n'
                 self.advance();
-                return true;
-            }
-            '\n' => {
-                // [Design] Should I put the below into a separate function?
-                self.chars.next();
-                self.line += 1; // Move to the next line
-                self.column = 0; // Reset column to 0
-                self.current += 1;
-                return true;
+                true
             }
             _ => false,
         }
     }
 
-    pub fn match_single_and_operator_tokens(&mut self, c: &char) -> Token {
+    pub fn match_single_and_operator_tokens(&mut self, c: &char) -> Option<Token<'a>> {
         match c {
             '(' => {
                 self.advance();
-                self.create_token(TokenType::LeftParen, c.to_string())
+                Some(self.create_token(TokenType::LeftParen))
             }
             ')' => {
                 self.advance();
-                self.create_token(TokenType::RightParen, c.to_string())
+                Some(self.create_token(TokenType::RightParen))
             }
             '{' => {
                 self.advance();
-                self.create_token(TokenType::LeftBrace, c.to_string())
+                Some(self.create_token(TokenType::LeftBrace))
             }
             '}' => {
                 self.advance();
-                self.create_token(TokenType::RightBrace, c.to_string())
+                Some(self.create_token(TokenType::RightBrace))
+            }
+            '[' => {
+                self.advance();
+                Some(self.create_token(TokenType::LeftBracket))
+            }
+            ']' => {
+                self.advance();
+                Some(self.create_token(TokenType::RightBracket))
+            }
+            ':' => {
+                self.advance();
+                Some(self.create_token(TokenType::Colon))
             }
             '.' => {
                 self.advance();
-                self.create_token(TokenType::Dot, c.to_string())
+                Some(self.create_token(TokenType::Dot))
             }
             ',' => {
                 self.advance();
-                self.create_token(TokenType::Comma, c.to_string())
+                Some(self.create_token(TokenType::Comma))
             }
             ';' => {
                 self.advance();
-                self.create_token(TokenType::Semicolon, c.to_string())
+                Some(self.create_token(TokenType::Semicolon))
             }
             '+' => {
                 self.advance();
-                self.create_token(TokenType::Plus, c.to_string())
+                Some(self.create_token(TokenType::Plus))
             }
             '-' => {
                 self.advance();
-                self.create_token(TokenType::Minus, c.to_string())
+                Some(self.create_token(TokenType::Minus))
             }
             '*' => {
                 self.advance();
-                self.create_token(TokenType::Star, c.to_string())
+                Some(self.create_token(TokenType::Star))
+            }
+            '/' => {
+                // [Note] Comments were already matched in scan_token, so this is division
+                self.advance();
+                Some(self.create_token(TokenType::Slash))
+            }
+            '&' => {
+                self.advance();
+                Some(self.create_token(TokenType::Ampersand))
+            }
+            '^' => {
+                self.advance();
+                Some(self.create_token(TokenType::Caret))
+            }
+            '|' => {
+                self.advance();
+                Some(self.create_token(TokenType::Pipe))
+            }
+            '~' => {
+                self.advance();
+                Some(self.create_token(TokenType::Tilde))
             }
             '=' => {
                 self.advance();
                 if self.chars.peek().unwrap_or(&' ') == &'=' {
                     self.advance();
-                    self.create_token(TokenType::EqualEqual, String::from("=="))
+                    Some(self.create_token(TokenType::EqualEqual))
                 } else {
-                    self.create_token(TokenType::Equal, c.to_string())
+                    Some(self.create_token(TokenType::Equal))
                 }
             }
             '!' => {
                 self.advance();
                 if self.chars.peek().unwrap_or(&' ') == &'=' {
                     self.advance();
-                    self.create_token(TokenType::BangEqual, String::from("!="))
+                    Some(self.create_token(TokenType::BangEqual))
                 } else {
-                    self.create_token(TokenType::Bang, c.to_string())
+                    Some(self.create_token(TokenType::Bang))
                 }
             }
             '<' => {
                 self.advance();
-                if self.chars.peek().unwrap_or(&' ') == &'=' {
-                    self.advance();
-                    self.create_token(TokenType::LessEqual, String::from("<="))
-                } else {
-                    self.create_token(TokenType::Less, c.to_string())
+                match self.chars.peek() {
+                    Some('=') => {
+                        self.advance();
+                        Some(self.create_token(TokenType::LessEqual))
+                    }
+                    Some('<') => {
+                        self.advance();
+                        Some(self.create_token(TokenType::LessLess))
+                    }
+                    _ => Some(self.create_token(TokenType::Less)),
                 }
             }
             '>' => {
                 self.advance();
-                if self.chars.peek().unwrap_or(&' ') == &'=' {
-                    self.advance();
-                    self.create_token(TokenType::GreaterEqual, String::from(">="))
-                } else {
-                    self.create_token(TokenType::Greater, c.to_string())
+                match self.chars.peek() {
+                    Some('=') => {
+                        self.advance();
+                        Some(self.create_token(TokenType::GreaterEqual))
+                    }
+                    Some('>') => {
+                        self.advance();
+                        Some(self.create_token(TokenType::GreaterGreater))
+                    }
+                    _ => Some(self.create_token(TokenType::Greater)),
                 }
             }
             _ => {
-                // The below is a hack till I figure out how to just return the tokens
+                // Nothing in Lox starts with this character. Report it, skip it and carry on
+                self.advance();
+                self.error(LexErrorKind::UnexpectedCharacter(*c), self.token_span());
+                None
+            }
+        }
+    }
+
+    /// Scan a `//` line comment or a `/* */` block comment. Nothing has been consumed yet.
+    // [Note] Block comments nest, so `/* a /* b */ c */` is one comment. A depth counter is enough
+    // to track this, the scanner does not need a stack.
+    pub fn scan_comment(&mut self) -> Option<Token<'a>> {
+        self.advance();
+
+        if self.advance() == Some('/') {
+            // A line comment runs until the end of the line. The '\n' is left for the whitespace skipper
+            while let Some(&c) = self.chars.peek() {
+                if c == '\n' {
+                    break;
+                }
                 self.advance();
-                self.create_token(TokenType::Illegal, c.to_string())
+            }
+        } else {
+            let mut depth = 1;
+            while depth > 0 {
+                match self.advance() {
+                    None => {
+                        self.error(LexErrorKind::UnterminatedComment, self.token_span());
+                        return None;
+                    }
+                    Some('/') if self.chars.peek() == Some(&'*') => {
+                        self.advance();
+                        depth += 1;
+                    }
+                    Some('*') if self.chars.peek() == Some(&'/') => {
+                        self.advance();
+                        depth -= 1;
+                    }
+                    Some(_) => (),
+                }
             }
         }
+
+        if self.preserve_comments {
+            Some(self.create_token(TokenType::Comment))
+        } else {
+            None
+        }
     }
 
-    pub fn advance(&mut self) {
-        self.chars.next();
-        self.current += 1;
-        self.column += 1;
+    /// Scan a string literal. The opening '"' has not been consumed yet.
+    // [Note] Strings may span multiple lines, so line and column are kept up to date by advance().
+    // The token keeps the raw lexeme (quotes and escapes included) and the decoded value as its literal.
+    pub fn scan_string(&mut self) -> Option<Token<'a>> {
+        // Decoded value. Only allocated once an escape sequence shows the raw text can't be used as is
+        let mut value: Option<String> = None;
+        let mut valid = true;
+
+        self.advance();
+        loop {
+            match self.advance() {
+                None => {
+                    self.error(LexErrorKind::UnterminatedString, self.token_span());
+                    return None;
+                }
+                Some('"') => break,
+                Some('\\') => {
+                    // The escape starts at the backslash we just consumed
+                    let (start, line, column) = (self.current - 1, self.line, self.column);
+                    let source = self.source;
+                    let value =
+                        value.get_or_insert_with(|| source[self.start + 1..start].to_string());
+                    match self.scan_escape() {
+                        Ok(c) => value.push(c),
+                        Err(message) => {
+                            let span = Span::new(start, self.current, line, column);
+                            self.error(LexErrorKind::InvalidEscape(message), span);
+                            valid = false;
+                        }
+                    }
+                }
+                Some(c) => {
+                    if let Some(value) = value.as_mut() {
+                        value.push(c);
+                    }
+                }
+            }
+        }
+
+        // A string with a bad escape has already been reported, don't hand it to the parser
+        if !valid {
+            return None;
+        }
+        let value = match value {
+            Some(value) => Cow::Owned(value),
+            None => Cow::Borrowed(&self.source[self.start + 1..self.current - 1]),
+        };
+        Some(Token::with_literal(
+            TokenType::String,
+            self.lexeme(),
+            Literal::String(value),
+            self.token_span(),
+        ))
+    }
+
+    /// Decode the escape sequence following a backslash inside a string literal.
+    pub fn scan_escape(&mut self) -> Result<char, String> {
+        let c = match self.chars.peek() {
+            // Leave '"' and newlines alone so an unterminated string is still reported correctly
+            Some(&c) if c != '\n' => c,
+            _ => return Err(String::from("Unterminated escape sequence.")),
+        };
+        self.advance();
+
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            'u' => self.scan_unicode_escape(),
+            _ => Err(format!("Invalid escape sequence '\\{}'.", c)),
+        }
+    }
+
+    /// Decode the `{XXXX}` part of a `\u{XXXX}` escape into a char.
+    pub fn scan_unicode_escape(&mut self) -> Result<char, String> {
+        if self.chars.peek() != Some(&'{') {
+            return Err(String::from("Expected '{' after '\\u'."));
+        }
+        self.advance();
+
+        let start = self.current;
+        while self.chars.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.advance();
+        }
+        let digits = &self.source[start..self.current];
+
+        if self.chars.peek() != Some(&'}') {
+            return Err(String::from("Expected '}' to close unicode escape."));
+        }
+        self.advance();
+
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(String::from("Unicode escape must have 1 to 6 hex digits."));
+        }
+        // [Note] from_str_radix cannot fail here, but surrogates and values above 10FFFF are not chars
+        u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| format!("Invalid unicode scalar value '{}'.", digits))
+    }
+
+    /// Scan a number literal. The first digit has not been consumed yet.
+    // [Note] Supported forms: 123, 1_000, 3.14, 6.02e23, 1e-9, 0xFF, 0b1010.
+    // All numbers in Lox are f64, so hex and binary literals are converted too.
+    pub fn scan_number(&mut self) -> Option<Token<'a>> {
+        match self.scan_number_value() {
+            Ok(value) => Some(Token::with_literal(
+                TokenType::Number,
+                self.lexeme(),
+                Literal::Number(value),
+                self.token_span(),
+            )),
+            Err(message) => {
+                // Swallow the rest of the malformed literal so it is reported once
+                while self
+                    .chars
+                    .peek()
+                    .is_some_and(|&c| c.is_ascii_alphanumeric() || c == '_')
+                {
+                    self.advance();
+                }
+                self.error(LexErrorKind::MalformedNumber(message), self.token_span());
+                None
+            }
+        }
+    }
+
+    /// Consume a number literal and return its value.
+    pub fn scan_number_value(&mut self) -> Result<f64, String> {
+        // Hex and binary literals: 0x... and 0b...
+        if self.chars.peek() == Some(&'0') {
+            let radix = match self.peek_next() {
+                Some('x' | 'X') => Some((16, "hex")),
+                Some('b' | 'B') => Some((2, "binary")),
+                _ => None,
+            };
+            if let Some((radix, name)) = radix {
+                self.advance();
+                self.advance();
+                let message = format!("Expected {} digit after '{}'.", name, self.lexeme());
+                self.scan_digits(radix, &message)?;
+                self.check_number_end(name)?;
+                // [Note] Folding into an f64 directly means large literals lose precision instead of overflowing.
+                return Ok(self.lexeme()[2..]
+                    .chars()
+                    .filter_map(|c| c.to_digit(radix))
+                    .fold(0.0, |value, digit| value * radix as f64 + digit as f64));
+            }
+        }
+
+        // Decimal literals: integer part, optional fraction, optional exponent
+        self.scan_digits(10, "Expected digit.")?;
+        if self.chars.peek() == Some(&'.') {
+            self.advance();
+            self.scan_digits(10, "Expected digit after '.' in number literal.")?;
+        }
+        if let Some('e' | 'E') = self.chars.peek() {
+            self.advance();
+            if let Some('+' | '-') = self.chars.peek() {
+                self.advance();
+            }
+            self.scan_digits(10, "Expected digit in number exponent.")?;
+        }
+        self.check_number_end("number")?;
+
+        // Rust's float parser does not accept digit separators, so only copy the text when there are some
+        let text = self.lexeme();
+        let parsed = if text.contains('_') {
+            text.replace('_', "").parse::<f64>()
+        } else {
+            text.parse::<f64>()
+        };
+        parsed.map_err(|_| format!("Invalid number literal '{}'.", text))
+    }
+
+    /// Consume a run of digits in the given radix, allowing `_` separators between digits.
+    /// Fails with `message` if there were no digits.
+    pub fn scan_digits(&mut self, radix: u32, message: &str) -> Result<(), String> {
+        let mut any_digits = false;
+        loop {
+            match self.chars.peek() {
+                Some(&c) if c.is_digit(radix) => {
+                    self.advance();
+                    any_digits = true;
+                }
+                Some(&'_') if any_digits => {
+                    self.advance();
+                    if !self.chars.peek().is_some_and(|c| c.is_digit(radix)) {
+                        return Err(String::from(
+                            "Digit separator '_' must be followed by a digit.",
+                        ));
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        if any_digits {
+            Ok(())
+        } else {
+            Err(String::from(message))
+        }
+    }
+
+    /// Reject letters and digits glued onto the end of a number literal, e.g. `0b102` or `12ab`.
+    pub fn check_number_end(&mut self, name: &str) -> Result<(), String> {
+        match self.chars.peek() {
+            Some(&c) if c.is_ascii_alphanumeric() || c == '_' => {
+                Err(format!("Invalid digit '{}' in {} literal.", c, name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Look at the character after the next one without consuming anything.
+    pub fn peek_next(&self) -> Option<char> {
+        // [Note] Cloning a Chars iterator is cheap, it is only a pair of pointers into the source.
+        let mut chars = self.chars.clone();
+        chars.next();
+        chars.next()
+    }
+
+    /// Scan an identifier or keyword. The first character has not been consumed yet.
+    // [Note] Identifiers follow the Unicode XID rules (like Rust): they start with an XID_Start
+    // character or '_', and continue with XID_Continue characters. So `café` and `_x1` are identifiers.
+    pub fn scan_identifier(&mut self) -> Token<'a> {
+        while self.chars.peek().is_some_and(|c| c.is_xid_continue()) {
+            self.advance();
+        }
+
+        // Reserved words take priority over user identifiers
+        let token_type = keyword(self.lexeme()).unwrap_or(TokenType::Identifier);
+        self.create_token(token_type)
+    }
+
+    /// Record a lexical error and keep scanning.
+    pub fn error(&mut self, kind: LexErrorKind, span: Span) {
+        self.pending.push_back(Err(LexError { kind, span }));
+    }
+
+    /// Consume the next character, keeping the line and column counters in step.
+    pub fn advance(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.current += c.len_utf8(); // Byte offset, a char may take up to 4 bytes
+        if c == '\n' {
+            self.line += 1; // Move to the next line
+            self.column = 0; // Reset column to 0
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
+
+/// The tokenizer is an iterator of tokens, so the parser can pull them one at a time.
+/// Lexical errors are yielded in place, and scanning continues after them.
+impl<'a> Iterator for Tokenizer<'a> {
+    type Item = Result<Token<'a>, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.fill_pending();
+        let next = self.pending.pop_front();
+        if self.trace {
+            match &next {
+                Some(Ok(token)) => eprintln!(" = {:?}", token),
+                Some(Err(error)) => eprintln!(" ! {}", error),
+                None => (),
+            }
+        }
+        next
     }
 }
 
@@ -277,71 +874,158 @@ mod tests {
     fn single_character_tokens_and_operators() {
         let input = String::from("=+(){}<=.<!>!=-==*");
         let mut tokenizer = Tokenizer::new(&input);
-        let tokens = tokenizer.scan_tokens();
+        let (tokens, _) = tokenizer.scan_tokens();
 
         assert_eq!(
             tokens[0],
-            Token::new(TokenType::Equal, String::from("="), 1, 1)
+            Token::new(TokenType::Equal, String::from("="), Span::new(0, 1, 1, 1))
         );
         assert_eq!(
             tokens[1],
-            Token::new(TokenType::Plus, String::from("+"), 1, 2)
+            Token::new(TokenType::Plus, String::from("+"), Span::new(1, 2, 1, 2))
         );
         assert_eq!(
             tokens[2],
-            Token::new(TokenType::LeftParen, String::from("("), 1, 3)
+            Token::new(
+                TokenType::LeftParen,
+                String::from("("),
+                Span::new(2, 3, 1, 3)
+            )
         );
         assert_eq!(
             tokens[3],
-            Token::new(TokenType::RightParen, String::from(")"), 1, 4)
+            Token::new(
+                TokenType::RightParen,
+                String::from(")"),
+                Span::new(3, 4, 1, 4)
+            )
         );
         assert_eq!(
             tokens[4],
-            Token::new(TokenType::LeftBrace, String::from("{"), 1, 5)
+            Token::new(
+                TokenType::LeftBrace,
+                String::from("{"),
+                Span::new(4, 5, 1, 5)
+            )
         );
         assert_eq!(
             tokens[5],
-            Token::new(TokenType::RightBrace, String::from("}"), 1, 6)
+            Token::new(
+                TokenType::RightBrace,
+                String::from("}"),
+                Span::new(5, 6, 1, 6)
+            )
         );
         assert_eq!(
             tokens[6],
-            Token::new(TokenType::LessEqual, String::from("<="), 1, 8)
+            Token::new(
+                TokenType::LessEqual,
+                String::from("<="),
+                Span::new(6, 8, 1, 7)
+            )
         );
         assert_eq!(
             tokens[7],
-            Token::new(TokenType::Dot, String::from("."), 1, 9)
+            Token::new(TokenType::Dot, String::from("."), Span::new(8, 9, 1, 9))
         );
         assert_eq!(
             tokens[8],
-            Token::new(TokenType::Less, String::from("<"), 1, 10)
+            Token::new(TokenType::Less, String::from("<"), Span::new(9, 10, 1, 10))
         );
         assert_eq!(
             tokens[9],
-            Token::new(TokenType::Bang, String::from("!"), 1, 11)
+            Token::new(TokenType::Bang, String::from("!"), Span::new(10, 11, 1, 11))
         );
         assert_eq!(
             tokens[10],
-            Token::new(TokenType::Greater, String::from(">"), 1, 12)
+            Token::new(
+                TokenType::Greater,
+                String::from(">"),
+                Span::new(11, 12, 1, 12)
+            )
         );
         assert_eq!(
             tokens[11],
-            Token::new(TokenType::BangEqual, String::from("!="), 1, 14)
+            Token::new(
+                TokenType::BangEqual,
+                String::from("!="),
+                Span::new(12, 14, 1, 13)
+            )
         );
         assert_eq!(
             tokens[12],
-            Token::new(TokenType::Minus, String::from("-"), 1, 15)
+            Token::new(
+                TokenType::Minus,
+                String::from("-"),
+                Span::new(14, 15, 1, 15)
+            )
         );
         assert_eq!(
             tokens[13],
-            Token::new(TokenType::EqualEqual, String::from("=="), 1, 17)
+            Token::new(
+                TokenType::EqualEqual,
+                String::from("=="),
+                Span::new(15, 17, 1, 16)
+            )
         );
         assert_eq!(
             tokens[14],
-            Token::new(TokenType::Star, String::from("*"), 1, 18)
+            Token::new(TokenType::Star, String::from("*"), Span::new(17, 18, 1, 18))
         );
         assert_eq!(
             tokens[15],
-            Token::new(TokenType::EOF, String::from(""), 1, 18)
+            Token::new(TokenType::EOF, String::from(""), Span::new(18, 18, 1, 19))
+        );
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let types: Vec<TokenType> = Tokenizer::new("& | ^ ~ << >> <<= >>= < > <<<")
+            .map(|token| token.unwrap().token_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::Ampersand,
+                TokenType::Pipe,
+                TokenType::Caret,
+                TokenType::Tilde,
+                TokenType::LessLess,
+                TokenType::GreaterGreater,
+                // [Note] There are no compound assignment operators, so `<<=` is `<<` then `=`
+                TokenType::LessLess,
+                TokenType::Equal,
+                TokenType::GreaterGreater,
+                TokenType::Equal,
+                TokenType::Less,
+                TokenType::Greater,
+                TokenType::LessLess,
+                TokenType::Less,
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn collection_punctuation() {
+        let types: Vec<TokenType> = Tokenizer::new("[1, 2]{\"k\": v}")
+            .map(|token| token.unwrap().token_type)
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                TokenType::LeftBracket,
+                TokenType::Number,
+                TokenType::Comma,
+                TokenType::Number,
+                TokenType::RightBracket,
+                TokenType::LeftBrace,
+                TokenType::String,
+                TokenType::Colon,
+                TokenType::Identifier,
+                TokenType::RightBrace,
+                TokenType::EOF,
+            ]
         );
     }
 
@@ -349,15 +1033,492 @@ mod tests {
     fn skip_whitespace() {
         let input = String::from("  \t\t+    ");
         let mut tokenizer = Tokenizer::new(&input);
-        let tokens = tokenizer.scan_tokens();
+        let (tokens, _) = tokenizer.scan_tokens();
+
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenType::Plus, String::from("+"), Span::new(4, 5, 1, 5))
+        );
+        assert_eq!(
+            tokens[1],
+            Token::new(TokenType::EOF, String::from(""), Span::new(9, 9, 1, 10))
+        );
+    }
+
+    #[test]
+    fn string_literals() {
+        let input = String::from("\"Hello, World\"");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
 
         assert_eq!(
             tokens[0],
-            Token::new(TokenType::Plus, String::from("+"), 1, 5)
+            Token::with_literal(
+                TokenType::String,
+                String::from("\"Hello, World\""),
+                Literal::String(Cow::from("Hello, World")),
+                Span::new(0, 14, 1, 1)
+            )
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn string_escape_sequences() {
+        let input = String::from(r#""a\tb\n\"c\"\\\u{41}\u{1F600}""#);
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+
+        assert_eq!(tokens[0].lexeme, input);
+        assert_eq!(
+            tokens[0].literal,
+            Some(Literal::String(Cow::from("a\tb\n\"c\"\\A\u{1F600}")))
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn multi_line_strings_track_lines() {
+        let input = String::from("\"one\ntwo\" +");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, _) = tokenizer.scan_tokens();
+
+        assert_eq!(
+            tokens[0].literal,
+            Some(Literal::String(Cow::from("one\ntwo")))
+        );
+        assert_eq!(
+            tokens[1],
+            Token::new(TokenType::Plus, String::from("+"), Span::new(10, 11, 2, 6))
+        );
+    }
+
+    #[test]
+    fn unterminated_and_invalid_strings() {
+        let input = String::from("\"abc");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+        assert_eq!(tokens[0].token_type, TokenType::EOF);
+        assert_eq!(
+            errors,
+            vec![LexError {
+                kind: LexErrorKind::UnterminatedString,
+                span: Span::new(0, 4, 1, 1),
+            }]
+        );
+
+        for input in [r#""\q""#, r#""\u{}""#, r#""\u{D800}""#, r#""\u41""#] {
+            let input = String::from(input);
+            let mut tokenizer = Tokenizer::new(&input);
+            let (_, errors) = tokenizer.scan_tokens();
+            assert!(!errors.is_empty(), "{} should be rejected", input);
+        }
+    }
+
+    #[test]
+    fn number_literals() {
+        let cases = [
+            ("0", 0.0),
+            ("123", 123.0),
+            ("3.25", 3.25),
+            ("1_000_000", 1_000_000.0),
+            ("6.02e23", 6.02e23),
+            ("1E-3", 1e-3),
+            ("2.5e+2", 250.0),
+            ("0xFF", 255.0),
+            ("0x1f_ff", 8191.0),
+            ("0b1010", 10.0),
+            ("0B1111_0000", 240.0),
+        ];
+        for (input, value) in cases {
+            let input = String::from(input);
+            let mut tokenizer = Tokenizer::new(&input);
+            let (tokens, errors) = tokenizer.scan_tokens();
+
+            assert_eq!(tokens[0].token_type, TokenType::Number, "{}", input);
+            assert_eq!(tokens[0].lexeme, input);
+            assert_eq!(tokens[0].literal, Some(Literal::Number(value)), "{}", input);
+            assert!(errors.is_empty(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn number_followed_by_operator() {
+        let input = String::from("12.5*2");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, _) = tokenizer.scan_tokens();
+
+        assert_eq!(
+            tokens[0],
+            Token::with_literal(
+                TokenType::Number,
+                String::from("12.5"),
+                Literal::Number(12.5),
+                Span::new(0, 4, 1, 1)
+            )
+        );
+        assert_eq!(
+            tokens[1],
+            Token::new(TokenType::Star, String::from("*"), Span::new(4, 5, 1, 5))
+        );
+        assert_eq!(tokens[2].literal, Some(Literal::Number(2.0)));
+    }
+
+    #[test]
+    fn malformed_number_literals() {
+        for input in [
+            "1.", "1.e5", "0x", "0b", "0b102", "0xFG", "1e", "1e+", "1__0", "1_", "12ab",
+        ] {
+            let input = String::from(input);
+            let mut tokenizer = Tokenizer::new(&input);
+            let (tokens, errors) = tokenizer.scan_tokens();
+
+            assert_eq!(tokens.len(), 1, "{} should produce no token", input);
+            assert_eq!(errors.len(), 1, "{} should be a single error", input);
+            assert!(
+                matches!(errors[0].kind, LexErrorKind::MalformedNumber(_)),
+                "{}",
+                input
+            );
+            assert_eq!(errors[0].span, Span::new(0, input.len(), 1, 1));
+        }
+    }
+
+    #[test]
+    fn keywords() {
+        let input = String::from(
+            "and break class continue else false fun for if in nil or print return super this true var while",
+        );
+        let mut tokenizer = Tokenizer::new(&input);
+        let token_types: Vec<TokenType> = tokenizer
+            .scan_tokens()
+            .0
+            .into_iter()
+            .map(|t| t.token_type)
+            .collect();
+
+        assert_eq!(
+            token_types,
+            vec![
+                TokenType::And,
+                TokenType::Break,
+                TokenType::Class,
+                TokenType::Continue,
+                TokenType::Else,
+                TokenType::False,
+                TokenType::Fun,
+                TokenType::For,
+                TokenType::If,
+                TokenType::In,
+                TokenType::Nil,
+                TokenType::Or,
+                TokenType::Print,
+                TokenType::Return,
+                TokenType::Super,
+                TokenType::This,
+                TokenType::True,
+                TokenType::Var,
+                TokenType::While,
+                TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn identifiers() {
+        let input = String::from("orchid classy _ _x1 iff nil0 Var café π");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+
+        let lexemes: Vec<&str> = tokens[..tokens.len() - 1]
+            .iter()
+            .map(|t| t.lexeme.as_ref())
+            .collect();
+        assert_eq!(
+            lexemes,
+            vec!["orchid", "classy", "_", "_x1", "iff", "nil0", "Var", "café", "π"]
+        );
+        for token in &tokens[..tokens.len() - 1] {
+            assert_eq!(token.token_type, TokenType::Identifier, "{}", token.lexeme);
+        }
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn variable_declaration() {
+        let input = String::from("var greet = \"Hello, World\";");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, _) = tokenizer.scan_tokens();
+
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenType::Var, String::from("var"), Span::new(0, 3, 1, 1))
+        );
+        assert_eq!(
+            tokens[1],
+            Token::new(
+                TokenType::Identifier,
+                String::from("greet"),
+                Span::new(4, 9, 1, 5)
+            )
+        );
+        assert_eq!(
+            tokens[2],
+            Token::new(
+                TokenType::Equal,
+                String::from("="),
+                Span::new(10, 11, 1, 11)
+            )
+        );
+        assert_eq!(tokens[3].token_type, TokenType::String);
+        assert_eq!(
+            tokens[4],
+            Token::new(
+                TokenType::Semicolon,
+                String::from(";"),
+                Span::new(26, 27, 1, 27)
+            )
         );
+    }
+
+    #[test]
+    fn slash_and_line_comments() {
+        let input = String::from("a / b // divide\n// whole line\n/");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, _) = tokenizer.scan_tokens();
+
         assert_eq!(
             tokens[1],
-            Token::new(TokenType::EOF, String::from(""), 1, 9)
+            Token::new(TokenType::Slash, String::from("/"), Span::new(2, 3, 1, 3))
+        );
+        assert_eq!(
+            tokens[3],
+            Token::new(TokenType::Slash, String::from("/"), Span::new(30, 31, 3, 1))
+        );
+        assert_eq!(tokens[4].token_type, TokenType::EOF);
+    }
+
+    #[test]
+    fn nested_block_comments() {
+        let input = String::from("/* outer /* inner\n */ still\n comment */ +");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+
+        assert_eq!(
+            tokens[0],
+            Token::new(TokenType::Plus, String::from("+"), Span::new(40, 41, 3, 13))
         );
+        assert_eq!(tokens.len(), 2);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let input = String::from("+ /* /* */");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+
+        assert_eq!(tokens.len(), 2);
+        assert!(!errors.is_empty());
+    }
+
+    #[test]
+    fn preserved_comments() {
+        let input = String::from("// note\n+ /* block */");
+        let mut tokenizer = Tokenizer::new(&input).preserve_comments(true);
+        let (tokens, _) = tokenizer.scan_tokens();
+
+        assert_eq!(
+            tokens[0],
+            Token::new(
+                TokenType::Comment,
+                String::from("// note"),
+                Span::new(0, 7, 1, 1)
+            )
+        );
+        assert_eq!(tokens[1].token_type, TokenType::Plus);
+        assert_eq!(
+            tokens[2],
+            Token::new(
+                TokenType::Comment,
+                String::from("/* block */"),
+                Span::new(10, 21, 2, 3)
+            )
+        );
+    }
+
+    #[test]
+    fn errors_are_collected_and_scanning_continues() {
+        let input = String::from("var x = @;\nprint \"a\\qb\" # 1.;\n/* open");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+
+        let messages: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "[line 1, column 9] Error: Unexpected character '@'.",
+                "[line 2, column 9] Error: Invalid escape sequence '\\q'.",
+                "[line 2, column 14] Error: Unexpected character '#'.",
+                "[line 2, column 16] Error: Expected digit after '.' in number literal.",
+                "[line 3, column 1] Error: Unterminated block comment.",
+            ]
+        );
+        assert_eq!(errors[0].span, Span::new(8, 9, 1, 9));
+        assert_eq!(errors[1].span, Span::new(19, 21, 2, 9));
+
+        // The valid tokens around the errors are still produced
+        let token_types: Vec<&TokenType> = tokens.iter().map(|t| &t.token_type).collect();
+        assert_eq!(
+            token_types,
+            vec![
+                &TokenType::Var,
+                &TokenType::Identifier,
+                &TokenType::Equal,
+                &TokenType::Semicolon,
+                &TokenType::Print,
+                &TokenType::Semicolon,
+                &TokenType::EOF,
+            ]
+        );
+    }
+
+    #[test]
+    fn error_spans_are_byte_offsets() {
+        let input = String::from("\"é\" ¤");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (_, errors) = tokenizer.scan_tokens();
+
+        assert_eq!(errors[0].kind, LexErrorKind::UnexpectedCharacter('¤'));
+        assert_eq!(errors[0].span, Span::new(5, 7, 1, 5));
+    }
+
+    #[test]
+    fn spans_slice_the_source() {
+        let input = String::from("var s = \"a\nb\";\nprint s >= 1.5e3; // done\n");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, errors) = tokenizer.scan_tokens();
+
+        assert!(errors.is_empty());
+        for token in &tokens {
+            assert_eq!(&input[token.span.start..token.span.end], token.lexeme);
+        }
+        // The multi-line string is positioned at its opening quote
+        assert_eq!(tokens[3].span, Span::new(8, 13, 1, 9));
+        assert_eq!(tokens[7].span, Span::new(23, 25, 3, 9));
+        // EOF sits just past the final newline
+        assert_eq!(
+            tokens.last().unwrap(),
+            &Token::new(TokenType::EOF, String::from(""), Span::new(41, 41, 4, 1))
+        );
+    }
+
+    #[test]
+    fn lexemes_borrow_from_the_source() {
+        let input = String::from("var plain = \"text\"; var escaped = \"a\\tb\";");
+        let mut tokenizer = Tokenizer::new(&input);
+        let (tokens, _) = tokenizer.scan_tokens();
+
+        for token in &tokens {
+            assert!(matches!(token.lexeme, Cow::Borrowed(_)), "{:?}", token);
+        }
+        // Only the string that needed decoding owns its value
+        assert!(matches!(
+            tokens[3].literal,
+            Some(Literal::String(Cow::Borrowed("text")))
+        ));
+        assert!(matches!(
+            tokens[8].literal,
+            Some(Literal::String(Cow::Owned(_)))
+        ));
+    }
+
+    #[test]
+    fn owned_tokens_outlive_the_source() {
+        let owned: Vec<Token<'static>> = {
+            let input = String::from("print \"hi\";");
+            let mut tokenizer = Tokenizer::new(&input);
+            let (tokens, _) = tokenizer.scan_tokens();
+            tokens.into_iter().map(Token::into_owned).collect()
+        };
+
+        assert_eq!(
+            owned[1],
+            Token::with_literal(
+                TokenType::String,
+                "\"hi\"",
+                Literal::String(Cow::from("hi")),
+                Span::new(6, 10, 1, 7)
+            )
+        );
+    }
+
+    #[test]
+    fn iterator_yields_tokens_lazily() {
+        let input = String::from("print 1;");
+        let mut tokenizer = Tokenizer::new(&input);
+
+        assert_eq!(
+            tokenizer.next(),
+            Some(Ok(Token::new(
+                TokenType::Print,
+                "print",
+                Span::new(0, 5, 1, 1)
+            )))
+        );
+        // Nothing past the first token has been scanned yet
+        assert_eq!(tokenizer.current, 5);
+
+        let rest: Vec<TokenType> = tokenizer.map(|t| t.unwrap().token_type).collect();
+        assert_eq!(
+            rest,
+            vec![TokenType::Number, TokenType::Semicolon, TokenType::EOF]
+        );
+    }
+
+    #[test]
+    fn iterator_interleaves_errors_in_source_order() {
+        let input = String::from("a @ \"\\q\\w\" b");
+        let items: Vec<Result<TokenType, LexErrorKind>> = Tokenizer::new(&input)
+            .map(|item| item.map(|t| t.token_type).map_err(|e| e.kind))
+            .collect();
+
+        assert_eq!(
+            items,
+            vec![
+                Ok(TokenType::Identifier),
+                Err(LexErrorKind::UnexpectedCharacter('@')),
+                Err(LexErrorKind::InvalidEscape(String::from(
+                    "Invalid escape sequence '\\q'."
+                ))),
+                Err(LexErrorKind::InvalidEscape(String::from(
+                    "Invalid escape sequence '\\w'."
+                ))),
+                Ok(TokenType::Identifier),
+                Ok(TokenType::EOF),
+            ]
+        );
+    }
+
+    #[test]
+    fn peek_does_not_consume() {
+        let input = String::from("x = 1");
+        let mut tokenizer = Tokenizer::new(&input);
+
+        assert_eq!(
+            tokenizer.peek().unwrap().as_ref().unwrap().token_type,
+            TokenType::Identifier
+        );
+        assert_eq!(
+            tokenizer.peek().unwrap().as_ref().unwrap().token_type,
+            TokenType::Identifier
+        );
+        assert_eq!(tokenizer.next().unwrap().unwrap().lexeme, "x");
+        assert_eq!(tokenizer.peek().unwrap().as_ref().unwrap().lexeme, "=");
+
+        // EOF is handed out exactly once, then the iterator is exhausted
+        assert_eq!(tokenizer.by_ref().count(), 3);
+        assert!(tokenizer.peek().is_none());
+        assert!(tokenizer.next().is_none());
     }
 }
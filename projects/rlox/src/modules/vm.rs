@@ -0,0 +1,891 @@
+use crate::modules::ast::BinaryOp;
+use crate::modules::bitwise::{self, Operand};
+use crate::modules::chunk::OpCode;
+use crate::modules::collection::{self, Key, LoxMap, Part};
+use crate::modules::disassembler;
+use crate::modules::interpreter::{Callable, RuntimeError, Value, MAX_CALL_DEPTH};
+use crate::modules::native::{self, NativeError};
+use crate::modules::object::*;
+use crate::modules::tokenize::Span;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+// A Virtual Machine: Chapters 15 and 18 to 29 of "Crafting Interpreters"
+// [Note] A stack machine: instructions take their operands off the value stack and push their
+// results back on. Each call gets a window of the stack, starting with the function called, then
+// its arguments, then its locals, so arguments become the first locals without being copied.
+// Runtime errors have the same messages, and point at the same code, as the tree-walker's.
+
+/// Struct representing a call in progress.
+#[derive(Debug)]
+struct CallFrame {
+    closure: Rc<ObjClosure>,
+    /// The offset of the next instruction to run in the closure's chunk
+    ip: usize,
+    /// Where the call's window starts on the stack: slot 0 of its locals
+    base: usize,
+}
+
+/// Struct representing the virtual machine, and the state a program keeps while it runs.
+pub struct Vm {
+    out: Box<dyn Write>,
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    /// The upvalues still pointing at a stack slot, so closures capturing the same variable share one
+    open_upvalues: Vec<Rc<RefCell<ObjUpvalue>>>,
+    trace: bool,
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    /// Create a VM that prints to stdout.
+    pub fn new() -> Self {
+        Self::with_output(Box::new(io::stdout()))
+    }
+
+    /// Create a VM that prints to `out`.
+    pub fn with_output(out: Box<dyn Write>) -> Self {
+        let globals = native::library()
+            .into_iter()
+            .map(|native| (Rc::from(native.name()), Value::Callable(Rc::new(native))))
+            .collect();
+        Vm {
+            out,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            globals,
+            open_upvalues: Vec::new(),
+            trace: false,
+        }
+    }
+
+    /// Print the stack and the next instruction to stderr before running each instruction. Off by default.
+    pub fn trace(mut self, trace: bool) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    /// Main entry point for running bytecode. Runs a compiled script, stopping at the first runtime error.
+    pub fn interpret(&mut self, script: Rc<ObjFunction>) -> Result<(), RuntimeError> {
+        let closure = Rc::new(ObjClosure {
+            function: script,
+            upvalues: Vec::new(),
+        });
+        self.stack
+            .push(Value::Object(Object::Closure(Rc::clone(&closure))));
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: 0,
+        });
+
+        let result = self.run();
+        if result.is_err() {
+            // [Note] Globals survive an error, as they do in the tree-walker, the calls in progress do not
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        result
+    }
+
+    fn run(&mut self) -> Result<(), RuntimeError> {
+        loop {
+            let start = self.frame().ip;
+            if self.trace {
+                self.trace_instruction(start);
+            }
+            let byte = self.read_byte();
+            let op = OpCode::from_byte(byte)
+                .unwrap_or_else(|| panic!("{} is not an opcode, at offset {}", byte, start));
+
+            match op {
+                OpCode::Constant => {
+                    let value = self.read_constant();
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.frame().base + usize::from(self.read_byte());
+                    self.stack.push(self.stack[slot].clone());
+                }
+                OpCode::SetLocal => {
+                    let slot = self.frame().base + usize::from(self.read_byte());
+                    // [Note] Assignment is an expression, so its value stays on the stack
+                    self.stack[slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_name();
+                    match self.globals.get(&name) {
+                        Some(value) => self.stack.push(value.clone()),
+                        None => return Err(self.undefined_variable(start, &name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_name();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => return Err(self.undefined_variable(start, &name)),
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = usize::from(self.read_byte());
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = match &*upvalue.borrow() {
+                        ObjUpvalue::Open(slot) => self.stack[*slot].clone(),
+                        ObjUpvalue::Closed(value) => value.clone(),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = usize::from(self.read_byte());
+                    let upvalue = Rc::clone(&self.frame().closure.upvalues[index]);
+                    let value = self.peek(0).clone();
+                    let mut upvalue = upvalue.borrow_mut();
+                    match &mut *upvalue {
+                        ObjUpvalue::Open(slot) => self.stack[*slot] = value,
+                        ObjUpvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_name();
+                    let object = self.pop();
+                    let value = self.get_property(start, object, &name)?;
+                    self.stack.push(value);
+                }
+                OpCode::CheckInstance => {
+                    let name = self.read_name();
+                    let object = self.peek(0);
+                    if !matches!(object, Value::Object(Object::Instance(_))) {
+                        return Err(self.error(
+                            start,
+                            format!(
+                                "Only instances have fields, tried to set '{}' on {}.",
+                                name,
+                                object.type_name()
+                            ),
+                        ));
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_name();
+                    let value = self.pop();
+                    let Value::Object(Object::Instance(instance)) = self.pop() else {
+                        unreachable!("the object should have been checked");
+                    };
+                    instance
+                        .borrow_mut()
+                        .fields
+                        .insert(name.to_string(), value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_name();
+                    let Value::Object(Object::Class(superclass)) = self.pop() else {
+                        unreachable!("super should be a class");
+                    };
+                    let receiver = self.pop();
+                    match superclass.find_method(&name) {
+                        Some(method) => self.stack.push(bind(receiver, method)),
+                        None => {
+                            return Err(self.error(
+                                start,
+                                format!(
+                                    "Undefined property '{}' on superclass {}.",
+                                    name, superclass.name
+                                ),
+                            ))
+                        }
+                    }
+                }
+                OpCode::Equal | OpCode::NotEqual => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.stack
+                        .push(Value::Bool((a == b) == (op == OpCode::Equal)));
+                }
+                OpCode::Add => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let value = match (&a, &b) {
+                        (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
+                        (Value::String(a), Value::String(b)) => {
+                            Value::String(Rc::from(format!("{}{}", a, b)))
+                        }
+                        _ => {
+                            return Err(
+                                self.error(start, "Operands must be two numbers or two strings.")
+                            )
+                        }
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Greater
+                | OpCode::GreaterEqual
+                | OpCode::Less
+                | OpCode::LessEqual
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    let (Value::Number(a), Value::Number(b)) = (a, b) else {
+                        return Err(self.error(start, "Operands must be numbers."));
+                    };
+                    let value = self.arithmetic(start, op, a, b)?;
+                    self.stack.push(value);
+                }
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.stack.push(Value::Bool(!value.is_truthy()));
+                }
+                OpCode::Negate | OpCode::BitNot => {
+                    let Value::Number(value) = self.pop() else {
+                        return Err(self.error(start, "Operand must be a number."));
+                    };
+                    let value = if op == OpCode::Negate {
+                        -value
+                    } else {
+                        bitwise::not(value).map_err(|error| {
+                            RuntimeError::new(self.operand_span(start, 0), error.message)
+                        })?
+                    };
+                    self.stack.push(Value::Number(value));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    if let Err(error) = writeln!(self.out, "{}", value) {
+                        return Err(self.error(start, error.to_string()));
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16();
+                    self.frame().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16();
+                    if !self.peek(0).is_truthy() {
+                        self.frame().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16();
+                    self.frame().ip -= offset;
+                }
+                OpCode::Call => {
+                    let count = usize::from(self.read_byte());
+                    self.call_value(start, count)?;
+                }
+                OpCode::Closure => {
+                    let Value::Object(Object::Function(function)) = self.read_constant() else {
+                        unreachable!("a closure should be made from a function");
+                    };
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = usize::from(self.read_byte());
+                        let upvalue = if is_local {
+                            let slot = self.frame().base + index;
+                            self.capture_upvalue(slot)
+                        } else {
+                            Rc::clone(&self.frame().closure.upvalues[index])
+                        };
+                        upvalues.push(upvalue);
+                    }
+                    let closure = ObjClosure { function, upvalues };
+                    self.stack
+                        .push(Value::Object(Object::Closure(Rc::new(closure))));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("a frame should be running");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_name();
+                    let class = ObjClass::new(&name);
+                    self.stack
+                        .push(Value::Object(Object::Class(Rc::new(class))));
+                }
+                OpCode::Inherit => {
+                    let name = self.read_name();
+                    let Value::Object(Object::Class(superclass)) = self.peek(1) else {
+                        return Err(self.error(
+                            start,
+                            format!(
+                                "Superclass must be a class, '{}' is a {}.",
+                                name,
+                                self.peek(1).type_name()
+                            ),
+                        ));
+                    };
+                    let Value::Object(Object::Class(class)) = self.peek(0) else {
+                        unreachable!("a subclass should be a class");
+                    };
+                    let methods = superclass.methods.borrow().clone();
+                    class.methods.borrow_mut().extend(methods);
+                    self.pop();
+                }
+                OpCode::Method => {
+                    let name = self.read_name();
+                    let Value::Object(Object::Closure(method)) = self.pop() else {
+                        unreachable!("a method should be a closure");
+                    };
+                    let Value::Object(Object::Class(class)) = self.peek(0) else {
+                        unreachable!("a method should be added to a class");
+                    };
+                    class.methods.borrow_mut().insert(name.to_string(), method);
+                }
+                OpCode::List => self.stack.push(collection::list(Vec::new())),
+                OpCode::Append => {
+                    let value = self.pop();
+                    let Value::List(list) = self.peek(0) else {
+                        unreachable!("an element should be appended to a list");
+                    };
+                    list.borrow_mut().push(value);
+                }
+                OpCode::Map => self
+                    .stack
+                    .push(Value::Map(Rc::new(RefCell::new(LoxMap::new())))),
+                OpCode::Insert => {
+                    let value = self.pop();
+                    let key = self.pop();
+                    let key = Key::new(&key).map_err(|message| self.error(start, message))?;
+                    let Value::Map(map) = self.peek(0) else {
+                        unreachable!("an entry should be inserted into a map");
+                    };
+                    map.borrow_mut().insert(key, value);
+                }
+                OpCode::GetIndex => {
+                    let index = self.pop();
+                    let object = self.pop();
+                    let value = collection::get(&object, &index)
+                        .map_err(|error| self.index_error(start, error))?;
+                    self.stack.push(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let index = self.pop();
+                    let object = self.pop();
+                    collection::set(&object, &index, value.clone())
+                        .map_err(|error| self.index_error(start, error))?;
+                    self.stack.push(value);
+                }
+                OpCode::Elements => {
+                    let iterable = self.pop();
+                    let list = collection::sequence(&iterable)
+                        .map_err(|message| self.error(start, message))?;
+                    self.stack.push(Value::List(list));
+                }
+                OpCode::ForIn => {
+                    let slot = self.frame().base + usize::from(self.read_byte());
+                    let offset = self.read_u16();
+                    let (Value::List(list), Value::Number(position)) =
+                        (&self.stack[slot], &self.stack[slot + 1])
+                    else {
+                        unreachable!("a for-in loop should keep its list and position");
+                    };
+                    // [Note] The length is checked every time round, as the body may change the list
+                    let element = list.borrow().get(*position as usize).cloned();
+                    match element {
+                        Some(element) => {
+                            self.stack[slot + 1] = Value::Number(position + 1.0);
+                            self.stack.push(element);
+                        }
+                        None => self.frame().ip += offset,
+                    }
+                }
+            }
+        }
+    }
+
+    // Calls
+
+    /// Call the value under the `count` arguments on top of the stack.
+    fn call_value(&mut self, start: usize, count: usize) -> Result<(), RuntimeError> {
+        let callee_slot = self.stack.len() - count - 1;
+        let callee = self.stack[callee_slot].clone();
+        let (arity, declaration) = match &callee {
+            Value::Object(Object::Closure(closure)) => {
+                (closure.function.arity, closure.function.declaration)
+            }
+            Value::Object(Object::BoundMethod(bound)) => (
+                bound.method.function.arity,
+                bound.method.function.declaration,
+            ),
+            Value::Object(Object::Class(class)) => match class.find_method("init") {
+                Some(init) => (init.function.arity, init.function.declaration),
+                None => (0, None),
+            },
+            Value::Callable(callable) => (callable.arity(), callable.declaration()),
+            _ => {
+                return Err(self.error(
+                    start,
+                    format!(
+                        "Can only call functions and classes, not {}.",
+                        callee.type_name()
+                    ),
+                ))
+            }
+        };
+        if count != arity {
+            let span = self.span(start);
+            return Err(RuntimeError::arity(span, arity, count, declaration));
+        }
+        if self.frames.len() - 1 == MAX_CALL_DEPTH {
+            return Err(self.error(start, "Stack overflow."));
+        }
+
+        match callee {
+            Value::Object(Object::Closure(closure)) => self.call_closure(closure, count),
+            Value::Object(Object::BoundMethod(bound)) => {
+                // [Note] The receiver takes the place of the method, as slot 0 of the call: `this`
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call_closure(Rc::clone(&bound.method), count);
+            }
+            Value::Object(Object::Class(class)) => {
+                let instance = ObjInstance {
+                    class: Rc::clone(&class),
+                    fields: HashMap::new(),
+                };
+                self.stack[callee_slot] =
+                    Value::Object(Object::Instance(Rc::new(RefCell::new(instance))));
+                // Without an initializer the new instance is already the result
+                if let Some(init) = class.find_method("init") {
+                    self.call_closure(init, count);
+                }
+            }
+            Value::Callable(callable) => {
+                let Some(native) = callable.as_native() else {
+                    unreachable!("the VM only has native callables");
+                };
+                let arguments = self.stack.split_off(callee_slot + 1);
+                let value = native.apply(&arguments).map_err(|error| match error {
+                    NativeError::Argument(index, message) => {
+                        RuntimeError::new(self.operand_span(start, index), message)
+                    }
+                    NativeError::Call(message) => self.error(start, message),
+                    NativeError::Exit(code) => RuntimeError::exit(self.span(start), code),
+                })?;
+                self.stack[callee_slot] = value;
+            }
+            _ => unreachable!("the callee should have been checked"),
+        }
+        Ok(())
+    }
+
+    fn call_closure(&mut self, closure: Rc<ObjClosure>, count: usize) {
+        let base = self.stack.len() - count - 1;
+        self.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base,
+        });
+    }
+
+    /// Read a property: a field, or else a method bound to the instance.
+    fn get_property(&self, start: usize, object: Value, name: &str) -> Result<Value, RuntimeError> {
+        match &object {
+            Value::Object(Object::Instance(instance)) => {
+                // Fields shadow methods
+                if let Some(value) = instance.borrow().fields.get(name) {
+                    return Ok(value.clone());
+                }
+                let class = Rc::clone(&instance.borrow().class);
+                match class.find_method(name) {
+                    Some(method) => Ok(bind(object, method)),
+                    None => Err(self.error(
+                        start,
+                        format!("Undefined property '{}' on {} instance.", name, class.name),
+                    )),
+                }
+            }
+            Value::List(_) | Value::Map(_) => match collection::method(&object, name) {
+                Some(method) => Ok(Value::Callable(Rc::new(method))),
+                None => Err(self.error(
+                    start,
+                    format!("Undefined property '{}' on {}.", name, object.type_name()),
+                )),
+            },
+            _ => Err(self.error(
+                start,
+                format!(
+                    "Only instances have properties, tried to read '{}' on {}.",
+                    name,
+                    object.type_name()
+                ),
+            )),
+        }
+    }
+
+    /// Apply a comparison, arithmetic or bitwise operator to two numbers.
+    fn arithmetic(&self, start: usize, op: OpCode, a: f64, b: f64) -> Result<Value, RuntimeError> {
+        let operator = match op {
+            OpCode::Greater => return Ok(Value::Bool(a > b)),
+            OpCode::GreaterEqual => return Ok(Value::Bool(a >= b)),
+            OpCode::Less => return Ok(Value::Bool(a < b)),
+            OpCode::LessEqual => return Ok(Value::Bool(a <= b)),
+            OpCode::Subtract => return Ok(Value::Number(a - b)),
+            OpCode::Multiply => return Ok(Value::Number(a * b)),
+            OpCode::Divide => return Ok(Value::Number(a / b)),
+            OpCode::BitAnd => BinaryOp::BitAnd,
+            OpCode::BitOr => BinaryOp::BitOr,
+            OpCode::BitXor => BinaryOp::BitXor,
+            OpCode::ShiftLeft => BinaryOp::ShiftLeft,
+            OpCode::ShiftRight => BinaryOp::ShiftRight,
+            _ => unreachable!("{:?} is not a binary operator on numbers", op),
+        };
+        bitwise::binary(operator, a, b)
+            .map(Value::Number)
+            .map_err(|error| {
+                // The compiler records the left operand, the right operand and the whole expression
+                let span = match error.operand {
+                    Some(Operand::Left) => self.operand_span(start, 0),
+                    Some(Operand::Right) => self.operand_span(start, 1),
+                    None => self.operand_span(start, 2),
+                };
+                RuntimeError::new(span, error.message)
+            })
+    }
+
+    // Upvalues
+
+    /// The upvalue for the stack slot `slot`, shared with any closure that already captured it.
+    fn capture_upvalue(&mut self, slot: usize) -> Rc<RefCell<ObjUpvalue>> {
+        let existing = self
+            .open_upvalues
+            .iter()
+            .find(|upvalue| matches!(*upvalue.borrow(), ObjUpvalue::Open(open) if open == slot));
+        if let Some(upvalue) = existing {
+            return Rc::clone(upvalue);
+        }
+        let upvalue = Rc::new(RefCell::new(ObjUpvalue::Open(slot)));
+        self.open_upvalues.push(Rc::clone(&upvalue));
+        upvalue
+    }
+
+    /// Move the values of every slot from `first` up off the stack, into the upvalues capturing them.
+    fn close_upvalues(&mut self, first: usize) {
+        let stack = &self.stack;
+        self.open_upvalues.retain(|upvalue| {
+            let mut upvalue = upvalue.borrow_mut();
+            match *upvalue {
+                ObjUpvalue::Open(slot) if slot >= first => {
+                    *upvalue = ObjUpvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    /// Print the stack, bottom first, then the instruction at `start` about to run.
+    fn trace_instruction(&self, start: usize) {
+        let mut line = String::from("          ");
+        for value in &self.stack {
+            line.push_str(&format!("[ {} ]", value));
+        }
+        let frame = self.frames.last().expect("a frame should be running");
+        line.push('\n');
+        disassembler::disassemble_instruction(&frame.closure.function.chunk, start, &mut line);
+        eprint!("{}", line);
+    }
+
+    // Reading the chunk
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("a frame should be running")
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame();
+        let byte = frame.closure.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> usize {
+        let frame = self.frame();
+        let value = frame.closure.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        usize::from(value)
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_u16();
+        self.frame().closure.function.chunk.constants[index].clone()
+    }
+
+    fn read_name(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Value::String(name) => name,
+            _ => unreachable!("a name should be a string constant"),
+        }
+    }
+
+    // The stack
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack should not be empty")
+    }
+
+    /// The value `distance` down from the top of the stack.
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    // Errors
+
+    /// Where the instruction at `start` in the running function came from.
+    fn span(&self, start: usize) -> Span {
+        let frame = self.frames.last().expect("a frame should be running");
+        frame.closure.function.chunk.span(start)
+    }
+
+    fn operand_span(&self, start: usize, index: usize) -> Span {
+        let frame = self.frames.last().expect("a frame should be running");
+        frame.closure.function.chunk.operand_spans(start)[index]
+    }
+
+    fn error(&self, start: usize, message: impl Into<String>) -> RuntimeError {
+        RuntimeError::new(self.span(start), message)
+    }
+
+    fn undefined_variable(&self, start: usize, name: &str) -> RuntimeError {
+        let names = self.globals.keys().map(|name| &**name);
+        RuntimeError::undefined_variable(self.span(start), name, names)
+    }
+
+    /// Point an indexing error at the object or the index, whichever is to blame.
+    fn index_error(&self, start: usize, error: collection::IndexError) -> RuntimeError {
+        let index = match error.part {
+            Part::Object => 0,
+            Part::Index => 1,
+        };
+        RuntimeError::new(self.operand_span(start, index), error.message)
+    }
+}
+
+/// A method bound to the instance it was read from.
+fn bind(receiver: Value, method: Rc<ObjClosure>) -> Value {
+    Value::Object(Object::BoundMethod(Rc::new(ObjBoundMethod {
+        receiver,
+        method,
+    })))
+}
+
+/// Tests for the virtual machine
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::compiler::Compiler;
+    use crate::modules::interpreter::tests::{output_on, run_on, Backend, SharedBuffer};
+    use crate::modules::parser::Parser;
+    use crate::modules::tokenize::Tokenizer;
+
+    fn output(source: &str) -> Vec<String> {
+        output_on(Backend::Vm, source)
+    }
+
+    /// Check the VM does exactly what the tree-walker does: prints the same, and fails the same.
+    fn same_as_tree(source: &str) {
+        assert_eq!(
+            run_on(Backend::Vm, source),
+            run_on(Backend::Tree, source),
+            "for {:?}",
+            source
+        );
+    }
+
+    #[test]
+    fn expressions_and_variables() {
+        assert_eq!(
+            output("var a = 1; var b = a + 2 * 3; a = b - -1; print a; print \"x\" + \"y\";"),
+            vec!["8", "xy"]
+        );
+        assert_eq!(
+            output("print !nil; print 1 == 1.0; print 2 <= 1; print nil or \"or\"; print 0 and 1;"),
+            vec!["true", "true", "false", "or", "1"]
+        );
+        assert_eq!(
+            output("print 6 & 3 | 8; print ~0; print 1 << 4;"),
+            vec!["10", "-1", "16"]
+        );
+    }
+
+    #[test]
+    fn closures_share_captured_variables() {
+        let source = "
+            var get; var set;
+            fun make() {
+                var value = 1;
+                fun g() { return value; }
+                fun s(v) { value = v; }
+                get = g; set = s;
+            }
+            make();
+            set(5);
+            print get();";
+        assert_eq!(output(source), vec!["5"]);
+
+        // Every iteration of a loop body has its own variables
+        let source = "
+            var fs = [];
+            for (var i = 0; i < 3; i = i + 1) {
+                var j = i;
+                fun f() { return j; }
+                fs.push(f);
+            }
+            for (var f in fs) print f();";
+        assert_eq!(output(source), vec!["0", "1", "2"]);
+    }
+
+    #[test]
+    fn jumps_out_of_loops_close_upvalues() {
+        let source = "
+            var f;
+            while (true) {
+                var local = \"kept\";
+                fun g() { return local; }
+                f = g;
+                break;
+            }
+            var other = \"overwrites the slot\";
+            print f();
+            var n = 0;
+            for (var x in [1, 2, 3, 4]) {
+                if (x == 2) continue;
+                if (x == 4) break;
+                n = n + x;
+            }
+            print n;";
+        assert_eq!(output(source), vec!["kept", "4"]);
+    }
+
+    #[test]
+    fn classes_and_inheritance() {
+        let source = "
+            class A {
+                init(name) { this.name = name; }
+                greet() { return \"A \" + this.name; }
+            }
+            class B < A {
+                greet() { return super.greet() + \" and B\"; }
+            }
+            var b = B(\"b\");
+            var greet = b.greet;
+            print greet();
+            print b.init(\"c\").name;
+            print B;
+            print b;";
+        assert_eq!(output(source), vec!["A b and B", "c", "B", "B instance"]);
+    }
+
+    #[test]
+    fn collections() {
+        let source = "
+            var list = [1, 2];
+            list.push(3);
+            list[0] = list.len();
+            var map = {\"a\": list};
+            map[\"b\"] = 2;
+            print map;
+            for (var key in map) print key;";
+        assert_eq!(
+            output(source),
+            vec!["{\"a\": [3, 2, 3], \"b\": 2}", "a", "b"]
+        );
+    }
+
+    #[test]
+    fn runtime_errors_match_the_tree_walker() {
+        for source in [
+            "print -\"a\";",
+            "print 1 < \"a\";",
+            "print \"a\" + 1;",
+            "print 1.5 | 1;",
+            "print 1 | 0.5;",
+            "print 1 << 60;",
+            "print ~0.5;",
+            "print undefined;",
+            "undefined = 1;",
+            "\"a\"();",
+            "fun f(a) {} f();",
+            "class A {} A(1);",
+            "class A {} print A().missing;",
+            "print true.field;",
+            "true.field = 2;",
+            "var NotAClass = 1; class B < NotAClass {}",
+            "class A {} class B < A { m() { super.missing(); } } B().m();",
+            "print [1][2];",
+            "print [1][\"a\"];",
+            "print 1[0];",
+            "print {\"a\": 1}[\"b\"];",
+            "print {nil: 1};",
+            "print [].pop();",
+            "print [].missing;",
+            "for (var x in 1) {}",
+            "print len(1);",
+            "print substr(\"abc\", 0, 9);",
+            "exit(3);",
+        ] {
+            same_as_tree(source);
+        }
+    }
+
+    #[test]
+    fn stack_overflow() {
+        // [Note] Not compared with the tree-walker, which needs a bigger stack than a test thread has
+        assert_eq!(
+            run_on(Backend::Vm, "fun f(n) {\n  f(n + 1);\n}\nf(0);").1,
+            Some(String::from("[line 2, column 3] Error: Stack overflow."))
+        );
+    }
+
+    #[test]
+    fn globals_survive_a_runtime_error() {
+        let out = SharedBuffer::default();
+        let mut vm = Vm::with_output(Box::new(out.clone()));
+        let compile = |source: &str| {
+            let statements = Parser::new(Tokenizer::new(source)).parse().unwrap();
+            Compiler::new().compile(&statements).unwrap()
+        };
+        assert!(vm
+            .interpret(compile("var a = 1; fun f() { nil(); } f();"))
+            .is_err());
+        vm.interpret(compile("print a;")).unwrap();
+        assert_eq!(out.contents(), "1\n");
+    }
+}
@@ -0,0 +1,220 @@
+// Command-line tests: run the rlox binary with different arguments and check what it prints and
+// the exit status it stops with. What scripts do is covered by the conformance tests.
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run the rlox binary with `args`, feeding it `stdin`.
+fn rlox(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rlox"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("rlox binary should start");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(stdin.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn inline_and_stdin_scripts() {
+    let output = rlox(&["-e", "print 1 + 2;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "3\n");
+
+    let output = rlox(&["run", "-"], "var a = \"from stdin\";\nprint a;\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "from stdin\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn exit_statuses() {
+    // Usage error
+    let output = rlox(&["--no-such-option"], "");
+    assert_eq!(output.status.code(), Some(64));
+    assert!(stderr(&output).starts_with("Error: Unknown option '--no-such-option'\n"));
+    assert!(stderr(&output).contains("Usage: rlox"));
+
+    // Compile error
+    let output = rlox(&["--error-format=short", "-e", "print ;"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        stderr(&output),
+        "[line 1, column 7] Error: Expected expression, found ';'.\n"
+    );
+
+    // Runtime error
+    let output = rlox(&["-e", "print -\"a\";"], "");
+    assert_eq!(output.status.code(), Some(70));
+
+    // I/O error
+    let output = rlox(&["run", "no/such/script.lox"], "");
+    assert_eq!(output.status.code(), Some(74));
+    assert_eq!(
+        stderr(&output),
+        "error[E001]: File 'no/such/script.lox' not found.\n"
+    );
+}
+
+#[test]
+fn backends() {
+    let script = "fun add(a, b) { return a + b; }\nprint add(1, 2);\nprint add(1, nil);";
+    for backend in ["--backend=tree", "--backend=vm"] {
+        let output = rlox(&[backend, "--error-format=short", "-e", script], "");
+        assert_eq!(output.status.code(), Some(70));
+        assert_eq!(stdout(&output), "3\n");
+        assert_eq!(
+            stderr(&output),
+            "[line 1, column 26] Error: Operands must be two numbers or two strings.\n"
+        );
+    }
+
+    let output = rlox(&["--backend=jit", "-e", "print 1;"], "");
+    assert_eq!(output.status.code(), Some(64));
+}
+
+#[test]
+fn disasm_and_trace() {
+    let output = rlox(&["disasm", "-e", "print 1;\nprint 2;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '1'
+0003    | OP_PRINT
+0004    2 OP_CONSTANT         1 '2'
+0007    | OP_PRINT
+0008    | OP_NIL
+0009    | OP_RETURN
+"
+    );
+
+    // The trace goes to stderr, so the program's own output is unchanged
+    let output = rlox(&["--backend=vm", "--trace", "-e", "print -1;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "-1\n");
+    assert!(stderr(&output).contains("          [ <script> ][ 1 ]\n0003    | OP_NEGATE\n"));
+
+    // Errors stop it before it prints anything
+    let output = rlox(&["--error-format=short", "disasm", "-e", "print ;"], "");
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(stdout(&output), "");
+}
+
+#[test]
+fn tokens() {
+    let output = rlox(&["tokens", "-e", "var s = \"hi\";\nprint 1.5;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "1:1 Var var\n\
+         1:5 Identifier s\n\
+         1:7 Equal =\n\
+         1:9 String \"hi\" \"hi\"\n\
+         1:13 Semicolon ;\n\
+         2:1 Print print\n\
+         2:7 Number 1.5 1.5\n\
+         2:10 Semicolon ;\n\
+         2:11 EOF \n"
+    );
+
+    let output = rlox(&["tokens", "-e", "print @;"], "");
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn ast() {
+    let output = rlox(&["ast", "-e", "print 1 + 2 * 3;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "(print (+ 1 (* 2 3)))\n");
+}
+
+#[test]
+fn check_does_not_run_the_script() {
+    let script = "fun f() { var unused; }\nprint \"ran\";";
+    let output = rlox(&["check", "--error-format=short", "-e", script], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "");
+    assert_eq!(
+        stderr(&output),
+        "[line 1, column 15] Warning: Local variable 'unused' is never used.\n"
+    );
+
+    let output = rlox(&["check", "-e", "return 1;"], "");
+    assert_eq!(output.status.code(), Some(65));
+}
+
+#[test]
+fn quiet_hides_warnings() {
+    let output = rlox(&["--quiet", "-e", "fun f() { var unused; } print 1;"], "");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(stdout(&output), "1\n");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn debug_output_only_with_the_flag() {
+    let output = rlox(&["-e", "print 1;"], "");
+    assert_eq!(stderr(&output), "");
+
+    let output = rlox(&["--debug", "-e", "print 1;"], "");
+    assert_eq!(stdout(&output), "1\n");
+    assert!(stderr(&output).contains("contents"));
+}
+
+#[test]
+fn error_formats() {
+    let script = "var a = 1;\nprint a +;\n";
+
+    // Human errors are the default, and are not colored when stderr is not a terminal
+    let output = rlox(&["-"], script);
+    assert_eq!(output.status.code(), Some(65));
+    assert_eq!(
+        stderr(&output),
+        "error[E201]: Expected expression, found ';'.\n \
+         --> <stdin>:2:10\n  \
+         |\n\
+         2 | print a +;\n  \
+         |          ^\n"
+    );
+
+    let output = rlox(&["--color=always", "-"], script);
+    assert!(stderr(&output).starts_with("\x1b[1;31merror[E201]\x1b[0m"));
+
+    let output = rlox(&["--error-format=json", "-"], script);
+    assert_eq!(
+        stderr(&output),
+        "{\"severity\":\"error\",\"code\":\"E201\",\"message\":\"Expected expression, found ';'.\",\
+         \"file\":\"<stdin>\",\"span\":{\"line\":2,\"column\":10,\"start\":20,\"end\":21},\
+         \"labels\":[],\"notes\":[]}\n"
+    );
+}
+
+#[test]
+fn natives_read_stdin_and_set_the_exit_status() {
+    let output = rlox(
+        &[
+            "-e",
+            "var name = input(); print \"hi \" + name; print input(); exit(4);",
+        ],
+        "Ada\n",
+    );
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stdout(&output), "hi Ada\nnil\n");
+    assert_eq!(stderr(&output), "");
+}
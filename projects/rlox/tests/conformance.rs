@@ -0,0 +1,117 @@
+// Conformance tests: run every script under test/conformance through the rlox binary and compare
+// what it prints against the .expected file next to it. See test/conformance/README.md for the format.
+// Both backends run every script, and must behave exactly the same.
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Every value of --backend.
+const BACKENDS: [&str; 2] = ["tree", "vm"];
+
+/// Struct representing what a script is expected to do when it runs.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    status: i32,
+}
+
+impl Outcome {
+    /// Read an .expected file: stdout, then optionally `[exit N]` followed by stderr.
+    fn parse(expected: &str) -> Self {
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        let mut status = None;
+
+        for line in expected.lines() {
+            if let Some(code) = line
+                .strip_prefix("[exit ")
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                status = Some(code.parse().expect("exit status should be a number"));
+            } else if status.is_none() {
+                stdout.push_str(line);
+                stdout.push('\n');
+            } else {
+                stderr.push_str(line);
+                stderr.push('\n');
+            }
+        }
+        Outcome {
+            stdout,
+            stderr,
+            status: status.unwrap_or(0),
+        }
+    }
+
+    /// Run a script through the rlox binary, on one backend.
+    fn run(script: &Path, backend: &str) -> Self {
+        // [Note] Short errors, one line each, keep the .expected files readable
+        let output = Command::new(env!("CARGO_BIN_EXE_rlox"))
+            .arg("--error-format=short")
+            .arg(format!("--backend={}", backend))
+            .arg(script)
+            .output()
+            .expect("rlox binary should start");
+
+        Outcome {
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            status: output.status.code().unwrap_or(-1),
+        }
+    }
+}
+
+/// Every .lox file under `dir`, in a stable order.
+fn scripts(dir: &Path) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir).expect("conformance directory should exist") {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            found.extend(scripts(&path));
+        } else if path.extension().is_some_and(|extension| extension == "lox") {
+            found.push(path);
+        }
+    }
+    found.sort();
+    found
+}
+
+#[test]
+fn conformance() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/conformance");
+    let scripts = scripts(&root);
+    assert!(
+        !scripts.is_empty(),
+        "no scripts found in {}",
+        root.display()
+    );
+
+    // [Note] Every script runs before failing, so one run shows everything that is broken
+    let mut failures = Vec::new();
+    for script in &scripts {
+        let expected = fs::read_to_string(script.with_extension("expected"))
+            .unwrap_or_else(|_| panic!("{} has no .expected file", script.display()));
+        let expected = Outcome::parse(&expected);
+        for backend in BACKENDS {
+            let actual = Outcome::run(script, backend);
+            if actual != expected {
+                failures.push(format!(
+                    "{} (--backend={})\n  expected: {:?}\n  actual:   {:?}",
+                    script.strip_prefix(&root).unwrap().display(),
+                    backend,
+                    expected,
+                    actual
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} of {} conformance runs failed:\n{}",
+        failures.len(),
+        scripts.len() * BACKENDS.len(),
+        failures.join("\n")
+    );
+}